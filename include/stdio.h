@@ -0,0 +1,12 @@
+#ifndef __STDIO_H
+#define __STDIO_H
+
+#include <stddef.h>
+
+#define EOF (0-1)
+
+int printf(char *, ...);
+int sprintf(char *, char *, ...);
+int puts(char *);
+
+#endif
@@ -0,0 +1,736 @@
+use std::collections::{HashMap, HashSet};
+
+// Encodes the Intel-syntax assembly that codegen emits into machine code,
+// so objects can be written (and run) without an external assembler.
+
+pub const TEXT: usize = 0;
+pub const DATA: usize = 1;
+pub const RODATA: usize = 2;
+pub const BSS: usize = 3;
+
+pub static SECTION_NAMES: [&str; 4] = [".text", ".data", ".rodata", ".bss"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RelocKind {
+    Pc32,   // R_X86_64_PC32
+    Plt32,  // R_X86_64_PLT32
+    Abs64,  // R_X86_64_64
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RelocTarget {
+    Section(usize),  // addend is an offset into the section
+    Symbol(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reloc {
+    pub section: usize,
+    pub offset: usize,
+    pub target: RelocTarget,
+    pub kind: RelocKind,
+    pub addend: i64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: usize,
+    pub offset: usize,
+    pub size: usize,
+    pub global: bool,
+    pub func: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Object {
+    pub sections: Vec<Vec<u8>>,  // indexed by TEXT, DATA, RODATA and BSS
+    pub symbols: Vec<Symbol>,    // `.L` labels are resolved away and not listed
+    pub relocs: Vec<Reloc>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Base {
+    Reg(u8),
+    Rip,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Mem {
+    base: Base,
+    disp: i64,
+    sym: Option<String>,
+    size: Option<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Reg(u8, u8),  // number, size in bytes
+    Xmm(u8),
+    Mem(Mem),
+    Imm(i64),
+    Sym(String),
+}
+
+// A reference to a label whose address is not known yet. The value stored
+// is `label + addend - offset`.
+struct Fixup {
+    section: usize,
+    offset: usize,
+    target: String,
+    kind: RelocKind,
+    addend: i64,
+}
+
+static REGS64: [&str; 16] = ["rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi",
+    "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15"];
+static REGS32: [&str; 16] = ["eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi",
+    "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d"];
+static REGS16: [&str; 16] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di",
+    "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w"];
+static REGS8: [&str; 16] = ["al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil",
+    "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b"];
+
+// Condition codes in encoding order, as used by jcc and setcc.
+static CONDS: [&str; 16] = ["o", "no", "b", "ae", "e", "ne", "be", "a",
+    "s", "ns", "p", "np", "l", "ge", "le", "g"];
+
+fn parse_reg(s: &str) -> Option<(u8, u8)> {
+    for &(names, size) in &[(&REGS64, 8), (&REGS32, 4), (&REGS16, 2), (&REGS8, 1)] {
+        if let Some(n) = names.iter().position(|&r| r == s) {
+            return Some((n as u8, size));
+        }
+    }
+    return None
+}
+
+fn parse_xmm(s: &str) -> Option<u8> {
+    if !s.starts_with("xmm") { return None }
+    return s[3..].parse::<u8>().ok().filter(|&n| n < 16)
+}
+
+// The predicates of cmpsd, in encoding order, as in `cmpltsd`.
+static SSE_PREDS: [&str; 8] = ["eq", "lt", "le", "unord", "neq", "nlt", "nle", "ord"];
+
+fn parse_cond(s: &str) -> Option<u8> {
+    let s = match s { "z" => "e", "nz" => "ne", "c" => "b", "nc" => "ae", _ => s };
+    return CONDS.iter().position(|&c| c == s).map(|n| n as u8)
+}
+
+fn parse_int(s: &str) -> Option<i64> {
+    let (neg, digits) = if s.starts_with('-') { (true, &s[1..]) } else { (false, s) };
+    let n = if digits.starts_with("0x") || digits.starts_with("0X") {
+        u64::from_str_radix(&digits[2..], 16).ok()?
+    } else {
+        digits.parse::<u64>().ok()?
+    };
+    return Some(if neg { (n as i64).wrapping_neg() } else { n as i64 })
+}
+
+fn fits_i8(n: i64) -> bool { return n >= -128 && n <= 127 }
+
+fn fits_i32(n: i64) -> bool { return n >= i32::min_value() as i64 && n <= i32::max_value() as i64 }
+
+fn parse_mem(s: &str, size: Option<u8>) -> Result<Mem, String> {
+    let mut mem = Mem{base: Base::Rip, disp: 0, sym: None, size};
+    let mut has_base = false;
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut sign = 1;
+    let mut term = String::new();
+    for c in s.chars().chain(Some('+')) {
+        if c != '+' && c != '-' {
+            term.push(c);
+            continue;
+        }
+        if term.is_empty() {
+            return Err(format!("bad memory operand [{}]", s));
+        }
+        if term == "rip" {
+            has_base = true;
+        } else if let Some((n, 8)) = parse_reg(&term) {
+            mem.base = Base::Reg(n);
+            has_base = true;
+        } else if let Some(n) = parse_int(&term) {
+            mem.disp += sign * n;
+        } else if sign == 1 && mem.sym.is_none() {
+            mem.sym = Some(term.clone());
+        } else {
+            return Err(format!("bad memory operand [{}]", s));
+        }
+        sign = if c == '-' { -1 } else { 1 };
+        term.clear();
+    }
+    if !has_base || mem.sym.is_some() && mem.base != Base::Rip {
+        return Err(format!("unsupported memory operand [{}]", s));
+    }
+    return Ok(mem)
+}
+
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    let mut s = s.trim();
+    let mut size = None;
+    for &(prefix, n) in &[("BYTE PTR", 1), ("WORD PTR", 2), ("DWORD PTR", 4), ("QWORD PTR", 8)] {
+        if s.starts_with(prefix) {
+            size = Some(n);
+            s = s[prefix.len()..].trim();
+        }
+    }
+    if s.starts_with('[') && s.ends_with(']') {
+        return Ok(Operand::Mem(parse_mem(&s[1..s.len() - 1], size)?));
+    }
+    if let Some((n, size)) = parse_reg(s) {
+        return Ok(Operand::Reg(n, size));
+    }
+    if let Some(n) = parse_xmm(s) {
+        return Ok(Operand::Xmm(n));
+    }
+    if let Some(n) = parse_int(s) {
+        return Ok(Operand::Imm(n));
+    }
+    if s.is_empty() {
+        return Err("missing operand".to_string());
+    }
+    return Ok(Operand::Sym(s.to_string()))
+}
+
+// Parses the body of a `.ascii`/`.asciz` string, handling the escapes GNU as does.
+fn parse_string(s: &str) -> Result<Vec<u8>, String> {
+    let s = s.trim();
+    if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') {
+        return Err(format!("bad string {}", s));
+    }
+    let bytes = s[1..s.len() - 1].as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        i += 1;
+        if c != b'\\' {
+            out.push(c);
+            continue;
+        }
+        let e = *bytes.get(i).ok_or("bad escape at end of string")?;
+        i += 1;
+        match e {
+            b'n' => out.push(b'\n'),
+            b't' => out.push(b'\t'),
+            b'r' => out.push(b'\r'),
+            b'f' => out.push(0x0c),
+            b'b' => out.push(0x08),
+            b'x' => {
+                let mut n: u32 = 0;
+                while i < bytes.len() && (bytes[i] as char).is_digit(16) {
+                    n = n * 16 + (bytes[i] as char).to_digit(16).unwrap();
+                    i += 1;
+                }
+                out.push(n as u8);
+            },
+            b'0'..=b'7' => {
+                let mut n = (e - b'0') as u32;
+                for _ in 0..2 {
+                    if i < bytes.len() && bytes[i] >= b'0' && bytes[i] <= b'7' {
+                        n = n * 8 + (bytes[i] - b'0') as u32;
+                        i += 1;
+                    }
+                }
+                out.push(n as u8);
+            },
+            _ => out.push(e),
+        }
+    }
+    return Ok(out)
+}
+
+// Splits operands on commas that are outside brackets and quotes.
+fn split_operands(s: &str) -> Vec<&str> {
+    let mut ops = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '[' if !quoted => depth += 1,
+            ']' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                ops.push(s[start..i].trim());
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    if !s[start..].trim().is_empty() {
+        ops.push(s[start..].trim());
+    }
+    return ops
+}
+
+// 8-bit spl, bpl, sil and dil are only reachable with a REX prefix.
+fn needs_rex8(ops: &[&Operand]) -> bool {
+    return ops.iter().any(|op| match **op {
+        Operand::Reg(n, 1) => n >= 4 && n < 8,
+        _ => false,
+    })
+}
+
+fn size_of(op: &Operand) -> Option<u8> {
+    match *op {
+        Operand::Reg(_, size) => return Some(size),
+        Operand::Mem(ref m) => return m.size,
+        _ => return None,
+    }
+}
+
+struct Assembler {
+    obj: Object,
+    cur: usize,
+    labels: HashMap<String, (usize, usize)>,
+    globals: HashSet<String>,
+    funcs: HashSet<String>,
+    sizes: HashMap<String, usize>,
+    fixups: Vec<Fixup>,
+}
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.obj.sections[self.cur].extend_from_slice(bytes);
+    }
+
+    fn pos(&self) -> usize {
+        return self.obj.sections[self.cur].len()
+    }
+
+    // A 32-bit field that will hold `target + addend - field address`.
+    fn emit_rel32(&mut self, target: &str, kind: RelocKind, addend: i64) {
+        let offset = self.pos();
+        self.fixups.push(Fixup{section: self.cur, offset, target: target.to_string(), kind, addend});
+        self.emit(&[0; 4]);
+    }
+
+    // Emits the optional 0x66 and REX prefixes, the opcode and a ModRM
+    // operand. `imm_len` is the size of an immediate that follows, which
+    // rip-relative displacements are measured past.
+    fn modrm(&mut self, size: u8, rex8: bool, opcode: &[u8], reg: u8, rm: &Operand, imm_len: i64) -> Result<(), String> {
+        let mut rex = 0x40;
+        if size == 8 { rex |= 8; }
+        if reg & 8 != 0 { rex |= 4; }
+        match *rm {
+            Operand::Reg(n, _) | Operand::Xmm(n) | Operand::Mem(Mem{base: Base::Reg(n), ..}) => if n & 8 != 0 { rex |= 1; },
+            _ => {},
+        }
+        if size == 2 { self.emit(&[0x66]); }
+        if rex != 0x40 || rex8 { self.emit(&[rex]); }
+        self.emit(opcode);
+        let reg = (reg & 7) << 3;
+        match *rm {
+            Operand::Reg(n, _) | Operand::Xmm(n) => self.emit(&[0xc0 | reg | (n & 7)]),
+            Operand::Mem(ref m) => match m.base {
+                Base::Rip => {
+                    self.emit(&[0x05 | reg]);
+                    match m.sym {
+                        Some(ref sym) => self.emit_rel32(sym, RelocKind::Pc32, m.disp - 4 - imm_len),
+                        None => self.emit(&(m.disp as i32).to_le_bytes()),
+                    }
+                },
+                Base::Reg(n) => {
+                    // rbp and r13 have no form without a displacement.
+                    let md = if m.disp == 0 && n & 7 != 5 { 0 } else if fits_i8(m.disp) { 1 } else { 2 };
+                    // rsp and r12 need a SIB byte.
+                    if n & 7 == 4 {
+                        self.emit(&[md << 6 | reg | 4, 0x24]);
+                    } else {
+                        self.emit(&[md << 6 | reg | (n & 7)]);
+                    }
+                    if md == 1 { self.emit(&[m.disp as i8 as u8]); }
+                    if md == 2 { self.emit(&(m.disp as i32).to_le_bytes()); }
+                },
+            },
+            _ => return Err(format!("register or memory operand expected, but got {:?}", rm)),
+        }
+        return Ok(())
+    }
+
+    // add, or, and, sub, xor and cmp share one encoding scheme, selected by `digit`.
+    fn alu(&mut self, digit: u8, dst: &Operand, src: &Operand) -> Result<(), String> {
+        let size = size_of(dst).or_else(|| size_of(src)).ok_or("operand size unknown")?;
+        let byte = if size == 1 { 0 } else { 1 };
+        let rex8 = needs_rex8(&[dst, src]);
+        match (dst, src) {
+            (_, &Operand::Imm(n)) => {
+                if size == 1 {
+                    self.modrm(size, rex8, &[0x80], digit, dst, 1)?;
+                    self.emit(&[n as u8]);
+                } else if fits_i8(n) {
+                    self.modrm(size, rex8, &[0x83], digit, dst, 1)?;
+                    self.emit(&[n as u8]);
+                } else if fits_i32(n) {
+                    self.modrm(size, rex8, &[0x81], digit, dst, 4)?;
+                    self.emit(&(n as i32).to_le_bytes());
+                } else {
+                    return Err(format!("immediate {} out of range", n));
+                }
+                return Ok(())
+            },
+            (_, &Operand::Reg(r, _)) => return self.modrm(size, rex8, &[digit * 8 + byte], r, dst, 0),
+            (&Operand::Reg(r, _), &Operand::Mem(_)) => return self.modrm(size, rex8, &[digit * 8 + 2 + byte], r, src, 0),
+            _ => return Err("bad operands".to_string()),
+        }
+    }
+
+    fn mov(&mut self, dst: &Operand, src: &Operand) -> Result<(), String> {
+        let size = size_of(dst).or_else(|| size_of(src)).ok_or("operand size unknown")?;
+        let byte = if size == 1 { 0 } else { 1 };
+        let rex8 = needs_rex8(&[dst, src]);
+        match (dst, src) {
+            (_, &Operand::Reg(r, _)) => return self.modrm(size, rex8, &[0x88 + byte], r, dst, 0),
+            (&Operand::Reg(r, _), &Operand::Mem(_)) => return self.modrm(size, rex8, &[0x8a + byte], r, src, 0),
+            (&Operand::Reg(r, 8), &Operand::Imm(n)) if !fits_i32(n) => {
+                self.emit(&[0x48 | (r >> 3)]);
+                self.emit(&[0xb8 + (r & 7)]);
+                self.emit(&n.to_le_bytes());
+                return Ok(())
+            },
+            (_, &Operand::Imm(n)) => {
+                let len = if size == 8 { 4 } else { size };
+                self.modrm(size, rex8, &[0xc6 + byte], 0, dst, len as i64)?;
+                self.emit(&n.to_le_bytes()[..len as usize]);
+                return Ok(())
+            },
+            _ => return Err("bad operands".to_string()),
+        }
+    }
+
+    // SSE instructions take a mandatory prefix, which goes before REX, and
+    // a two-byte opcode. `wide` sets REX.W for a 64-bit integer operand.
+    fn sse(&mut self, prefix: u8, opcode: u8, wide: bool, reg: u8, rm: &Operand, imm_len: i64) -> Result<(), String> {
+        self.emit(&[prefix]);
+        return self.modrm(if wide { 8 } else { 4 }, false, &[0x0f, opcode], reg, rm, imm_len)
+    }
+
+    fn instruction(&mut self, mnemonic: &str, ops: &[Operand]) -> Result<(), String> {
+        match (mnemonic, ops) {
+            ("ret", []) => self.emit(&[0xc3]),
+            ("cqo", []) => self.emit(&[0x48, 0x99]),
+            ("push", [Operand::Reg(r, 8)]) | ("pop", [Operand::Reg(r, 8)]) => {
+                if r & 8 != 0 { self.emit(&[0x41]); }
+                self.emit(&[if mnemonic == "push" { 0x50 } else { 0x58 } + (r & 7)]);
+            },
+            ("mov", [dst, src]) => return self.mov(dst, src),
+            ("add", [dst, src]) => return self.alu(0, dst, src),
+            ("or", [dst, src]) => return self.alu(1, dst, src),
+            ("and", [dst, src]) => return self.alu(4, dst, src),
+            ("sub", [dst, src]) => return self.alu(5, dst, src),
+            ("xor", [dst, src]) => return self.alu(6, dst, src),
+            ("cmp", [dst, src]) => return self.alu(7, dst, src),
+            ("not", [op]) | ("neg", [op]) | ("mul", [op]) | ("imul", [op]) | ("div", [op]) | ("idiv", [op]) => {
+                let digit = match mnemonic { "not" => 2, "neg" => 3, "mul" => 4, "imul" => 5, "div" => 6, _ => 7 };
+                let size = size_of(op).ok_or("operand size unknown")?;
+                return self.modrm(size, needs_rex8(&[op]), &[if size == 1 { 0xf6 } else { 0xf7 }], digit, op, 0)
+            },
+            ("shl", [op, count]) | ("sal", [op, count]) | ("shr", [op, count]) | ("sar", [op, count]) => {
+                let digit = match mnemonic { "shl" | "sal" => 4, "shr" => 5, _ => 7 };
+                let size = size_of(op).ok_or("operand size unknown")?;
+                let byte = if size == 1 { 0 } else { 1 };
+                match *count {
+                    Operand::Reg(1, 1) => return self.modrm(size, needs_rex8(&[op]), &[0xd2 + byte], digit, op, 0),
+                    Operand::Imm(n) => {
+                        self.modrm(size, needs_rex8(&[op]), &[0xc0 + byte], digit, op, 1)?;
+                        self.emit(&[n as u8]);
+                    },
+                    _ => return Err(format!("shift count must be cl or an immediate, but got {:?}", count)),
+                }
+            },
+            ("movzx", [Operand::Reg(r, size), src]) | ("movsx", [Operand::Reg(r, size), src]) => {
+                let wide = size_of(src) == Some(2);
+                let opcode = if mnemonic == "movzx" { 0xb6 } else { 0xbe } + if wide { 1 } else { 0 };
+                return self.modrm(*size, needs_rex8(&[src]), &[0x0f, opcode], *r, src, 0)
+            },
+            ("imul", [Operand::Reg(r, size), src]) => return self.modrm(*size, false, &[0x0f, 0xaf], *r, src, 0),
+            ("movsxd", [Operand::Reg(r, 8), src]) => return self.modrm(8, false, &[0x63], *r, src, 0),
+            ("lea", [Operand::Reg(r, size), src @ Operand::Mem(_)]) => return self.modrm(*size, false, &[0x8d], *r, src, 0),
+            ("jmp", [op @ Operand::Reg(_, 8)]) => return self.modrm(4, false, &[0xff], 4, op, 0),
+            ("jmp", [Operand::Sym(label)]) => {
+                self.emit(&[0xe9]);
+                self.emit_rel32(label, RelocKind::Pc32, -4);
+            },
+            ("call", [Operand::Sym(label)]) => {
+                self.emit(&[0xe8]);
+                self.emit_rel32(label, RelocKind::Plt32, -4);
+            },
+            ("movsd", [Operand::Xmm(r), src]) => return self.sse(0xf2, 0x10, false, *r, src, 0),
+            ("movsd", [dst, Operand::Xmm(r)]) => return self.sse(0xf2, 0x11, false, *r, dst, 0),
+            ("movss", [Operand::Xmm(r), src]) => return self.sse(0xf3, 0x10, false, *r, src, 0),
+            ("movss", [dst, Operand::Xmm(r)]) => return self.sse(0xf3, 0x11, false, *r, dst, 0),
+            ("movapd", [Operand::Xmm(r), src]) => return self.sse(0x66, 0x28, false, *r, src, 0),
+            ("movq", [Operand::Xmm(r), src @ Operand::Reg(_, 8)]) => return self.sse(0x66, 0x6e, true, *r, src, 0),
+            ("movq", [dst @ Operand::Reg(_, 8), Operand::Xmm(r)]) => return self.sse(0x66, 0x7e, true, *r, dst, 0),
+            ("addsd", [Operand::Xmm(r), src]) | ("mulsd", [Operand::Xmm(r), src]) |
+            ("subsd", [Operand::Xmm(r), src]) | ("divsd", [Operand::Xmm(r), src]) => {
+                let opcode = match mnemonic { "addsd" => 0x58, "mulsd" => 0x59, "subsd" => 0x5c, _ => 0x5e };
+                return self.sse(0xf2, opcode, false, *r, src, 0)
+            },
+            ("andpd", [Operand::Xmm(r), src]) => return self.sse(0x66, 0x54, false, *r, src, 0),
+            ("xorpd", [Operand::Xmm(r), src]) => return self.sse(0x66, 0x57, false, *r, src, 0),
+            ("cvtsd2ss", [Operand::Xmm(r), src]) => return self.sse(0xf2, 0x5a, false, *r, src, 0),
+            ("cvtss2sd", [Operand::Xmm(r), src]) => return self.sse(0xf3, 0x5a, false, *r, src, 0),
+            ("cvtsi2sd", [Operand::Xmm(r), src]) => return self.sse(0xf2, 0x2a, size_of(src) == Some(8), *r, src, 0),
+            ("cvttsd2si", [Operand::Reg(r, size), src]) => return self.sse(0xf2, 0x2c, *size == 8, *r, src, 0),
+            _ if mnemonic.starts_with("cmp") && mnemonic.ends_with("sd") &&
+                 SSE_PREDS.contains(&&mnemonic[3..mnemonic.len() - 2]) => {
+                let pred = SSE_PREDS.iter().position(|&p| p == &mnemonic[3..mnemonic.len() - 2]).unwrap();
+                match ops {
+                    [Operand::Xmm(r), src] => {
+                        self.sse(0xf2, 0xc2, false, *r, src, 1)?;
+                        self.emit(&[pred as u8]);
+                    },
+                    _ => return Err(format!("bad operands for {}", mnemonic)),
+                }
+            },
+            _ if mnemonic.starts_with('j') && parse_cond(&mnemonic[1..]).is_some() => {
+                let cc = parse_cond(&mnemonic[1..]).unwrap();
+                match ops {
+                    [Operand::Sym(label)] => {
+                        self.emit(&[0x0f, 0x80 + cc]);
+                        self.emit_rel32(label, RelocKind::Pc32, -4);
+                    },
+                    _ => return Err(format!("bad operands for {}", mnemonic)),
+                }
+            },
+            _ if mnemonic.starts_with("set") && parse_cond(&mnemonic[3..]).is_some() => {
+                let cc = parse_cond(&mnemonic[3..]).unwrap();
+                match ops {
+                    [op] => return self.modrm(1, needs_rex8(&[op]), &[0x0f, 0x90 + cc], 0, op, 0),
+                    _ => return Err(format!("bad operands for {}", mnemonic)),
+                }
+            },
+            _ => return Err(format!("unsupported instruction {} {:?}", mnemonic, ops)),
+        }
+        return Ok(())
+    }
+
+    fn directive(&mut self, name: &str, args: &str) -> Result<(), String> {
+        match name {
+            ".intel_syntax" => {},
+            ".text" => self.cur = TEXT,
+            ".data" => self.cur = DATA,
+            ".bss" => self.cur = BSS,
+            ".section" => {
+                let section = args.split(',').next().unwrap().trim();
+                match SECTION_NAMES.iter().position(|&s| s == section) {
+                    Some(n) => self.cur = n,
+                    // Every object we write marks the stack non-executable.
+                    None if section == ".note.GNU-stack" => {},
+                    None => return Err(format!("unsupported section {}", section)),
+                }
+            },
+            ".global" | ".globl" => { self.globals.insert(args.trim().to_string()); },
+            ".type" => {
+                let ops = split_operands(args);
+                if ops.len() == 2 && ops[1] == "@function" {
+                    self.funcs.insert(ops[0].to_string());
+                }
+            },
+            ".size" => {
+                let ops = split_operands(args);
+                let sym = ops[0].to_string();
+                if ops.len() != 2 || ops[1] != format!(".-{}", sym) {
+                    return Err(format!("unsupported .size {}", args));
+                }
+                let start = match self.labels.get(&sym) {
+                    Some(&(section, offset)) if section == self.cur => offset,
+                    _ => return Err(format!("{} is not defined in this section", sym)),
+                };
+                let size = self.pos() - start;
+                self.sizes.insert(sym, size);
+            },
+            ".ascii" | ".asciz" | ".string" => {
+                let mut bytes = parse_string(args)?;
+                if name != ".ascii" { bytes.push(0); }
+                self.emit(&bytes);
+            },
+            ".byte" | ".short" | ".long" | ".quad" => {
+                let len = match name { ".byte" => 1, ".short" => 2, ".long" => 4, _ => 8 };
+                for op in split_operands(args) {
+                    match parse_operand(op)? {
+                        Operand::Imm(n) => self.emit(&n.to_le_bytes()[..len]),
+                        // `a-b` with b in this section, as in jump tables.
+                        Operand::Sym(ref sym) if len == 4 && sym.contains('-') => {
+                            let (a, b) = sym.split_at(sym.find('-').unwrap());
+                            let base = match self.labels.get(&b[1..]) {
+                                Some(&(section, offset)) if section == self.cur => offset,
+                                _ => return Err(format!("{} is not defined in this section", &b[1..])),
+                            };
+                            let addend = self.pos() as i64 - base as i64;
+                            self.emit_rel32(a, RelocKind::Pc32, addend);
+                        },
+                        // `sym`, `sym+n` or `sym-n`; names may start with a dot but have no signs.
+                        Operand::Sym(ref sym) if len == 8 => {
+                            let (target, addend) = match sym.rfind(['+', '-']) {
+                                Some(i) if i > 0 => {
+                                    let n = parse_int(&sym[i + 1..]).ok_or(format!("bad {} operand {}", name, op))?;
+                                    (sym[..i].to_string(), if &sym[i..i + 1] == "-" { -n } else { n })
+                                },
+                                _ => (sym.clone(), 0),
+                            };
+                            let offset = self.pos();
+                            self.fixups.push(Fixup{section: self.cur, offset, target, kind: RelocKind::Abs64, addend});
+                            self.emit(&[0; 8]);
+                        },
+                        _ => return Err(format!("bad {} operand {}", name, op)),
+                    }
+                }
+            },
+            ".zero" => {
+                let n = parse_int(args.trim()).ok_or("bad .zero size")?;
+                self.emit(&vec![0; n as usize]);
+            },
+            ".align" | ".p2align" => {
+                let n = parse_int(args.trim()).ok_or("bad alignment")? as usize;
+                let align = if name == ".p2align" { 1 << n } else { n };
+                // Pad code with nops and data with zeros.
+                let fill = if self.cur == TEXT { 0x90 } else { 0 };
+                while self.pos() % align != 0 {
+                    self.emit(&[fill]);
+                }
+            },
+            _ => return Err(format!("unsupported directive {}", name)),
+        }
+        return Ok(())
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        if line.ends_with(':') && !line.contains(char::is_whitespace) {
+            let name = line[..line.len() - 1].to_string();
+            if self.labels.contains_key(&name) {
+                return Err(format!("{} is already defined", name));
+            }
+            let pos = self.pos();
+            self.labels.insert(name, (self.cur, pos));
+            return Ok(());
+        }
+        let (head, rest) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        if head.starts_with('.') {
+            return self.directive(head, rest);
+        }
+        let ops = split_operands(rest).into_iter().map(parse_operand).collect::<Result<Vec<_>, _>>()?;
+        return self.instruction(head, &ops)
+    }
+
+    fn finish(mut self) -> Result<Object, String> {
+        for f in self.fixups.drain(..) {
+            let label = self.labels.get(&f.target).cloned();
+            match label {
+                // A pc-relative reference within one section needs no relocation.
+                Some((section, offset)) if section == f.section && f.kind != RelocKind::Abs64 => {
+                    let value = offset as i64 + f.addend - f.offset as i64;
+                    self.obj.sections[f.section][f.offset..f.offset + 4].copy_from_slice(&(value as i32).to_le_bytes());
+                },
+                Some((section, offset)) if f.target.starts_with(".L") => {
+                    self.obj.relocs.push(Reloc{section: f.section, offset: f.offset, target: RelocTarget::Section(section),
+                        kind: f.kind, addend: offset as i64 + f.addend});
+                },
+                _ => {
+                    self.obj.relocs.push(Reloc{section: f.section, offset: f.offset, target: RelocTarget::Symbol(f.target),
+                        kind: f.kind, addend: f.addend});
+                },
+            }
+        }
+        let mut labels: Vec<(String, (usize, usize))> = self.labels.into_iter().filter(|&(ref name, _)| !name.starts_with(".L")).collect();
+        labels.sort_by_key(|&(_, pos)| pos);
+        for (name, (section, offset)) in labels {
+            self.obj.symbols.push(Symbol{
+                size: self.sizes.get(&name).cloned().unwrap_or(0),
+                global: self.globals.contains(&name),
+                func: self.funcs.contains(&name),
+                name, section, offset,
+            });
+        }
+        return Ok(self.obj)
+    }
+}
+
+pub fn assemble(text: &str) -> Result<Object, String> {
+    let mut a = Assembler {
+        obj: Object{sections: vec![Vec::new(); SECTION_NAMES.len()], ..Default::default()},
+        cur: TEXT,
+        labels: HashMap::new(),
+        globals: HashSet::new(),
+        funcs: HashSet::new(),
+        sizes: HashMap::new(),
+        fixups: Vec::new(),
+    };
+    for (i, line) in text.lines().enumerate() {
+        a.line(line).map_err(|e| format!("line {}: {}: {}", i + 1, e, line.trim()))?;
+    }
+    return a.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn text(asm: &str) -> Vec<u8> {
+        return assemble(asm).unwrap().sections[TEXT].clone()
+    }
+
+    # [test]
+    fn can_encode_instructions() {
+        assert_eq!(text("push rbp\nmov rbp, rsp\nsub rsp, 8\npush r12"), [0x55, 0x48, 0x89, 0xe5, 0x48, 0x83, 0xec, 0x08, 0x41, 0x54]);
+        assert_eq!(text("mov r10, 5"), [0x49, 0xc7, 0xc2, 0x05, 0x00, 0x00, 0x00]);
+        assert_eq!(text("mov [rbp-8], rdi"), [0x48, 0x89, 0x7d, 0xf8]);
+        assert_eq!(text("mov ebx, [r12]"), [0x41, 0x8b, 0x1c, 0x24]);
+        assert_eq!(text("mov [r13], sil"), [0x41, 0x88, 0x75, 0x00]);
+        assert_eq!(text("setl bl\nmovzx rbx, bl"), [0x0f, 0x9c, 0xc3, 0x48, 0x0f, 0xb6, 0xdb]);
+        assert_eq!(text("mov rax, r10\nmul r11\ndiv rbx\ncqo\nret"),
+            [0x4c, 0x89, 0xd0, 0x49, 0xf7, 0xe3, 0x48, 0xf7, 0xf3, 0x48, 0x99, 0xc3]);
+        assert_eq!(text("shl r10, cl\nsar rbx, cl\nshr eax, 3\nneg r11"),
+            [0x49, 0xd3, 0xe2, 0x48, 0xd3, 0xfb, 0xc1, 0xe8, 0x03, 0x49, 0xf7, 0xdb]);
+        assert_eq!(text("imul r10, r11\nimul ebx, DWORD PTR [rbp-4]"), [0x4d, 0x0f, 0xaf, 0xd3, 0x0f, 0xaf, 0x5d, 0xfc]);
+        assert_eq!(text("movsxd rcx, DWORD PTR [rcx]\njmp rax\njmp r11"), [0x48, 0x63, 0x09, 0xff, 0xe0, 0x41, 0xff, 0xe3]);
+    }
+
+    # [test]
+    fn can_encode_sse_instructions() {
+        assert_eq!(text("movsd xmm8, QWORD PTR [r10]\nmovsd QWORD PTR [rbp-8], xmm0"),
+            [0xf2, 0x45, 0x0f, 0x10, 0x02, 0xf2, 0x0f, 0x11, 0x45, 0xf8]);
+        assert_eq!(text("movss DWORD PTR [rsp], xmm0\ncvtss2sd xmm9, DWORD PTR [rbx]"),
+            [0xf3, 0x0f, 0x11, 0x04, 0x24, 0xf3, 0x44, 0x0f, 0x5a, 0x0b]);
+        assert_eq!(text("addsd xmm8, xmm9\nsubsd xmm0, xmm1\nmulsd xmm8, xmm0\ndivsd xmm1, xmm15"),
+            [0xf2, 0x45, 0x0f, 0x58, 0xc1, 0xf2, 0x0f, 0x5c, 0xc1, 0xf2, 0x44, 0x0f, 0x59, 0xc0, 0xf2, 0x41, 0x0f, 0x5e, 0xcf]);
+        assert_eq!(text("movq xmm8, rax\nmovq r10, xmm1\nmovapd xmm0, xmm8"),
+            [0x66, 0x4c, 0x0f, 0x6e, 0xc0, 0x66, 0x49, 0x0f, 0x7e, 0xca, 0x66, 0x41, 0x0f, 0x28, 0xc0]);
+        assert_eq!(text("cvtsi2sd xmm8, r10\ncvttsd2si rbx, xmm9\ncvtsd2ss xmm0, xmm8"),
+            [0xf2, 0x4d, 0x0f, 0x2a, 0xc2, 0xf2, 0x49, 0x0f, 0x2c, 0xd9, 0xf2, 0x41, 0x0f, 0x5a, 0xc0]);
+        assert_eq!(text("cmpltsd xmm8, xmm9\ncmpneqsd xmm0, xmm1\nandpd xmm8, xmm0\nxorpd xmm9, xmm0"),
+            [0xf2, 0x45, 0x0f, 0xc2, 0xc1, 0x01, 0xf2, 0x0f, 0xc2, 0xc1, 0x04, 0x66, 0x44, 0x0f, 0x54, 0xc0, 0x66, 0x44, 0x0f, 0x57, 0xc8]);
+    }
+
+    # [test]
+    fn can_emit_jump_tables() {
+        let obj = assemble(".text\n.L1:\n\tret\n.L2:\n\tret\n.section .rodata\n.L.jt0:\n\t.long .L1-.L.jt0\n\t.long .L2-.L.jt0\n.text\n").unwrap();
+        assert_eq!(obj.sections[RODATA], [0; 8]);
+        assert_eq!(obj.relocs, [
+            Reloc{section: RODATA, offset: 0, target: RelocTarget::Section(TEXT), kind: RelocKind::Pc32, addend: 0},
+            Reloc{section: RODATA, offset: 4, target: RelocTarget::Section(TEXT), kind: RelocKind::Pc32, addend: 1 + 4},
+        ]);
+        assert!(assemble(".text\n.L1:\n\t.long .L1-.L9\n").is_err());
+    }
+
+    # [test]
+    fn can_resolve_labels_and_relocs() {
+        let obj = assemble(".section .rodata\n.L.str0:\n\t.asciz \"a\\n\"\n.text\n.global main\n.type main, @function\nmain:\n\tlea r10, [rip + .L.str0]\n\tcall puts\n.L1:\n\tjmp .L1\n").unwrap();
+        assert_eq!(obj.sections[RODATA], b"a\n\0");
+        assert_eq!(obj.sections[TEXT], [0x4c, 0x8d, 0x15, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0, 0xe9, 0xfb, 0xff, 0xff, 0xff]);
+        assert_eq!(obj.relocs, [
+            Reloc{section: TEXT, offset: 3, target: RelocTarget::Section(RODATA), kind: RelocKind::Pc32, addend: -4},
+            Reloc{section: TEXT, offset: 8, target: RelocTarget::Symbol("puts".to_string()), kind: RelocKind::Plt32, addend: -4},
+        ]);
+        assert_eq!(obj.symbols, [Symbol{name: "main".to_string(), section: TEXT, offset: 0, size: 0, global: true, func: true}]);
+    }
+}
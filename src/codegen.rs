@@ -1,140 +1,404 @@
 extern crate rugcc;
-use self::rugcc::common::{ND, IRType, Function};
-use {REGS, REGS8, REGS32};
+use self::rugcc::common::{ND, IRType, Function, GlobalVar, CompileError, Span, align_to};
+use {REGS, REGS8, REGS16, REGS32, XMMS};
+use target::Target;
+use std::cmp::max;
 
 
 static ARGREG64: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
 static ARGREG32: [&str; 6] = ["edi", "esi", "edx", "ecx", "r8d", "r9d"];
+static ARGREG16: [&str; 6] = ["di", "si", "dx", "cx", "r8w", "r9w"];
 static ARGREG8: [&str; 6] = ["dil", "sil", "dl", "cl", "r8b", "r9b"];
+static ARGXMM: [&str; 8] = ["xmm0", "xmm1", "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7"];
 
+// The bits of 1.0, and of the sign of a double.
+const ONE_BITS: u64 = 0x3ff0_0000_0000_0000;
+const SIGN_BIT: u64 = 1 << 63;
 
-fn gen(func: Function, label: usize) {
-    println!(".data");
+macro_rules! emit {
+    ($out:expr, $($arg:tt)*) => {{
+        $out.push_str(&format!($($arg)*));
+        $out.push('\n');
+    }}
+}
+
+// Private labels such as `.L.str0` are not C symbols, so they are left alone.
+fn symbol(name: &str, target: Target) -> String {
+    if name.starts_with(".L") {
+        return name.to_string()
+    }
+    return target.symbol(name)
+}
+
+// Variables with an initializer go to .data and the rest to .bss.
+fn gen_data(var: GlobalVar, target: Target, out: &mut String) {
+    emit!(out, "{}", if var.data.is_some() { ".data" } else { ".bss" });
+    emit!(out, "\t.p2align {}", var.align.trailing_zeros());
+    let sym = symbol(&var.name, target);
+    if !var.is_static {
+        emit!(out, ".global {}", sym);
+    }
+    emit!(out, "{}:", sym);
+    let data = match var.data {
+        Some(data) => data,
+        None => {
+            emit!(out, "\t.zero {}", var.size);
+            return
+        },
+    };
+    let mut relocs = var.relocs.clone();
+    relocs.sort_by_key(|r| r.0);
+    let mut pos = 0;
+    for (offset, name, addend) in relocs {
+        for chunk in data[pos..offset].chunks(16) {
+            let list: Vec<String> = chunk.iter().map(|b| b.to_string()).collect();
+            emit!(out, "\t.byte {}", list.join(","));
+        }
+        match addend {
+            0 => emit!(out, "\t.quad {}", symbol(&name, target)),
+            n if n > 0 => emit!(out, "\t.quad {}+{}", symbol(&name, target), n),
+            n => emit!(out, "\t.quad {}-{}", symbol(&name, target), -n),
+        }
+        pos = offset + 8;
+    }
+    for chunk in data[pos..].chunks(16) {
+        let list: Vec<String> = chunk.iter().map(|b| b.to_string()).collect();
+        emit!(out, "\t.byte {}", list.join(","));
+    }
+}
+
+fn gen(func: Function, label: usize, target: Target, out: &mut String) -> Result<(), CompileError> {
     for node in func.strings {
         if node.op == ND::STR {
-            println!("{}:", node.val);
-            println!("\t.asciz \"{}\"", node.str);
+            // A literal with a NUL inside would be split apart in a
+            // C string section.
+            let section = if node.str.contains(&0) { target.const_section() } else { target.rodata_section() };
+            emit!(out, "{}", section);
+            emit!(out, "{}:", node.val);
+            // Bytes rather than .asciz, so quotes and escapes need no quoting.
+            let mut bytes = node.str.clone();
+            bytes.push(0);
+            for chunk in bytes.chunks(16) {
+                let list: Vec<String> = chunk.iter().map(|b| b.to_string()).collect();
+                emit!(out, "\t.byte {}", list.join(","));
+            }
         } else {
-            unreachable!("ND::STR expected but got: {:?}", node.op);
+            error!(Internal, node.span, "ND::STR expected but got: {:?}", node.op);
         }
     }
 
 
     let ret = format!(".Lend{}", label);
-    println!(".text");
-    println!(".global _{}", func.name);
-    println!("_{}:", func.name);
-    println!("\tpush rbp");
-    println!("\tmov rbp, rsp");
-    println!("\tsub rsp, {}", func.stack_size);
-    println!("\tpush r12");
-    println!("\tpush r13");
-    println!("\tpush r14");
-    println!("\tpush r15");
+    emit!(out, ".text");
+    for line in target.function_begin(&func.name) {
+        emit!(out, "{}", line);
+    }
+    emit!(out, "{}:", target.symbol(&func.name));
+    emit!(out, "\tpush rbp");
+    emit!(out, "\tmov rbp, rsp");
+    // Five callee-saved pushes here plus two around each call: an odd number
+    // of eight-byte slots below the frame keeps rsp aligned at calls.
+    let align = target.stack_align();
+    emit!(out, "\tsub rsp, {}", align_to(func.stack_size, align) + 8);
+    emit!(out, "\tpush rbx");
+    emit!(out, "\tpush r12");
+    emit!(out, "\tpush r13");
+    emit!(out, "\tpush r14");
+    emit!(out, "\tpush r15");
 
+    // Jump tables go to .rodata once the function is done.
+    let mut tables = Vec::new();
+    // Words of arguments pushed for the next call, and the number of xmm
+    // registers it is passed, which a variadic callee expects in al.
+    let mut stack_args = 0;
+    let mut xmm_args = 0;
     for ir in func.irs {
         match ir.op {
             IRType::IMM => {
-                println!("\tmov {}, {}", REGS[ir.lhs], ir.rhs);
+                // Negative constants are stored two's complement.
+                emit!(out, "\tmov {}, {}", REGS[ir.lhs], ir.rhs as i64);
             }
             IRType::MOV => {
-                println!("\tmov {}, {}", REGS[ir.lhs], REGS[ir.rhs]);
+                emit!(out, "\tmov {}, {}", REGS[ir.lhs], REGS[ir.rhs]);
             },
             IRType::SUB_IMM => {
-                println!("\tsub {}, {}", REGS[ir.lhs], ir.rhs);
+                emit!(out, "\tsub {}, {}", REGS[ir.lhs], ir.rhs);
             }
             IRType::RETURN => {
-                println!("\tmov rax, {}", REGS[ir.lhs]);
-                println!("\tjmp {}", ret);
-            },
-            IRType::ADD => println!("\tadd {}, {}", REGS[ir.lhs], REGS[ir.rhs]),
-            IRType::SUB => println!("\tsub {}, {}", REGS[ir.lhs], REGS[ir.rhs]),
-            IRType::MUL => {
-                println!("\tmov rax, {}", REGS[ir.rhs]);
-                println!("\tmul {}", REGS[ir.lhs]);
-                println!("\tmov {}, rax", REGS[ir.lhs]);
-            },
-            IRType::DIV => {
-                println!("\tmov rax, {}", REGS[ir.lhs]);
-                println!("\tcqo");
-                println!("\tdiv {}", REGS[ir.rhs]);
-                println!("\tmov {}, rax", REGS[ir.lhs]);
+                emit!(out, "\tmov rax, {}", REGS[ir.lhs]);
+                emit!(out, "\tjmp {}", ret);
+            },
+            IRType::ADD => emit!(out, "\tadd {}, {}", REGS[ir.lhs], REGS[ir.rhs]),
+            IRType::SUB => emit!(out, "\tsub {}, {}", REGS[ir.lhs], REGS[ir.rhs]),
+            // The low 64 bits of the product are the same for signed and
+            // unsigned operands.
+            IRType::MUL => emit!(out, "\timul {}, {}", REGS[ir.lhs], REGS[ir.rhs]),
+            // The dividend in rdx:rax is sign-extended for idiv and
+            // zero-extended for div.
+            IRType::DIV | IRType::MOD | IRType::UDIV | IRType::UMOD => {
+                emit!(out, "\tmov rax, {}", REGS[ir.lhs]);
+                if ir.op == IRType::DIV || ir.op == IRType::MOD {
+                    emit!(out, "\tcqo");
+                    emit!(out, "\tidiv {}", REGS[ir.rhs]);
+                } else {
+                    emit!(out, "\txor edx, edx");
+                    emit!(out, "\tdiv {}", REGS[ir.rhs]);
+                }
+                let res = if ir.op == IRType::DIV || ir.op == IRType::UDIV { "rax" } else { "rdx" };
+                emit!(out, "\tmov {}, {}", REGS[ir.lhs], res);
+            },
+            IRType::AND => emit!(out, "\tand {}, {}", REGS[ir.lhs], REGS[ir.rhs]),
+            IRType::OR => emit!(out, "\tor {}, {}", REGS[ir.lhs], REGS[ir.rhs]),
+            IRType::XOR => emit!(out, "\txor {}, {}", REGS[ir.lhs], REGS[ir.rhs]),
+            IRType::SHL | IRType::SHR | IRType::USHR => {
+                let insn = match ir.op { IRType::SHL => "shl", IRType::SHR => "sar", _ => "shr" };
+                emit!(out, "\tmov rcx, {}", REGS[ir.rhs]);
+                emit!(out, "\t{} {}, cl", insn, REGS[ir.lhs]);
             },
+            IRType::NEG => emit!(out, "\tneg {}", REGS[ir.lhs]),
             IRType::CALL => {
-                for i in 0..ir.args.clone().len(){
-                    println!("\tmov {}, {}", ARGREG64[i], REGS[ir.args[i]]);
+                if stack_args == 0 {
+                    emit!(out, "\tpush r10");
+                    emit!(out, "\tpush r11");
+                }
+                emit!(out, "\tmov rax, {}", xmm_args);
+                xmm_args = 0;
+                emit!(out, "\tcall {}", target.symbol(&ir.name));
+                if stack_args > 0 {
+                    emit!(out, "\tadd rsp, {}", (stack_args + stack_args % 2) * 8);
+                    stack_args = 0;
                 }
-                println!("\tpush r10");
-                println!("\tpush r11");
-                println!("\tmov rax, 0");
-                println!("\tcall _{}", ir.name);
-                println!("\tpop r11");
-                println!("\tpop r10");
-                println!("\tmov {}, rax", REGS[ir.lhs]);
+                emit!(out, "\tpop r11");
+                emit!(out, "\tpop r10");
+                emit!(out, "\tmov {}, rax", REGS[ir.lhs]);
+            },
+            // Stack arguments have to end right above the return address, so
+            // r10 and r11 are saved before them, with padding to keep rsp aligned.
+            IRType::STACK_ARGS => {
+                emit!(out, "\tpush r10");
+                emit!(out, "\tpush r11");
+                if ir.lhs % 2 == 1 {
+                    emit!(out, "\tsub rsp, 8");
+                }
+                stack_args = ir.lhs;
+            },
+            IRType::PUSH_ARG => emit!(out, "\tpush {}", REGS[ir.lhs]),
+            IRType::GET_RDX => emit!(out, "\tmov {}, rdx", REGS[ir.lhs]),
+            IRType::SET_RDX => emit!(out, "\tmov rdx, {}", REGS[ir.lhs]),
+            IRType::ARG => {
+                emit!(out, "\tmov {}, {}", ARGREG64[ir.rhs], REGS[ir.lhs]);
+            },
+            IRType::LOAD_SPILL => {
+                emit!(out, "\tmov {}, [rbp-{}]", REGS[ir.lhs], ir.rhs);
+            },
+            IRType::STORE_SPILL => {
+                emit!(out, "\tmov [rbp-{}], {}", ir.rhs, REGS[ir.lhs]);
             },
             IRType::STORE8_ARG => {
-                println!("\tmov [rbp-{}], {}", ir.lhs, ARGREG8[ir.rhs]);
+                emit!(out, "\tmov [rbp-{}], {}", ir.lhs, ARGREG8[ir.rhs]);
+            },
+            IRType::STORE16_ARG => {
+                emit!(out, "\tmov [rbp-{}], {}", ir.lhs, ARGREG16[ir.rhs]);
             },
             IRType::STORE32_ARG => {
-                println!("\tmov [rbp-{}], {}", ir.lhs, ARGREG32[ir.rhs]);
+                emit!(out, "\tmov [rbp-{}], {}", ir.lhs, ARGREG32[ir.rhs]);
             },
             IRType::STORE64_ARG => {
-                println!("\tmov [rbp-{}], {}", ir.lhs, ARGREG64[ir.rhs]);
+                emit!(out, "\tmov [rbp-{}], {}", ir.lhs, ARGREG64[ir.rhs]);
             },
-            IRType::LT => {
-                println!("\tcmp {}, {}", REGS[ir.lhs], REGS[ir.rhs]);
-                println!("\tsetl {}", REGS8[ir.lhs]);
-                println!("\tmovzx {}, {}", REGS[ir.lhs], REGS8[ir.lhs]);
+            IRType::LT | IRType::LE | IRType::ULT | IRType::ULE | IRType::EQ | IRType::NE => {
+                let insn = match ir.op {
+                    IRType::LT => "setl",
+                    IRType::LE => "setle",
+                    IRType::ULT => "setb",
+                    IRType::ULE => "setbe",
+                    IRType::EQ => "sete",
+                    _ => "setne",
+                };
+                emit!(out, "\tcmp {}, {}", REGS[ir.lhs], REGS[ir.rhs]);
+                emit!(out, "\t{} {}", insn, REGS8[ir.lhs]);
+                emit!(out, "\tmovzx {}, {}", REGS[ir.lhs], REGS8[ir.lhs]);
             }
-            IRType::LABEL => println!(".L{}:", ir.lhs),
-            IRType::LABEL_ADDR => println!("\tlea {}, [rip + {}]", REGS[ir.lhs], ir.name),
+            IRType::LABEL => emit!(out, ".L{}:", ir.lhs),
+            IRType::LABEL_ADDR => emit!(out, "\tlea {}, [rip + {}]", REGS[ir.lhs], symbol(&ir.name, target)),
             IRType::UNLESS => {
-                println!("\tcmp {}, 0", REGS[ir.lhs]);
-                println!("\tje .L{}", ir.rhs);
+                emit!(out, "\tcmp {}, 0", REGS[ir.lhs]);
+                emit!(out, "\tje .L{}", ir.rhs);
             },
             IRType::JMP => {
-                println!("\tjmp .L{}", ir.lhs);
+                emit!(out, "\tjmp .L{}", ir.lhs);
+            },
+            // Entries hold the distance from the table to each case.
+            IRType::JMP_TABLE => {
+                let table = format!(".L.jt{}", ir.rhs);
+                emit!(out, "\tcmp {}, {}", REGS[ir.lhs], ir.targets.len());
+                emit!(out, "\tjae .L{}", ir.rhs);
+                emit!(out, "\tlea rax, [rip + {}]", table);
+                emit!(out, "\tmov rcx, {}", REGS[ir.lhs]);
+                emit!(out, "\tshl rcx, 2");
+                emit!(out, "\tadd rcx, rax");
+                emit!(out, "\tmovsxd rcx, DWORD PTR [rcx]");
+                emit!(out, "\tadd rax, rcx");
+                emit!(out, "\tjmp rax");
+                tables.push((table, ir.targets));
             },
             IRType::LOAD8 => {
-                println!("\tmov {}, [{}]", REGS8[ir.lhs], REGS[ir.rhs]);
-                println!("\tmovzx {}, {}", REGS[ir.lhs], REGS8[ir.lhs]);
+                emit!(out, "\tmov {}, [{}]", REGS8[ir.lhs], REGS[ir.rhs]);
+                emit!(out, "\tmovzx {}, {}", REGS[ir.lhs], REGS8[ir.lhs]);
             },
+            IRType::LOAD16 => emit!(out, "\tmovzx {}, WORD PTR [{}]", REGS[ir.lhs], REGS[ir.rhs]),
             IRType::LOAD32 => {
-                println!("\tmov {}, [{}]", REGS32[ir.lhs], REGS[ir.rhs]);
+                emit!(out, "\tmov {}, [{}]", REGS32[ir.lhs], REGS[ir.rhs]);
             },
+            IRType::SLOAD8 => emit!(out, "\tmovsx {}, BYTE PTR [{}]", REGS[ir.lhs], REGS[ir.rhs]),
+            IRType::SLOAD16 => emit!(out, "\tmovsx {}, WORD PTR [{}]", REGS[ir.lhs], REGS[ir.rhs]),
+            IRType::SLOAD32 => emit!(out, "\tmovsxd {}, DWORD PTR [{}]", REGS[ir.lhs], REGS[ir.rhs]),
             IRType::LOAD64 => {
-                println!("\tmov {}, [{}]", REGS[ir.lhs], REGS[ir.rhs]);
+                emit!(out, "\tmov {}, [{}]", REGS[ir.lhs], REGS[ir.rhs]);
             },
             IRType::STORE8 => {
-                println!("\tmov [{}], {}", REGS[ir.lhs], REGS8[ir.rhs]);
+                emit!(out, "\tmov [{}], {}", REGS[ir.lhs], REGS8[ir.rhs]);
             },
+            IRType::STORE16 => emit!(out, "\tmov [{}], {}", REGS[ir.lhs], REGS16[ir.rhs]),
             IRType::STORE32 => {
-                println!("\tmov [{}], {}", REGS[ir.lhs], REGS32[ir.rhs]);
+                emit!(out, "\tmov [{}], {}", REGS[ir.lhs], REGS32[ir.rhs]);
+            },
+            IRType::SEXT => match ir.rhs {
+                1 => emit!(out, "\tmovsx {}, {}", REGS[ir.lhs], REGS8[ir.lhs]),
+                2 => emit!(out, "\tmovsx {}, {}", REGS[ir.lhs], REGS16[ir.lhs]),
+                _ => emit!(out, "\tmovsxd {}, {}", REGS[ir.lhs], REGS32[ir.lhs]),
+            },
+            // Writing a 32-bit register clears the upper half.
+            IRType::ZEXT => match ir.rhs {
+                1 => emit!(out, "\tmovzx {}, {}", REGS[ir.lhs], REGS8[ir.lhs]),
+                2 => emit!(out, "\tmovzx {}, {}", REGS[ir.lhs], REGS16[ir.lhs]),
+                _ => emit!(out, "\tmov {}, {}", REGS32[ir.lhs], REGS32[ir.lhs]),
             },
             IRType::STORE64 => {
-                println!("\tmov [{}], {}", REGS[ir.lhs], REGS[ir.rhs]);
+                emit!(out, "\tmov [{}], {}", REGS[ir.lhs], REGS[ir.rhs]);
+            },
+            IRType::FIMM => {
+                emit!(out, "\tmov rax, {}", ir.rhs as i64);
+                emit!(out, "\tmovq {}, rax", XMMS[ir.lhs]);
+            },
+            IRType::FMOV => emit!(out, "\tmovapd {}, {}", XMMS[ir.lhs], XMMS[ir.rhs]),
+            IRType::FADD => emit!(out, "\taddsd {}, {}", XMMS[ir.lhs], XMMS[ir.rhs]),
+            IRType::FSUB => emit!(out, "\tsubsd {}, {}", XMMS[ir.lhs], XMMS[ir.rhs]),
+            IRType::FMUL => emit!(out, "\tmulsd {}, {}", XMMS[ir.lhs], XMMS[ir.rhs]),
+            IRType::FDIV => emit!(out, "\tdivsd {}, {}", XMMS[ir.lhs], XMMS[ir.rhs]),
+            // The comparison leaves a mask of all ones or zeros, which picks
+            // the bits of 1.0 or none.
+            IRType::FLT | IRType::FLE | IRType::FEQ | IRType::FNE => {
+                let insn = match ir.op {
+                    IRType::FLT => "cmpltsd",
+                    IRType::FLE => "cmplesd",
+                    IRType::FEQ => "cmpeqsd",
+                    _ => "cmpneqsd",
+                };
+                emit!(out, "\t{} {}, {}", insn, XMMS[ir.lhs], XMMS[ir.rhs]);
+                emit!(out, "\tmov rax, {}", ONE_BITS as i64);
+                emit!(out, "\tmovq xmm0, rax");
+                emit!(out, "\tandpd {}, xmm0", XMMS[ir.lhs]);
+            },
+            IRType::FNEG => {
+                emit!(out, "\tmov rax, {}", SIGN_BIT as i64);
+                emit!(out, "\tmovq xmm0, rax");
+                emit!(out, "\txorpd {}, xmm0", XMMS[ir.lhs]);
+            },
+            IRType::FROUND => {
+                emit!(out, "\tcvtsd2ss {}, {}", XMMS[ir.lhs], XMMS[ir.lhs]);
+                emit!(out, "\tcvtss2sd {}, {}", XMMS[ir.lhs], XMMS[ir.lhs]);
+            },
+            IRType::I2F => emit!(out, "\tcvtsi2sd {}, {}", XMMS[ir.lhs], REGS[ir.rhs]),
+            IRType::F2I => emit!(out, "\tcvttsd2si {}, {}", REGS[ir.lhs], XMMS[ir.rhs]),
+            IRType::FLOAD32 => emit!(out, "\tcvtss2sd {}, DWORD PTR [{}]", XMMS[ir.lhs], REGS[ir.rhs]),
+            IRType::FLOAD64 => emit!(out, "\tmovsd {}, QWORD PTR [{}]", XMMS[ir.lhs], REGS[ir.rhs]),
+            IRType::FSTORE32 => {
+                emit!(out, "\tcvtsd2ss xmm0, {}", XMMS[ir.rhs]);
+                emit!(out, "\tmovss DWORD PTR [{}], xmm0", REGS[ir.lhs]);
+            },
+            IRType::FSTORE64 => emit!(out, "\tmovsd QWORD PTR [{}], {}", REGS[ir.lhs], XMMS[ir.rhs]),
+            IRType::FSTORE32_ARG => emit!(out, "\tmovss DWORD PTR [rbp-{}], {}", ir.lhs, ARGXMM[ir.rhs]),
+            IRType::FSTORE64_ARG => emit!(out, "\tmovsd QWORD PTR [rbp-{}], {}", ir.lhs, ARGXMM[ir.rhs]),
+            IRType::FARG32 | IRType::FARG64 | IRType::SET_XMM => {
+                match ir.op {
+                    IRType::FARG32 => emit!(out, "\tcvtsd2ss {}, {}", ARGXMM[ir.rhs], XMMS[ir.lhs]),
+                    IRType::FARG64 => emit!(out, "\tmovapd {}, {}", ARGXMM[ir.rhs], XMMS[ir.lhs]),
+                    _ => emit!(out, "\tmovq {}, {}", ARGXMM[ir.rhs], REGS[ir.lhs]),
+                }
+                xmm_args = max(xmm_args, ir.rhs + 1);
+            },
+            IRType::GET_XMM => emit!(out, "\tmovq {}, {}", REGS[ir.lhs], ARGXMM[ir.rhs]),
+            IRType::FPUSH_ARG => {
+                emit!(out, "\tsub rsp, 8");
+                if ir.rhs == 4 {
+                    emit!(out, "\tcvtsd2ss xmm0, {}", XMMS[ir.lhs]);
+                    emit!(out, "\tmovss DWORD PTR [rsp], xmm0");
+                } else {
+                    emit!(out, "\tmovsd QWORD PTR [rsp], {}", XMMS[ir.lhs]);
+                }
             },
+            IRType::FRETURN => {
+                if ir.rhs == 4 {
+                    emit!(out, "\tcvtsd2ss xmm0, {}", XMMS[ir.lhs]);
+                } else {
+                    emit!(out, "\tmovapd xmm0, {}", XMMS[ir.lhs]);
+                }
+                emit!(out, "\tjmp {}", ret);
+            },
+            IRType::FRESULT => {
+                if ir.rhs == 4 {
+                    emit!(out, "\tcvtss2sd {}, xmm0", XMMS[ir.lhs]);
+                } else {
+                    emit!(out, "\tmovapd {}, xmm0", XMMS[ir.lhs]);
+                }
+            },
+            IRType::FLOAD_SPILL => emit!(out, "\tmovsd {}, QWORD PTR [rbp-{}]", XMMS[ir.lhs], ir.rhs),
+            IRType::FSTORE_SPILL => emit!(out, "\tmovsd QWORD PTR [rbp-{}], {}", ir.rhs, XMMS[ir.lhs]),
             IRType::NOP => {},
-            IRType::KILL => unreachable!("unexpected IRType KILL"),
+            IRType::KILL => error!(Internal, Span::default(), "unexpected IRType KILL"),
         }
     }
 
-    println!("{}:", ret);
-    println!("\tpop r15");
-    println!("\tpop r14");
-    println!("\tpop r13");
-    println!("\tpop r12");
-    println!("\tmov rsp, rbp");
-    println!("\tpop rbp");
-    println!("\tret");
-
+    emit!(out, "{}:", ret);
+    emit!(out, "\tpop r15");
+    emit!(out, "\tpop r14");
+    emit!(out, "\tpop r13");
+    emit!(out, "\tpop r12");
+    emit!(out, "\tpop rbx");
+    emit!(out, "\tmov rsp, rbp");
+    emit!(out, "\tpop rbp");
+    emit!(out, "\tret");
+    for line in target.function_end(&func.name) {
+        emit!(out, "{}", line);
+    }
+    if !tables.is_empty() {
+        emit!(out, "{}", target.const_section());
+        emit!(out, "\t.p2align 2");
+        for (table, targets) in tables {
+            emit!(out, "{}:", table);
+            for t in targets {
+                emit!(out, "\t.long .L{}-{}", t, table);
+            }
+        }
+        emit!(out, ".text");
+    }
+    return Ok(())
 }
 
-pub fn gen_x86(fns: Vec<Function>){
-    println!(".intel_syntax noprefix");
+pub fn gen_x86(fns: Vec<Function>, globals: Vec<GlobalVar>, target: Target) -> Result<String, CompileError> {
+    let mut out = String::new();
+    emit!(out, ".intel_syntax noprefix");
+    for var in globals {
+        gen_data(var, target, &mut out);
+    }
     let mut label = 0;
     for f in fns{
-        gen(f, label);
+        gen(f, label, target, &mut out)?;
         label += 1;
     }
+    for line in target.file_end() {
+        emit!(out, "{}", line);
+    }
+    return Ok(out)
 }
@@ -0,0 +1,46 @@
+extern crate rugcc;
+use self::rugcc::common::{Span, CompileError};
+
+// Formats a diagnostic as `file:line:col: error: msg`, followed by the
+// offending source line and a caret under the span.
+pub fn format_error(span: &Span, msg: &str) -> String {
+    let file = match span.file {
+        Some(ref file) => file,
+        None => return format!("rugcc: error: {}\n", msg),
+    };
+    let text = &file.text;
+    let offset = if span.offset > text.len() { text.len() } else { span.offset };
+    let start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let end = text[offset..].find('\n').map(|i| offset + i).unwrap_or(text.len());
+    let line = &text[start..end];
+
+    // Keep tabs in the indentation so the caret lines up with the source.
+    let mut marker: String = text[start..offset].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    marker.push('^');
+    let width = text[offset..offset + (end - offset).min(span.len)].chars().count();
+    for _i in 1..width {
+        marker.push('~');
+    }
+    return format!("{}:{}:{}: error: {}\n{}\n{}\n", file.name, span.line, span.col, msg, line, marker)
+}
+
+pub fn report(err: &CompileError) {
+    eprint!("{}", format_error(&err.span, &err.msg));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use self::rugcc::common::SourceFile;
+    use std::rc::Rc;
+
+    # [test]
+    fn can_format_error() {
+        let file = Rc::new(SourceFile{name: "foo.c".to_string(), text: "int main() {\n\treturn 1 +;\n}\n".to_string()});
+        let span = Span{file: Some(file), line: 2, col: 12, offset: 24, len: 1};
+
+        let result = format_error(&span, "number expected, but got ;");
+
+        assert_eq!(result, "foo.c:2:12: error: number expected, but got ;\n\treturn 1 +;\n\t          ^\n");
+    }
+}
@@ -0,0 +1,115 @@
+use assembler;
+use elf;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Preprocess,  // -E
+    Assembly,    // -S
+    Object,      // -c
+    Executable,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input<'a> {
+    Code(&'a str),    // -e
+    C(&'a str),
+    Asm(&'a str),
+    Linker(&'a str),  // objects and libraries, passed to the linker as they are
+}
+
+// Sorts an input file by extension, as cc does. Anything unknown is C.
+pub fn classify<'a>(path: &'a str) -> Input<'a> {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("s") => return Input::Asm(path),
+        Some("o") | Some("a") | Some("so") | Some("dylib") => return Input::Linker(path),
+        _ => return Input::C(path),
+    }
+}
+
+// `foo/bar.c` -> `bar.<ext>`, as cc does for `-S` and `-c`. Code given with
+// -e is named `a`.
+pub fn output_path(input: Input, ext: &str) -> String {
+    let path = match input {
+        Input::Code(_) => "a",
+        Input::C(path) | Input::Asm(path) | Input::Linker(path) => path,
+    };
+    let stem = Path::new(path).file_stem().unwrap().to_string_lossy();
+    return format!("{}.{}", stem, ext)
+}
+
+// A private directory for intermediate files, removed when dropped.
+pub struct TempDir {
+    dir: PathBuf,
+    count: usize,
+}
+
+impl TempDir {
+    pub fn new() -> Result<TempDir, String> {
+        let dir = env::temp_dir().join(format!("rugcc-{}", process::id()));
+        fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+        return Ok(TempDir{dir, count: 0})
+    }
+
+    pub fn file(&mut self, ext: &str) -> String {
+        self.count += 1;
+        return self.dir.join(format!("{}.{}", self.count, ext)).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn run(mut cmd: Command) -> Result<(), String> {
+    let name = cmd.get_program().to_string_lossy().into_owned();
+    match cmd.status() {
+        Ok(ref status) if status.success() => return Ok(()),
+        Ok(status) => return Err(format!("{} failed with {}", name, status)),
+        Err(e) => return Err(format!("cannot run {}: {}", name, e)),
+    }
+}
+
+pub fn assemble(asm: &str, obj: &str) -> Result<(), String> {
+    let mut cmd = Command::new("as");
+    cmd.arg("-o").arg(obj).arg(asm);
+    return run(cmd)
+}
+
+// Encodes codegen's output directly into an ELF object, without `as`.
+pub fn assemble_integrated(asm: &str, obj: &str) -> Result<(), String> {
+    let object = assembler::assemble(asm).map_err(|e| format!("internal assembler: {}", e))?;
+    return fs::write(obj, elf::write(&object)).map_err(|e| format!("cannot write {}: {}", obj, e))
+}
+
+// cc is used as the linker so it supplies the C runtime and libc.
+pub fn link(objs: &[String], lib_dirs: &[&str], libs: &[&str], out: &str) -> Result<(), String> {
+    let mut cmd = Command::new("cc");
+    cmd.arg("-o").arg(out).args(objs);
+    for dir in lib_dirs {
+        cmd.arg(format!("-L{}", dir));
+    }
+    for lib in libs {
+        cmd.arg(format!("-l{}", lib));
+    }
+    return run(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    # [test]
+    fn can_classify_inputs() {
+        assert_eq!(classify("foo/bar.c"), Input::C("foo/bar.c"));
+        assert_eq!(classify("-"), Input::C("-"));
+        assert_eq!(classify("bar.s"), Input::Asm("bar.s"));
+        assert_eq!(classify("libm.a"), Input::Linker("libm.a"));
+        assert_eq!(output_path(classify("foo/bar.c"), "o"), "bar.o");
+        assert_eq!(output_path(Input::Code("int main(){}"), "s"), "a.s");
+    }
+}
@@ -0,0 +1,219 @@
+use assembler::{Object, RelocKind, RelocTarget, SECTION_NAMES, BSS, TEXT, RODATA};
+use std::collections::HashMap;
+
+// Serializes an assembled Object as an ELF64 relocatable file for x86-64.
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+struct SectionHeader {
+    name: u32,
+    ty: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+// A NUL-separated string table. Offset 0 is the empty string.
+struct StrTab {
+    data: Vec<u8>,
+}
+
+impl StrTab {
+    fn new() -> StrTab {
+        return StrTab{data: vec![0]}
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+        return offset
+    }
+}
+
+fn sym(out: &mut Vec<u8>, name: u32, bind: u8, ty: u8, shndx: u16, value: u64, size: u64) {
+    out.extend_from_slice(&name.to_le_bytes());
+    out.push(bind << 4 | ty);
+    out.push(0);
+    out.extend_from_slice(&shndx.to_le_bytes());
+    out.extend_from_slice(&value.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes());
+}
+
+fn align(out: &mut Vec<u8>, n: usize) {
+    while out.len() % n != 0 {
+        out.push(0);
+    }
+}
+
+pub fn write(obj: &Object) -> Vec<u8> {
+    // Section header indices: 0 is null, then the content sections in
+    // Object order, then one .rela section for each that has relocations.
+    let shndx = |section: usize| (section + 1) as u16;
+
+    let mut strtab = StrTab::new();
+    let mut symtab = Vec::new();
+    let mut sym_index: HashMap<String, u32> = HashMap::new();
+    sym(&mut symtab, 0, STB_LOCAL, STT_NOTYPE, 0, 0, 0);
+    for i in 0..SECTION_NAMES.len() {
+        sym(&mut symtab, 0, STB_LOCAL, STT_SECTION, shndx(i), 0, 0);
+    }
+    let mut count = 1 + SECTION_NAMES.len() as u32;
+    let kind = |s: &::assembler::Symbol| if s.func { STT_FUNC } else if s.section == TEXT { STT_NOTYPE } else { STT_OBJECT };
+    for s in obj.symbols.iter().filter(|s| !s.global) {
+        let name = strtab.add(&s.name);
+        sym(&mut symtab, name, STB_LOCAL, kind(s), shndx(s.section), s.offset as u64, s.size as u64);
+        sym_index.insert(s.name.clone(), count);
+        count += 1;
+    }
+    let first_global = count;
+    for s in obj.symbols.iter().filter(|s| s.global) {
+        let name = strtab.add(&s.name);
+        sym(&mut symtab, name, STB_GLOBAL, kind(s), shndx(s.section), s.offset as u64, s.size as u64);
+        sym_index.insert(s.name.clone(), count);
+        count += 1;
+    }
+    // Anything referenced but not defined here is left to the linker.
+    for r in &obj.relocs {
+        if let RelocTarget::Symbol(ref name) = r.target {
+            if !sym_index.contains_key(name) {
+                let offset = strtab.add(name);
+                sym(&mut symtab, offset, STB_GLOBAL, STT_NOTYPE, 0, 0, 0);
+                sym_index.insert(name.clone(), count);
+                count += 1;
+            }
+        }
+    }
+
+    let mut relas: Vec<(usize, Vec<u8>)> = Vec::new();
+    for section in 0..SECTION_NAMES.len() {
+        let mut rela = Vec::new();
+        for r in obj.relocs.iter().filter(|r| r.section == section) {
+            let index = match r.target {
+                RelocTarget::Section(s) => 1 + s as u64,
+                RelocTarget::Symbol(ref name) => sym_index[name] as u64,
+            };
+            let ty: u64 = match r.kind { RelocKind::Abs64 => 1, RelocKind::Pc32 => 2, RelocKind::Plt32 => 4 };
+            rela.extend_from_slice(&(r.offset as u64).to_le_bytes());
+            rela.extend_from_slice(&(index << 32 | ty).to_le_bytes());
+            rela.extend_from_slice(&r.addend.to_le_bytes());
+        }
+        if !rela.is_empty() {
+            relas.push((section, rela));
+        }
+    }
+
+    let mut shstrtab = StrTab::new();
+    let mut headers = vec![SectionHeader{name: 0, ty: 0, flags: 0, offset: 0, size: 0, link: 0, info: 0, align: 0, entsize: 0}];
+    let mut out = vec![0; 64];
+    for (i, data) in obj.sections.iter().enumerate() {
+        let (ty, flags, alignment) = match i {
+            TEXT => (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 16),
+            RODATA => (SHT_PROGBITS, SHF_ALLOC, 16),
+            BSS => (SHT_NOBITS, SHF_ALLOC | SHF_WRITE, 16),
+            _ => (SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 16),
+        };
+        align(&mut out, alignment);
+        let offset = out.len() as u64;
+        if ty != SHT_NOBITS {
+            out.extend_from_slice(data);
+        }
+        headers.push(SectionHeader{name: shstrtab.add(SECTION_NAMES[i]), ty, flags, offset, size: data.len() as u64,
+            link: 0, info: 0, align: alignment as u64, entsize: 0});
+    }
+    let symtab_index = (headers.len() + relas.len()) as u32;
+    for (section, rela) in relas {
+        align(&mut out, 8);
+        let offset = out.len() as u64;
+        out.extend_from_slice(&rela);
+        headers.push(SectionHeader{name: shstrtab.add(&format!(".rela{}", SECTION_NAMES[section])), ty: SHT_RELA,
+            flags: SHF_INFO_LINK, offset, size: rela.len() as u64, link: symtab_index, info: shndx(section) as u32,
+            align: 8, entsize: 24});
+    }
+    align(&mut out, 8);
+    headers.push(SectionHeader{name: shstrtab.add(".symtab"), ty: SHT_SYMTAB, flags: 0, offset: out.len() as u64,
+        size: symtab.len() as u64, link: symtab_index + 1, info: first_global, align: 8, entsize: 24});
+    out.extend_from_slice(&symtab);
+    headers.push(SectionHeader{name: shstrtab.add(".strtab"), ty: SHT_STRTAB, flags: 0, offset: out.len() as u64,
+        size: strtab.data.len() as u64, link: 0, info: 0, align: 1, entsize: 0});
+    out.extend_from_slice(&strtab.data);
+    // An empty .note.GNU-stack marks the stack as non-executable.
+    headers.push(SectionHeader{name: shstrtab.add(".note.GNU-stack"), ty: SHT_PROGBITS, flags: 0, offset: out.len() as u64,
+        size: 0, link: 0, info: 0, align: 1, entsize: 0});
+    let shstrndx = headers.len() as u16;
+    let name = shstrtab.add(".shstrtab");
+    headers.push(SectionHeader{name, ty: SHT_STRTAB, flags: 0, offset: out.len() as u64,
+        size: shstrtab.data.len() as u64, link: 0, info: 0, align: 1, entsize: 0});
+    out.extend_from_slice(&shstrtab.data);
+
+    align(&mut out, 8);
+    let shoff = out.len() as u64;
+    for h in &headers {
+        out.extend_from_slice(&h.name.to_le_bytes());
+        out.extend_from_slice(&h.ty.to_le_bytes());
+        out.extend_from_slice(&h.flags.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&h.offset.to_le_bytes());
+        out.extend_from_slice(&h.size.to_le_bytes());
+        out.extend_from_slice(&h.link.to_le_bytes());
+        out.extend_from_slice(&h.info.to_le_bytes());
+        out.extend_from_slice(&h.align.to_le_bytes());
+        out.extend_from_slice(&h.entsize.to_le_bytes());
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(b"\x7fELF");
+    header.extend_from_slice(&[2, 1, 1, 0]);  // 64-bit, little endian, version 1, System V ABI
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&1u16.to_le_bytes());   // ET_REL
+    header.extend_from_slice(&62u16.to_le_bytes());  // EM_X86_64
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.extend_from_slice(&0u64.to_le_bytes());   // entry
+    header.extend_from_slice(&0u64.to_le_bytes());   // program headers
+    header.extend_from_slice(&shoff.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());   // flags
+    header.extend_from_slice(&64u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&64u16.to_le_bytes());
+    header.extend_from_slice(&(headers.len() as u16).to_le_bytes());
+    header.extend_from_slice(&shstrndx.to_le_bytes());
+    out[..64].copy_from_slice(&header);
+    return out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::assemble;
+    # [test]
+    fn can_write_elf_header() {
+        let out = write(&assemble(".text\n.global main\nmain:\n\tcall puts\n\tret\n").unwrap());
+        assert_eq!(&out[..4], b"\x7fELF");
+        assert_eq!(out[16], 1);  // ET_REL
+        assert_eq!(out[18], 62); // EM_X86_64
+        let shnum = u16::from_le_bytes([out[60], out[61]]);
+        // null, 4 content sections, .rela.text, .symtab, .strtab, .note.GNU-stack, .shstrtab
+        assert_eq!(shnum, 10);
+    }
+}
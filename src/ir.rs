@@ -1,15 +1,124 @@
 extern crate rugcc;
-use self::rugcc::common::{IR, ND, Node, IRType, Function, TY};
+use self::rugcc::common::{IR, ND, Node, IRType, Function, Type, TY, CompileError, align_to};
+use std::collections::HashMap;
+use std::cmp::min;
+
+// The labels of the cases of a switch being generated.
+struct Switch {
+    cases: HashMap<i64, usize>,
+    default: usize,
+    has_default: bool,
+}
+
+// A switch gets a jump table when it has at least this many cases and they
+// fill at least a third of the range between the smallest and the largest.
+const JMP_TABLE_MIN_CASES: usize = 4;
+
+// Integer arguments go in rdi, rsi, rdx, rcx, r8 and r9, floating ones in
+// xmm0 to xmm7, and the rest on the stack.
+const ARG_REGS: usize = 6;
+const FLOAT_ARG_REGS: usize = 8;
+
+// System V passes and returns a struct of up to two eightbytes in as many
+// registers, and anything bigger in memory, for which this is 0.
+fn struct_regs(ty: &Type) -> usize {
+    let size = ty.size_of();
+    if size > 16 { return 0 }
+    return size.div_ceil(8)
+}
+
+// Whether each eightbyte of a struct passed in registers is of the SSE
+// class, which it is when only floats and doubles overlap it, rather than
+// INTEGER. A struct passed in memory has none.
+fn struct_classes(ty: &Type) -> Vec<bool> {
+    let mut sse = vec![true; struct_regs(ty)];
+    if !sse.is_empty() {
+        classify(ty, 0, &mut sse);
+    }
+    return sse
+}
+
+fn classify(ty: &Type, offset: usize, sse: &mut [bool]) {
+    match ty.ty {
+        TY::STRUCT => for m in ty.members.iter().flatten() {
+            classify(&m.ty, offset + m.offset, sse);
+        },
+        TY::ARY => {
+            let elem = ty.ary_of.as_ref().unwrap();
+            for i in 0..ty.len {
+                classify(elem, offset + i * elem.size_of(), sse);
+            }
+        },
+        _ if ty.is_flonum() => {},
+        _ => for word in &mut sse[offset / 8..=(offset + ty.size_of() - 1) / 8] {
+            *word = false;
+        },
+    }
+}
 
 pub struct IrGenerator {
     code: Vec<IR>,
     regno: usize,
     label: usize,
+    // The break targets of the enclosing loops and switches, and the
+    // continue targets of the enclosing loops, innermost last.
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+    switches: Vec<Switch>,
+    // goto labels of the current function, numbered on first use.
+    labels: HashMap<String, usize>,
+    // The return type of the current function, and where the caller's
+    // address for a struct returned in memory is kept.
+    ret: Type,
+    ret_ptr: usize,
+}
+
+// The IR instruction for a binary operator that maps onto one directly.
+// Division, comparison and right shift depend on whether the operands are
+// unsigned, which pointers are.
+fn binop_ir(op: &ND, ty: &Type) -> Option<IRType> {
+    if ty.is_flonum() {
+        match *op {
+            ND::OPE('+') => return Some(IRType::FADD),
+            ND::OPE('-') => return Some(IRType::FSUB),
+            ND::OPE('*') => return Some(IRType::FMUL),
+            ND::OPE('/') => return Some(IRType::FDIV),
+            ND::OPE('<') => return Some(IRType::FLT),
+            ND::LE => return Some(IRType::FLE),
+            ND::EQ => return Some(IRType::FEQ),
+            ND::NE => return Some(IRType::FNE),
+            _ => return None,
+        }
+    }
+    let unsigned = ty.is_unsigned || ty.ty == TY::PTR;
+    match *op {
+        ND::OPE('/') if unsigned => return Some(IRType::UDIV),
+        ND::OPE('%') if unsigned => return Some(IRType::UMOD),
+        ND::OPE('<') if unsigned => return Some(IRType::ULT),
+        ND::LE if unsigned => return Some(IRType::ULE),
+        ND::SHR if unsigned => return Some(IRType::USHR),
+        ND::OPE('+') => return Some(IRType::ADD),
+        ND::OPE('-') => return Some(IRType::SUB),
+        ND::OPE('*') => return Some(IRType::MUL),
+        ND::OPE('/') => return Some(IRType::DIV),
+        ND::OPE('%') => return Some(IRType::MOD),
+        ND::OPE('&') => return Some(IRType::AND),
+        ND::OPE('|') => return Some(IRType::OR),
+        ND::OPE('^') => return Some(IRType::XOR),
+        ND::OPE('<') => return Some(IRType::LT),
+        ND::LE => return Some(IRType::LE),
+        ND::EQ => return Some(IRType::EQ),
+        ND::NE => return Some(IRType::NE),
+        ND::SHL => return Some(IRType::SHL),
+        ND::SHR => return Some(IRType::SHR),
+        _ => return None,
+    }
 }
 
 impl IrGenerator {
     pub fn new() -> IrGenerator {
-        return IrGenerator{code: Vec::new(), regno: 1, label: 0}
+        return IrGenerator{code: Vec::new(), regno: 1, label: 0, breaks: Vec::new(), continues: Vec::new(),
+                           switches: Vec::new(), labels: HashMap::new(), ret: Type::default(), ret_ptr: 0}
     }
 
     fn add(&mut self, op: IRType, lhs: usize, rhs: usize) {
@@ -20,238 +129,997 @@ impl IrGenerator {
 
     fn label(&mut self, x: usize) { self.add(IRType::LABEL, x, 0); }
 
-    fn gen_lval(&mut self, node: Node) -> usize {
+    fn new_label(&mut self) -> usize {
+        let x = self.label;
+        self.label += 1;
+        return x
+    }
+
+    fn goto_label(&mut self, name: &str) -> usize {
+        if let Some(&x) = self.labels.get(name) {
+            return x
+        }
+        let x = self.new_label();
+        self.labels.insert(name.to_string(), x);
+        return x
+    }
+
+    fn new_reg(&mut self) -> usize {
+        let r = self.regno;
+        self.regno += 1;
+        return r
+    }
+
+    // Registers hold integers extended to 64 bits as their type says, so
+    // narrow values are loaded with a sign or zero extension. A struct value
+    // is the address of the struct, so it is not loaded, and storing one
+    // copies it.
+    fn load(&mut self, ty: &Type, dst: usize, src: usize) {
+        let op = match (ty.size_of(), ty.is_unsigned || ty.ty == TY::BOOL) {
+            (4, _) if ty.ty == TY::FLOAT => IRType::FLOAD32,
+            _ if ty.ty == TY::DOUBLE => IRType::FLOAD64,
+            _ if ty.ty == TY::STRUCT => {
+                if dst != src { self.add(IRType::MOV, dst, src) }
+                return
+            },
+            _ if ty.ty == TY::ARY => IRType::LOAD64,
+            (1, false) => IRType::SLOAD8,
+            (1, true) => IRType::LOAD8,
+            (2, false) => IRType::SLOAD16,
+            (2, true) => IRType::LOAD16,
+            (4, false) => IRType::SLOAD32,
+            (4, true) => IRType::LOAD32,
+            _ => IRType::LOAD64,
+        };
+        self.add(op, dst, src);
+    }
+
+    // Loads the value of type ty at addr. A float goes into a register of its
+    // own, since addr is in a general-purpose one.
+    fn load_value(&mut self, ty: &Type, addr: usize) -> usize {
+        if !ty.is_flonum() {
+            self.load(ty, addr, addr);
+            return addr
+        }
+        let r = self.new_reg();
+        self.load(ty, r, addr);
+        self.kill(addr);
+        return r
+    }
+
+    fn store(&mut self, ty: &Type, dst: usize, src: usize) {
+        match ty.ty {
+            TY::STRUCT => self.copy(ty.size_of(), dst, src),
+            TY::FLOAT => self.add(IRType::FSTORE32, dst, src),
+            TY::DOUBLE => self.add(IRType::FSTORE64, dst, src),
+            TY::ARY => self.add(IRType::STORE64, dst, src),
+            _ => match ty.size_of() {
+                1 => self.add(IRType::STORE8, dst, src),
+                2 => self.add(IRType::STORE16, dst, src),
+                4 => self.add(IRType::STORE32, dst, src),
+                _ => self.add(IRType::STORE64, dst, src),
+            },
+        }
+    }
+
+    // Extends the low bytes of r holding a value of the integer type ty to
+    // the whole register.
+    fn extend(&mut self, ty: &Type, r: usize) {
+        let size = ty.size_of();
+        if size >= 8 || ty.ty == TY::STRUCT {
+            return
+        }
+        let op = if ty.is_unsigned || ty.ty == TY::BOOL { IRType::ZEXT } else { IRType::SEXT };
+        self.add(op, r, size);
+    }
+
+    // Converts the value in r from one scalar type to another, and gives the
+    // register holding the result. Widening keeps the value, which r already
+    // holds extended, unless it turns a negative number unsigned.
+    fn cast(&mut self, from: &Type, to: &Type, r: usize) -> usize {
+        if from.is_flonum() || to.is_flonum() {
+            return self.cast_float(from, to, r)
+        }
+        if to.ty == TY::BOOL {
+            let zero = self.new_reg();
+            self.add(IRType::IMM, zero, 0);
+            self.add(IRType::NE, r, zero);
+            self.kill(zero);
+            return r
+        }
+        let widening = from.ty != TY::PTR && from.size_of() < to.size_of();
+        if widening && (from.is_unsigned || from.ty == TY::BOOL || !to.is_unsigned) {
+            return r
+        }
+        self.extend(to, r);
+        return r
+    }
+
+    // Conversions to and from floating types move the value between the
+    // integer and xmm registers. The hardware converts signed 64-bit integers
+    // only, so unsigned longs of 2^63 and up take a detour.
+    fn cast_float(&mut self, from: &Type, to: &Type, r: usize) -> usize {
+        let u64_type = |ty: &Type| ty.is_unsigned && ty.size_of() == 8;
+        if from.is_flonum() && to.is_flonum() {
+            if to.ty == TY::FLOAT { self.add(IRType::FROUND, r, 0) }
+            return r
+        }
+        if to.is_flonum() {
+            let f = self.new_reg();
+            if u64_type(from) {
+                self.unsigned_to_float(f, r);
+            } else {
+                self.add(IRType::I2F, f, r);
+            }
+            self.kill(r);
+            if to.ty == TY::FLOAT { self.add(IRType::FROUND, f, 0) }
+            return f
+        }
+        let i = self.new_reg();
+        if to.ty == TY::BOOL {
+            let zero = self.new_reg();
+            self.add(IRType::FIMM, zero, 0);
+            self.add(IRType::FNE, r, zero);
+            self.kill(zero);
+            self.add(IRType::F2I, i, r);
+        } else if u64_type(to) {
+            self.float_to_unsigned(i, r);
+        } else {
+            self.add(IRType::F2I, i, r);
+            self.extend(to, i);
+        }
+        self.kill(r);
+        return i
+    }
+
+    // f = (double)r for an unsigned long r. One with the top bit set is
+    // halved first, keeping its low bit so that it rounds the same, and
+    // doubled again once converted.
+    fn unsigned_to_float(&mut self, f: usize, r: usize) {
+        let small = self.new_label();
+        let done = self.new_label();
+        let neg = self.new_reg();
+        let zero = self.new_reg();
+        self.add(IRType::MOV, neg, r);
+        self.add(IRType::IMM, zero, 0);
+        self.add(IRType::LT, neg, zero);
+        self.kill(zero);
+        self.add(IRType::UNLESS, neg, small);
+        self.kill(neg);
+        let half = self.new_reg();
+        let one = self.new_reg();
+        self.add(IRType::MOV, half, r);
+        self.add(IRType::IMM, one, 1);
+        self.add(IRType::USHR, half, one);
+        self.add(IRType::AND, one, r);
+        self.add(IRType::OR, half, one);
+        self.kill(one);
+        self.add(IRType::I2F, f, half);
+        self.kill(half);
+        self.add(IRType::FADD, f, f);
+        self.add(IRType::JMP, done, 0);
+        self.label(small);
+        self.add(IRType::I2F, f, r);
+        self.label(done);
+    }
+
+    // i = (unsigned long)r. Values of 2^63 and up are brought below it
+    // before the conversion, and the top bit is put back after.
+    fn float_to_unsigned(&mut self, i: usize, r: usize) {
+        let big = self.new_label();
+        let done = self.new_label();
+        let limit = self.new_reg();
+        self.add(IRType::FIMM, limit, ((1u64 << 63) as f64).to_bits() as usize);
+        let t = self.new_reg();
+        let below = self.new_reg();
+        self.add(IRType::FMOV, t, r);
+        self.add(IRType::FLT, t, limit);
+        self.add(IRType::F2I, below, t);
+        self.kill(t);
+        self.add(IRType::UNLESS, below, big);
+        self.kill(below);
+        self.add(IRType::F2I, i, r);
+        self.add(IRType::JMP, done, 0);
+        self.label(big);
+        self.add(IRType::FSUB, r, limit);
+        self.add(IRType::F2I, i, r);
+        let top = self.new_reg();
+        self.add(IRType::IMM, top, 1 << 63);
+        self.add(IRType::XOR, i, top);
+        self.kill(top);
+        self.label(done);
+        self.kill(limit);
+    }
+
+    // A new register holding base + off.
+    fn offset_addr(&mut self, base: usize, off: usize) -> usize {
+        let r = self.new_reg();
+        self.add(IRType::MOV, r, base);
+        if off > 0 {
+            let t = self.new_reg();
+            self.add(IRType::IMM, t, off);
+            self.add(IRType::ADD, r, t);
+            self.kill(t);
+        }
+        return r
+    }
+
+    // The address of a variable at rbp - offset.
+    fn frame_addr(&mut self, offset: usize) -> usize {
+        let r = self.new_reg();
+        self.add(IRType::MOV, r, 0);
+        self.add(IRType::SUB_IMM, r, offset);
+        return r
+    }
+
+    fn copy(&mut self, size: usize, dst: usize, src: usize) {
+        let mut off = 0;
+        while off < size {
+            let (n, load, store) = match size - off {
+                8..=usize::MAX => (8, IRType::LOAD64, IRType::STORE64),
+                4..=7 => (4, IRType::LOAD32, IRType::STORE32),
+                _ => (1, IRType::LOAD8, IRType::STORE8),
+            };
+            let s = self.offset_addr(src, off);
+            self.add(load, s, s);
+            let d = self.offset_addr(dst, off);
+            self.add(store, d, s);
+            self.kill(d);
+            self.kill(s);
+            off += n;
+        }
+    }
+
+    // Reads the struct at addr as the eightbytes it is passed in, without
+    // touching the bytes past its end.
+    fn load_words(&mut self, addr: usize, size: usize) -> Vec<usize> {
+        let mut words = Vec::new();
+        for w in 0..size.div_ceil(8) {
+            let n = min(8, size - 8 * w);
+            let r = self.offset_addr(addr, 8 * w);
+            if n == 8 {
+                self.add(IRType::LOAD64, r, r);
+                words.push(r);
+                continue
+            }
+            // A short tail is put together from the top byte down, with the
+            // low four bytes read at once when there are that many.
+            let val = self.new_reg();
+            self.add(IRType::IMM, val, 0);
+            let mut i = n;
+            while i > 0 {
+                let (c, load) = if i == 4 { (4, IRType::LOAD32) } else { (1, IRType::LOAD8) };
+                i -= c;
+                let p = self.offset_addr(r, i);
+                self.add(load, p, p);
+                let sh = self.new_reg();
+                self.add(IRType::IMM, sh, 8 * c);
+                self.add(IRType::SHL, val, sh);
+                self.kill(sh);
+                self.add(IRType::OR, val, p);
+                self.kill(p);
+            }
+            self.kill(r);
+            words.push(val);
+        }
+        return words
+    }
+
+    // The reverse of load_words. The registers are clobbered.
+    fn store_words(&mut self, addr: usize, words: &[usize], size: usize) {
+        for (w, &r) in words.iter().enumerate() {
+            let n = min(8, size - 8 * w);
+            let mut i = 0;
+            while i < n {
+                let (c, store) = match n - i {
+                    8 => (8, IRType::STORE64),
+                    4..=7 => (4, IRType::STORE32),
+                    _ => (1, IRType::STORE8),
+                };
+                let p = self.offset_addr(addr, 8 * w + i);
+                self.add(store, p, r);
+                self.kill(p);
+                i += c;
+                if i < n {
+                    let sh = self.new_reg();
+                    self.add(IRType::IMM, sh, 8 * c);
+                    self.add(IRType::USHR, r, sh);
+                    self.kill(sh);
+                }
+            }
+        }
+    }
+
+    fn gen_lval(&mut self, node: Node) -> Result<usize, CompileError> {
         match node.op {
             ND::DEREF => return self.gen_expr(*node.expr.unwrap()),
+            // The struct, evaluated to its address, plus the member's offset.
+            ND::DOT => {
+                let r = self.gen_expr(*node.expr.unwrap())?;
+                if node.offset > 0 {
+                    let t = self.new_reg();
+                    self.add(IRType::IMM, t, node.offset);
+                    self.add(IRType::ADD, r, t);
+                    self.kill(t);
+                }
+                return Ok(r)
+            },
             ND::LVAR => {
                 let r = self.regno;
                 self.regno += 1;
 
                 self.add(IRType::MOV, r, 0);
                 self.add(IRType::SUB_IMM, r, node.offset);
-                return r
+                return Ok(r)
             },
             ND::GVAR => {
                 let r = self.regno;
                 self.regno += 1;
                 self.code.push(IR{op: IRType::LABEL_ADDR, lhs: r, rhs: 0, name: node.val, ..Default::default() });
-                return r
+                return Ok(r)
             },
-            _ => unreachable!("unexpected node type:{:?}", node.op),
+            _ => error!(Internal, node.span, "unexpected node type:{:?}", node.op),
         }
     }
 
-    fn gen_binop(&mut self, ty: IRType, lhs: Node, rhs: Node) -> usize {
-        let r1 = self.gen_expr(lhs);
-        let r2 = self.gen_expr(rhs);
-        self.add(ty, r1, r2);
+    // Results that can leave the range of their type, as `INT_MAX + 1` or
+    // `~0u` do in 64 bits, are extended again from their low bytes, and
+    // float results are rounded. A floating comparison leaves 1.0 or 0.0,
+    // which is turned into an integer.
+    fn gen_binop(&mut self, op: IRType, ty: &Type, lhs: Node, rhs: Node) -> Result<usize, CompileError> {
+        let r1 = self.gen_expr(lhs)?;
+        let r2 = self.gen_expr(rhs)?;
+        self.add(op, r1, r2);
         self.kill(r2);
-        return r1;
+        match op {
+            IRType::ADD | IRType::SUB | IRType::MUL | IRType::DIV | IRType::SHL => self.extend(ty, r1),
+            IRType::FADD | IRType::FSUB | IRType::FMUL | IRType::FDIV if ty.ty == TY::FLOAT => self.add(IRType::FROUND, r1, 0),
+            IRType::FLT | IRType::FLE | IRType::FEQ | IRType::FNE => {
+                let r = self.new_reg();
+                self.add(IRType::F2I, r, r1);
+                self.kill(r1);
+                return Ok(r)
+            },
+            _ => {},
+        }
+        return Ok(r1)
+    }
+
+    // `lhs op= rhs` evaluates the address of lhs only once. Postfix forms
+    // give back the value lhs held before the update.
+    fn gen_assign_op(&mut self, op: IRType, lhs: Node, rhs: Node, post: bool) -> Result<usize, CompileError> {
+        let ty = lhs.ty.clone();
+        let op_ty = rhs.ty.clone();
+        let addr = self.gen_lval(lhs)?;
+        let val = self.regno;
+        self.regno += 1;
+        self.load(&ty, val, addr);
+        let r = self.gen_expr(rhs)?;
+        if ty.ty == TY::PTR {
+            let size = self.regno;
+            self.regno += 1;
+            self.add(IRType::IMM, size, ty.ptr_of.clone().unwrap().size_of());
+            self.add(IRType::MUL, r, size);
+            self.kill(size);
+        }
+        let old = self.regno;
+        if post {
+            self.regno += 1;
+            self.add(if ty.is_flonum() { IRType::FMOV } else { IRType::MOV }, old, val);
+        }
+        // The operation is done in the type of rhs, and the result converted
+        // back, so that it is the value as stored.
+        let mut val = val;
+        if ty.ty != TY::PTR && (ty.ty != op_ty.ty || ty.is_unsigned != op_ty.is_unsigned) {
+            val = self.cast(&ty, &op_ty, val);
+        }
+        self.add(op, val, r);
+        self.kill(r);
+        if ty.ty != TY::PTR {
+            val = self.cast(&op_ty, &ty, val);
+        }
+        self.store(&ty, addr, val);
+        self.kill(addr);
+        if post {
+            self.kill(val);
+            return Ok(old)
+        }
+        return Ok(val)
     }
 
-    fn gen_expr(&mut self, node: Node) -> usize {
+    fn gen_expr(&mut self, node: Node) -> Result<usize, CompileError> {
         match node.op {
             ND::NUM => {
                 let r = self.regno;
                 self.regno += 1;
-                self.add(IRType::IMM, r, node.val.parse::<usize>().unwrap());
-                return r
-            },
-            ND::LVAR | ND::GVAR => {
-                let r = self.gen_lval(node.clone());
-                match node.ty.ty {
-                    TY::CHAR => self.add(IRType::LOAD8, r, r),
-                    TY::INT => self.add(IRType::LOAD32, r, r),
-                    TY::PTR | TY::ARY => self.add(IRType::LOAD64, r, r),
+                if node.ty.is_flonum() {
+                    self.add(IRType::FIMM, r, node.fnum.to_bits() as usize);
+                } else {
+                    self.add(IRType::IMM, r, node.num as usize);
                 }
-                return r
+                return Ok(r)
+            },
+            ND::LVAR | ND::GVAR | ND::DOT => {
+                let r = self.gen_lval(node.clone())?;
+                return Ok(self.load_value(&node.ty, r))
             },
             ND::LOGAND => {
-                let x = self.label;
-                self.label += 1;
-                let r1 = self.gen_expr(*node.lhs.unwrap());
+                let x = self.new_label();
+                let r1 = self.gen_expr(*node.lhs.unwrap())?;
                 self.add(IRType::UNLESS, r1, x);
-                let r2 = self.gen_expr(*node.rhs.unwrap());
+                let r2 = self.gen_expr(*node.rhs.unwrap())?;
                 self.add(IRType::MOV, r1, r2);
                 self.kill(r2);
                 self.add(IRType::UNLESS, r1, x);
                 self.add(IRType::IMM, r1, 1);
                 self.label(x);
-                return r1
+                return Ok(r1)
             },
             ND::LOGOR => {
-                let x = self.label;
-                self.label += 1;
-                let y = self.label;
-                self.label += 1;
+                let x = self.new_label();
+                let y = self.new_label();
 
-                let r1 = self.gen_expr(*node.lhs.unwrap());
+                let r1 = self.gen_expr(*node.lhs.unwrap())?;
                 self.add(IRType::UNLESS, r1, x);
                 self.add(IRType::IMM, r1, 1);
                 self.add(IRType::JMP, y, 0);
                 self.label(x);
 
-                let r2 = self.gen_expr(*node.rhs.unwrap());
+                let r2 = self.gen_expr(*node.rhs.unwrap())?;
                 self.add(IRType::MOV, r1, r2);
                 self.kill(r2);
                 self.add(IRType::UNLESS, r1, y);
                 self.add(IRType::IMM, r1, 1);
                 self.label(y);
-                return r1;
+                return Ok(r1)
+            },
+            ND::TERNARY => {
+                let x = self.new_label();
+                let y = self.new_label();
+
+                let r = self.gen_expr(*node.cond.unwrap())?;
+                self.add(IRType::UNLESS, r, x);
+                // The condition is an integer, so a float result needs a
+                // register of its own.
+                let (r, mov) = if node.ty.is_flonum() {
+                    self.kill(r);
+                    (self.new_reg(), IRType::FMOV)
+                } else {
+                    (r, IRType::MOV)
+                };
+                let r2 = self.gen_expr(*node.then.unwrap())?;
+                self.add(mov, r, r2);
+                self.kill(r2);
+                self.add(IRType::JMP, y, 0);
+                self.label(x);
+
+                let r3 = self.gen_expr(*node.els.unwrap())?;
+                self.add(mov, r, r3);
+                self.kill(r3);
+                self.label(y);
+                return Ok(r)
+            },
+            ND::COMMA => {
+                let r = self.gen_expr(*node.lhs.unwrap())?;
+                self.kill(r);
+                return self.gen_expr(*node.rhs.unwrap())
             },
             ND::CALL => {
                 let mut args = Vec::new();
                 for n in node.args {
-                    args.push(self.gen_expr(n));
+                    let ty = n.ty.clone();
+                    args.push((ty, self.gen_expr(n)?));
                 }
-                let r = self.regno;
-                self.regno += 1;
-
-                let ir = IR { op: IRType::CALL, lhs: r, rhs: 0, name: node.val, args, ..Default::default() };
-                self.code.push(ir.clone());
-                for i in ir.args {
-                    self.kill(i);
+                // A struct returned in memory goes where the hidden first
+                // argument points.
+                let ret_classes = if node.ty.ty == TY::STRUCT { Some(struct_classes(&node.ty)) } else { None };
+                let in_memory = ret_classes.as_ref().is_some_and(|c| c.is_empty());
+                let mut nreg = if in_memory { 1 } else { 0 };
+                let mut nxmm = 0;
+                // Each argument gets its registers while enough of both kinds
+                // are left, and goes on the stack otherwise. Struct arguments
+                // are split into eightbytes, and those of the SSE class go in
+                // xmm registers. Stack slots are pushed by the IR that fills
+                // them, along with the size of a float.
+                let mut regs = Vec::new();
+                let mut xmms = Vec::new();
+                let mut stack = Vec::new();
+                for (ty, r) in args {
+                    if ty.is_flonum() {
+                        if nxmm < FLOAT_ARG_REGS {
+                            let op = if ty.ty == TY::FLOAT { IRType::FARG32 } else { IRType::FARG64 };
+                            xmms.push((op, r));
+                            nxmm += 1;
+                        } else {
+                            stack.push((IRType::FPUSH_ARG, r, ty.size_of()));
+                        }
+                        continue
+                    }
+                    if ty.ty != TY::STRUCT {
+                        if nreg < ARG_REGS {
+                            regs.push(r);
+                            nreg += 1;
+                        } else {
+                            stack.push((IRType::PUSH_ARG, r, 0));
+                        }
+                        continue
+                    }
+                    let classes = struct_classes(&ty);
+                    let n = classes.len();
+                    let sse = classes.iter().filter(|&&c| c).count();
+                    let words = self.load_words(r, ty.size_of());
+                    self.kill(r);
+                    if n > 0 && nreg + n - sse <= ARG_REGS && nxmm + sse <= FLOAT_ARG_REGS {
+                        for (w, is_sse) in words.into_iter().zip(classes) {
+                            if is_sse {
+                                xmms.push((IRType::SET_XMM, w));
+                            } else {
+                                regs.push(w);
+                            }
+                        }
+                        nreg += n - sse;
+                        nxmm += sse;
+                    } else {
+                        stack.extend(words.into_iter().map(|w| (IRType::PUSH_ARG, w, 0)));
+                    }
+                }
+                // Arguments are moved into place only once all of them have
+                // been evaluated, since evaluating one may call a function.
+                if !stack.is_empty() {
+                    self.add(IRType::STACK_ARGS, stack.len(), 0);
+                    for (op, r, size) in stack.into_iter().rev() {
+                        self.add(op, r, size);
+                        self.kill(r);
+                    }
+                }
+                let first = if in_memory {
+                    let buf = self.frame_addr(node.offset);
+                    self.add(IRType::ARG, buf, 0);
+                    self.kill(buf);
+                    1
+                } else {
+                    0
+                };
+                for (i, arg) in regs.into_iter().enumerate() {
+                    self.add(IRType::ARG, arg, first + i);
+                    self.kill(arg);
+                }
+                for (i, (op, arg)) in xmms.into_iter().enumerate() {
+                    self.add(op, arg, i);
+                    self.kill(arg);
+                }
+                let r = self.new_reg();
+                self.code.push(IR { op: IRType::CALL, lhs: r, rhs: nreg, name: node.val, ..Default::default() });
+                if node.ty.is_flonum() {
+                    let f = self.new_reg();
+                    self.add(IRType::FRESULT, f, node.ty.size_of());
+                    self.kill(r);
+                    return Ok(f)
+                }
+                match ret_classes {
+                    // Spill the registers into the caller's temporary. INTEGER
+                    // eightbytes come back in rax and rdx, SSE ones in xmm0
+                    // and xmm1.
+                    Some(ref classes) if !classes.is_empty() => {
+                        let mut words = Vec::new();
+                        let (mut nreg, mut nxmm) = (0, 0);
+                        for &is_sse in classes {
+                            if !is_sse && nreg == 0 {
+                                words.push(r);
+                                nreg += 1;
+                                continue
+                            }
+                            let d = self.new_reg();
+                            if is_sse {
+                                self.add(IRType::GET_XMM, d, nxmm);
+                                nxmm += 1;
+                            } else {
+                                self.add(IRType::GET_RDX, d, 0);
+                            }
+                            words.push(d);
+                        }
+                        if nreg == 0 { self.kill(r) }
+                        let buf = self.frame_addr(node.offset);
+                        self.store_words(buf, &words, node.ty.size_of());
+                        for w in words {
+                            self.kill(w);
+                        }
+                        return Ok(buf)
+                    },
+                    // rax holds the address of the returned struct, or the
+                    // value, of which only the bytes of its type are defined.
+                    _ => {
+                        self.extend(&node.ty, r);
+                        return Ok(r)
+                    },
                 }
-                return r
             },
             ND::ADDR => return self.gen_lval(*node.expr.unwrap()),
             ND::DEREF => {
-                let r = self.gen_expr(*node.expr.clone().unwrap());
-                match node.expr.unwrap().ty.ty {
-                    TY::CHAR => self.add(IRType::LOAD8, r, r),
-                    TY::INT => self.add(IRType::LOAD32, r, r),
-                    TY::PTR | TY::ARY => self.add(IRType::LOAD64, r, r),
-                }
-                return r
+                let r = self.gen_expr(*node.expr.unwrap())?;
+                return Ok(self.load_value(&node.ty, r))
             },
             ND::OPE('=') => {
-                let rhs = self.gen_expr(*node.rhs.unwrap());
-                let lhs = self.gen_lval(*node.lhs.clone().unwrap());
-                match node.ty.ty {
-                    TY::CHAR => self.add(IRType::STORE8, lhs, rhs),
-                    TY::INT => self.add(IRType::STORE32, lhs, rhs),
-                    TY::PTR | TY::ARY =>  self.add(IRType::STORE64, lhs, rhs),
-                }
-                self.kill(rhs);
-                return lhs
+                let rhs = self.gen_expr(*node.rhs.unwrap())?;
+                let lhs = self.gen_lval(*node.lhs.clone().unwrap())?;
+                self.store(&node.ty, lhs, rhs);
+                self.kill(lhs);
+                return Ok(rhs)
+            },
+            ND::ASSIGN_OP(ref op) => {
+                let op = binop_ir(op, &node.rhs.as_ref().unwrap().ty).unwrap();
+                return self.gen_assign_op(op, *node.lhs.clone().unwrap(), *node.rhs.clone().unwrap(), false)
             },
-            ND::OPE('<') => {
-                return self.gen_binop(IRType::LT, *node.lhs.unwrap(), *node.rhs.unwrap())
+            ND::POST_INC | ND::POST_DEC => {
+                let expr = *node.expr.unwrap();
+                let ty = if expr.ty.ty == TY::PTR { Type::default() } else { expr.ty.clone() };
+                let ope = if node.op == ND::POST_INC { ND::OPE('+') } else { ND::OPE('-') };
+                let op = binop_ir(&ope, &ty).unwrap();
+                let one = Node{op: ND::NUM, num: 1, fnum: 1.0, ty, ..Default::default()};
+                return self.gen_assign_op(op, expr, one, true)
+            },
+            ND::CAST => {
+                let expr = *node.expr.unwrap();
+                let from = expr.ty.clone();
+                let r = self.gen_expr(expr)?;
+                return Ok(self.cast(&from, &node.ty, r))
+            },
+            ND::NEG if node.ty.is_flonum() => {
+                let r = self.gen_expr(*node.expr.unwrap())?;
+                self.add(IRType::FNEG, r, 0);
+                return Ok(r)
+            },
+            ND::NEG => {
+                let r = self.gen_expr(*node.expr.unwrap())?;
+                self.add(IRType::NEG, r, 0);
+                self.extend(&node.ty, r);
+                return Ok(r)
+            },
+            // `!x` is `x == 0` and `~x` is `x ^ -1`.
+            ND::NOT | ND::BITNOT => {
+                let r = self.gen_expr(*node.expr.unwrap())?;
+                let imm = self.regno;
+                self.regno += 1;
+                if node.op == ND::NOT {
+                    self.add(IRType::IMM, imm, 0);
+                    self.add(IRType::EQ, r, imm);
+                } else {
+                    self.add(IRType::IMM, imm, -1i64 as usize);
+                    self.add(IRType::XOR, r, imm);
+                    self.extend(&node.ty, r);
+                }
+                self.kill(imm);
+                return Ok(r)
             },
             ND::OPE('+') | ND::OPE('-') =>{
-                let insn = if node.op == ND::OPE('+') { IRType::ADD } else { IRType::SUB };
+                let insn = binop_ir(&node.op, &node.ty).unwrap();
                 if node.lhs.clone().unwrap().ty.ty != TY::PTR {
-                    return self.gen_binop(insn, *node.lhs.unwrap(), *node.rhs.unwrap())
+                    return self.gen_binop(insn, &node.ty, *node.lhs.unwrap(), *node.rhs.unwrap())
                 }
-                let rhs = self.gen_expr(*node.rhs.unwrap());
+                let rhs = self.gen_expr(*node.rhs.unwrap())?;
                 let r = self.regno;
                 self.regno += 1;
                 self.add(IRType::IMM, r, node.lhs.clone().unwrap().ty.ptr_of.unwrap().size_of());
                 self.add(IRType::MUL, rhs, r);
                 self.kill(r);
-                let lhs = self.gen_expr(*node.lhs.unwrap());
+                let lhs = self.gen_expr(*node.lhs.unwrap())?;
                 self.add(insn, lhs, rhs);
                 self.kill(rhs);
-                return lhs
+                return Ok(lhs)
+            },
+            // Comparisons go by the type of their operands, not of their result.
+            _ if binop_ir(&node.op, &node.ty).is_some() => {
+                let op = binop_ir(&node.op, &node.lhs.as_ref().unwrap().ty).unwrap();
+                return self.gen_binop(op, &node.ty, *node.lhs.unwrap(), *node.rhs.unwrap())
             },
-            ND::OPE('*') => return self.gen_binop(IRType::MUL, *node.lhs.unwrap(), *node.rhs.unwrap()),
-            ND::OPE('/') => return self.gen_binop(IRType::DIV, *node.lhs.unwrap(), *node.rhs.unwrap()),
-            _ => unreachable!("unexpected node type:{:?}", node.op),
+            _ => error!(Internal, node.span, "unexpected node type:{:?}", node.op),
         }
     }
 
-    fn gen_stmt(&mut self, node: Node) {
+    // Jumps to the case matching the value in r: through a jump table when
+    // the case values are dense, and by comparing with each one otherwise.
+    fn gen_dispatch(&mut self, r: usize, cases: &[i64], sw: &Switch) {
+        let (min, max) = match (cases.iter().min(), cases.iter().max()) {
+            (Some(&min), Some(&max)) => (min, max),
+            _ => {
+                self.add(IRType::JMP, sw.default, 0);
+                return
+            },
+        };
+        let range = (max as i128 - min as i128 + 1) as u128;
+        if cases.len() >= JMP_TABLE_MIN_CASES && range <= cases.len() as u128 * 3 {
+            if min != 0 {
+                let t = self.regno;
+                self.regno += 1;
+                self.add(IRType::IMM, t, min as usize);
+                self.add(IRType::SUB, r, t);
+                self.kill(t);
+            }
+            let targets = (0..range as i64).map(|i| *sw.cases.get(&(min + i)).unwrap_or(&sw.default)).collect();
+            self.code.push(IR{op: IRType::JMP_TABLE, lhs: r, rhs: sw.default, targets, ..Default::default()});
+            return
+        }
+        for val in cases {
+            let t = self.regno;
+            let c = self.regno + 1;
+            self.regno += 2;
+            self.add(IRType::MOV, t, r);
+            self.add(IRType::IMM, c, *val as usize);
+            self.add(IRType::NE, t, c);
+            self.kill(c);
+            self.add(IRType::UNLESS, t, sw.cases[val]);
+            self.kill(t);
+        }
+        self.add(IRType::JMP, sw.default, 0);
+    }
+
+    fn gen_stmt(&mut self, node: Node) -> Result<(), CompileError> {
         match node.op {
             ND::VARDEF => {
-                if node.init.is_none() { return }
+                if node.init.is_none() { return Ok(()) }
 
-                let rhs = self.gen_expr(*node.init.unwrap());
+                let rhs = self.gen_expr(*node.init.unwrap())?;
                 let lhs = self.regno;
                 self.regno += 1;
                 self.add(IRType::MOV, lhs, 0);
                 self.add(IRType::SUB_IMM, lhs, node.offset);
-                if node.ty.ty == TY::PTR {
-                    self.add(IRType::STORE64, lhs, rhs);
-                } else {
-                    self.add(IRType::STORE32, lhs, rhs);
-                }                self.kill(lhs);
+                self.store(&node.ty, lhs, rhs);
+                self.kill(lhs);
                 self.kill(rhs);
             },
             ND::IF => {
-                let x = self.label;
-                self.label += 1;
+                let x = self.new_label();
                 if node.els.is_some() {
-                    let y = self.label;
-                    self.label += 1;
-                    let r = self.gen_expr(*node.cond.unwrap());
+                    let y = self.new_label();
+                    let r = self.gen_expr(*node.cond.unwrap())?;
                     self.add(IRType::UNLESS, r, x);
                     self.kill(r);
-                    self.gen_stmt(*node.then.unwrap());
+                    self.gen_stmt(*node.then.unwrap())?;
                     self.add(IRType::JMP, y, 0);
                     self.label(x);
-                    self.gen_stmt(*node.els.unwrap());
+                    self.gen_stmt(*node.els.unwrap())?;
                     self.label(y);
                 } else {
-                    let r = self.gen_expr(*node.cond.unwrap());
+                    let r = self.gen_expr(*node.cond.unwrap())?;
                     self.add(IRType::UNLESS, r, x);
                     self.kill(r);
-                    self.gen_stmt(*node.then.unwrap());
+                    self.gen_stmt(*node.then.unwrap())?;
                     self.label(x);
                 }
             },
             ND::FOR => {
-                let x = self.label;
-                self.label += 1;
-                let y = self.label;
-                self.label += 1;
-                self.gen_stmt(*node.init.unwrap());
+                let x = self.new_label();
+                let y = self.new_label();
+                let z = self.new_label();
+                if let Some(init) = node.init { self.gen_stmt(*init)?; }
                 self.label(x);
-                let r2 = self.gen_expr(*node.cond.unwrap());
-                self.add(IRType::UNLESS, r2, y);
-                self.kill(r2);
-                self.gen_stmt(*node.body.unwrap());
-                let r3 = self.gen_expr(*node.inc.unwrap());
-                self.kill(r3);
+                if let Some(cond) = node.cond {
+                    let r2 = self.gen_expr(*cond)?;
+                    self.add(IRType::UNLESS, r2, y);
+                    self.kill(r2);
+                }
+                self.breaks.push(y);
+                self.continues.push(z);
+                self.gen_stmt(*node.body.unwrap())?;
+                self.breaks.pop();
+                self.continues.pop();
+                self.label(z);
+                if let Some(inc) = node.inc {
+                    let r3 = self.gen_expr(*inc)?;
+                    self.kill(r3);
+                }
+                self.add(IRType::JMP, x, 0);
+                self.label(y);
+            },
+            ND::DO_WHILE => {
+                let x = self.new_label();
+                let y = self.new_label();
+                let z = self.new_label();
+                self.label(x);
+                self.breaks.push(y);
+                self.continues.push(z);
+                self.gen_stmt(*node.body.unwrap())?;
+                self.breaks.pop();
+                self.continues.pop();
+                self.label(z);
+                let r = self.gen_expr(*node.cond.unwrap())?;
+                self.add(IRType::UNLESS, r, y);
+                self.kill(r);
                 self.add(IRType::JMP, x, 0);
                 self.label(y);
             },
+            ND::BREAK => {
+                let y = *self.breaks.last().unwrap();
+                self.add(IRType::JMP, y, 0);
+            },
+            ND::CONTINUE => {
+                let z = *self.continues.last().unwrap();
+                self.add(IRType::JMP, z, 0);
+            },
+            ND::SWITCH => {
+                let y = self.new_label();
+                let mut sw = Switch{cases: HashMap::new(), default: self.new_label(), has_default: false};
+                for &val in &node.cases {
+                    let x = self.new_label();
+                    sw.cases.insert(val, x);
+                }
+                let r = self.gen_expr(*node.cond.unwrap())?;
+                self.gen_dispatch(r, &node.cases, &sw);
+                self.kill(r);
+
+                self.breaks.push(y);
+                self.switches.push(sw);
+                self.gen_stmt(*node.body.unwrap())?;
+                let sw = self.switches.pop().unwrap();
+                self.breaks.pop();
+                // Without a default, a value matching no case leaves the switch.
+                if !sw.has_default { self.label(sw.default); }
+                self.label(y);
+            },
+            ND::CASE => {
+                let x = self.switches.last().unwrap().cases[&node.num];
+                self.label(x);
+                self.gen_stmt(*node.body.unwrap())?;
+            },
+            ND::DEFAULT => {
+                let x = {
+                    let sw = self.switches.last_mut().unwrap();
+                    sw.has_default = true;
+                    sw.default
+                };
+                self.label(x);
+                self.gen_stmt(*node.body.unwrap())?;
+            },
+            ND::GOTO => {
+                let x = self.goto_label(&node.val);
+                self.add(IRType::JMP, x, 0);
+            },
+            ND::LABEL => {
+                let x = self.goto_label(&node.val);
+                self.label(x);
+                self.gen_stmt(*node.body.unwrap())?;
+            },
             ND::RETURN => {
-                let r = self.gen_expr(*node.expr.unwrap());
-                self.add(IRType::RETURN, r, 0);
+                let r = self.gen_expr(*node.expr.unwrap())?;
+                if self.ret.is_flonum() {
+                    self.add(IRType::FRETURN, r, self.ret.size_of());
+                    self.kill(r);
+                    return Ok(())
+                }
+                if self.ret.ty != TY::STRUCT {
+                    self.add(IRType::RETURN, r, 0);
+                    self.kill(r);
+                    return Ok(())
+                }
+                let size = self.ret.size_of();
+                if struct_regs(&self.ret) == 0 {
+                    // Copy to the caller's buffer, and hand its address back.
+                    let buf = self.frame_addr(self.ret_ptr);
+                    self.add(IRType::LOAD64, buf, buf);
+                    self.copy(size, buf, r);
+                    self.kill(r);
+                    self.add(IRType::RETURN, buf, 0);
+                    self.kill(buf);
+                    return Ok(())
+                }
+                let words = self.load_words(r, size);
                 self.kill(r);
+                let mut ints = Vec::new();
+                let mut nxmm = 0;
+                for (&w, is_sse) in words.iter().zip(struct_classes(&self.ret)) {
+                    if is_sse {
+                        self.add(IRType::SET_XMM, w, nxmm);
+                        nxmm += 1;
+                    } else {
+                        ints.push(w);
+                    }
+                }
+                if ints.len() == 2 {
+                    self.add(IRType::SET_RDX, ints[1], 0);
+                }
+                // rax is left as it is when every eightbyte went in xmm.
+                self.add(IRType::RETURN, *ints.first().unwrap_or(&words[0]), 0);
+                for w in words {
+                    self.kill(w);
+                }
             },
             ND::EXPR_STMT => {
-                let r = self.gen_expr(*node.expr.unwrap());
+                let r = self.gen_expr(*node.expr.unwrap())?;
                 self.kill(r);
             },
             ND::COMP_STMT => {
                 for n in node.stmts {
-                    self.gen_stmt(n);
+                    self.gen_stmt(n)?;
                 }
             },
-            _ => unreachable!("unknown node: {:?}", node.op)
+            _ => error!(Internal, node.span, "unknown node: {:?}", node.op)
+        }
+        return Ok(())
+    }
+
+    // Stores the parameters into their slots, assigning registers and stack
+    // the way the caller did.
+    fn gen_params(&mut self, node: &Node) {
+        let mut nreg = 0;
+        if self.ret.ty == TY::STRUCT && struct_regs(&self.ret) == 0 {
+            self.add(IRType::STORE64_ARG, self.ret_ptr, 0);
+            nreg = 1;
+        }
+        let mut nxmm = 0;
+        let mut in_memory = Vec::new();
+        for arg in &node.args {
+            let classes = match arg.ty.ty {
+                TY::STRUCT => struct_classes(&arg.ty),
+                _ => vec![arg.ty.is_flonum()],
+            };
+            let sse = classes.iter().filter(|&&c| c).count();
+            if classes.is_empty() || nreg + classes.len() - sse > ARG_REGS || nxmm + sse > FLOAT_ARG_REGS {
+                in_memory.push(arg);
+                continue
+            }
+            match arg.ty.ty {
+                // Struct slots are whole eightbytes, so each one fits.
+                TY::STRUCT => for (w, is_sse) in classes.into_iter().enumerate() {
+                    if is_sse {
+                        self.add(IRType::FSTORE64_ARG, arg.offset - 8 * w, nxmm);
+                        nxmm += 1;
+                    } else {
+                        self.add(IRType::STORE64_ARG, arg.offset - 8 * w, nreg);
+                        nreg += 1;
+                    }
+                },
+                TY::FLOAT => {
+                    self.add(IRType::FSTORE32_ARG, arg.offset, nxmm);
+                    nxmm += 1;
+                },
+                TY::DOUBLE => {
+                    self.add(IRType::FSTORE64_ARG, arg.offset, nxmm);
+                    nxmm += 1;
+                },
+                _ => {
+                    match arg.ty.size_of() {
+                        1 => self.add(IRType::STORE8_ARG, arg.offset, nreg),
+                        2 => self.add(IRType::STORE16_ARG, arg.offset, nreg),
+                        4 => self.add(IRType::STORE32_ARG, arg.offset, nreg),
+                        _ => self.add(IRType::STORE64_ARG, arg.offset, nreg),
+                    }
+                    nreg += 1;
+                },
+            }
+        }
+        // The rest sit above the return address, in eightbytes. They are
+        // copied only once the argument registers have been saved.
+        let mut stack = 16;
+        for arg in in_memory {
+            let src = self.offset_addr(0, stack);
+            let dst = self.frame_addr(arg.offset);
+            if arg.ty.ty == TY::STRUCT || arg.ty.is_flonum() {
+                self.copy(arg.ty.size_of(), dst, src);
+            } else {
+                self.add(IRType::LOAD64, src, src);
+                self.store(&arg.ty, dst, src);
+            }
+            self.kill(dst);
+            self.kill(src);
+            stack += align_to(arg.ty.size_of(), 8);
         }
     }
 
-    pub fn gen_ir(&mut self, nodes: Vec<Node>) -> Vec<Function> {
+    pub fn gen_ir(&mut self, nodes: Vec<Node>) -> Result<Vec<Function>, CompileError> {
         let mut funcs = Vec::new();
         for node in nodes {
-            assert!(node.op ==ND::FUNC);
+            if node.op != ND::FUNC { error!(Internal, node.span, "function expected, but got {:?}", node.op) }
             self.code= Vec::new();
             self.regno = 1;
+            self.labels = HashMap::new();
             let name = node.val.clone();
-            for i in 0..node.args.len() {
-                let arg = node.args[i].clone();
-                match arg.ty.ty {
-                    TY::CHAR => self.add(IRType::STORE8_ARG, arg.offset, i),
-                    TY::INT => self.add(IRType::STORE32_ARG, arg.offset, i),
-                    TY::PTR | TY::ARY => self.add(IRType::STORE64_ARG, arg.offset, i),
-                }
-            }
-            self.gen_stmt(*node.body.unwrap());
+            self.ret = node.ty.clone();
+            self.ret_ptr = node.offset;
+            self.gen_params(&node);
+            self.gen_stmt(*node.body.unwrap())?;
             funcs.push(Function{name, irs: self.code.clone(), stack_size: node.stack_size, strings: node.strings, ..Default::default()})
         }
-        return funcs
+        return Ok(funcs)
     }
 }
 
@@ -259,7 +1127,126 @@ impl IrGenerator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use self::rugcc::common::{Type};
+    use self::rugcc::common::{Type, SourceFile};
+    use token::tokenize;
+    use node::parse;
+    use sema::SemaGenerator;
+    use std::rc::Rc;
+
+    fn gen(src: &str) -> Vec<IR> {
+        let mut tokens = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+        let nodes = SemaGenerator::new().sema(parse(&mut tokens).unwrap()).unwrap();
+        return IrGenerator::new().gen_ir(nodes).unwrap().remove(0).irs
+    }
+
+    # [test]
+    fn can_pass_structs_by_value() {
+        let count = |irs: &[IR], op: IRType| irs.iter().filter(|ir| ir.op == op).count();
+        // 12 bytes go in two registers, and come back in rax and rdx.
+        let small = gen("struct S { int a; int b; int c; }; struct S f(struct S s); int main() { struct S s; f(s); return 0; }");
+        assert_eq!((count(&small, IRType::ARG), count(&small, IRType::GET_RDX), count(&small, IRType::STACK_ARGS)), (2, 1, 0));
+        // 24 bytes go on the stack, and come back through a pointer passed first.
+        let big = gen("struct B { int a[6]; }; struct B f(int x, struct B b); int main() { struct B b; f(1, b); return 0; }");
+        let stack: Vec<&IR> = big.iter().filter(|ir| ir.op == IRType::STACK_ARGS).collect();
+        assert_eq!(stack[0].lhs, 3);
+        assert_eq!(count(&big, IRType::PUSH_ARG), 3);
+        let args: Vec<usize> = big.iter().filter(|ir| ir.op == IRType::ARG).map(|ir| ir.rhs).collect();
+        assert_eq!(args, [0, 1]);
+        assert_eq!(count(&big, IRType::GET_RDX), 0);
+        // Once the six registers are taken, a struct that would fit goes to the stack whole.
+        let full = gen("struct S { int a; int b; int c; }; int f(int a, int b, int c, int d, int e, struct S s); int main() { struct S s; return f(1, 2, 3, 4, 5, s); }");
+        assert_eq!((count(&full, IRType::ARG), count(&full, IRType::PUSH_ARG)), (5, 2));
+
+        let ret = gen("struct S { int a; int b; int c; }; struct S f() { struct S s; return s; }");
+        assert_eq!(count(&ret, IRType::STORE64_ARG), 0);
+        assert_eq!(count(&ret, IRType::SET_RDX), 1);
+        let params = gen("struct S { int a; int b; int c; }; struct B { int a[6]; }; struct B f(struct S s, struct B b) { return b; }");
+        // The hidden pointer and both eightbytes of s come in registers; b is copied from the caller's frame.
+        let stored: Vec<(usize, usize)> = params.iter().filter(|ir| ir.op == IRType::STORE64_ARG).map(|ir| (ir.lhs, ir.rhs)).collect();
+        assert_eq!(stored, [(8, 0), (24, 1), (16, 2)]);
+    }
+
+    # [test]
+    fn can_pass_floats_in_xmm_registers() {
+        let count = |irs: &[IR], op: IRType| irs.iter().filter(|ir| ir.op == op).count();
+        let nodes = |src: &str| {
+            let mut tokens = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+            return SemaGenerator::new().sema(parse(&mut tokens).unwrap()).unwrap()
+        };
+        let ty = |src: &str| nodes(src)[0].args[0].ty.clone();
+        assert_eq!(struct_classes(&ty("struct D { double a; double b; }; int f(struct D s) { return 0; }")), [true, true]);
+        assert_eq!(struct_classes(&ty("struct F { float a; int b; double c; }; int f(struct F s) { return 0; }")), [false, true]);
+        assert_eq!(struct_classes(&ty("struct A { float a[3]; }; int f(struct A s) { return 0; }")), [true, true]);
+        assert_eq!(struct_classes(&ty("union U { double d; long l; }; int f(union U s) { return 0; }")), [false]);
+        assert!(struct_classes(&ty("struct B { double a[3]; }; int f(struct B s) { return 0; }")).is_empty());
+
+        // Nine floating arguments fill xmm0 to xmm7 and spill one to the
+        // stack, while the int still gets rdi. A float parameter is passed
+        // as one.
+        let call = gen("double f(double a, double b, double c, double d, double e, double f, double g, float h, ...); \
+                        int main() { float x; return f(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, x, 9.0, 1); }");
+        assert_eq!((count(&call, IRType::FARG64), count(&call, IRType::FARG32), count(&call, IRType::FPUSH_ARG)), (7, 1, 1));
+        assert_eq!((count(&call, IRType::ARG), count(&call, IRType::FRESULT), count(&call, IRType::F2I)), (1, 1, 1));
+        let farg: Vec<usize> = call.iter().filter(|ir| ir.op == IRType::FARG32).map(|ir| ir.rhs).collect();
+        assert_eq!(farg, [7]);
+
+        // A struct of doubles goes in two xmm registers and comes back in two.
+        let s = gen("struct D { double a; double b; }; struct D f(struct D s); int main() { struct D s; f(s); return 0; }");
+        assert_eq!((count(&s, IRType::SET_XMM), count(&s, IRType::GET_XMM), count(&s, IRType::ARG)), (2, 2, 0));
+        let params = gen("struct M { long l; double d; }; double f(struct M m, float x) { return x; }");
+        let stored: Vec<(IRType, usize, usize)> = params.iter().filter(|ir| ir.op == IRType::STORE64_ARG || ir.op == IRType::FSTORE64_ARG ||
+            ir.op == IRType::FSTORE32_ARG).map(|ir| (ir.op, ir.lhs, ir.rhs)).collect();
+        assert_eq!(stored, [(IRType::STORE64_ARG, 16, 0), (IRType::FSTORE64_ARG, 8, 0), (IRType::FSTORE32_ARG, 20, 1)]);
+        assert_eq!(count(&params, IRType::FRETURN), 1);
+    }
+
+    # [test]
+    fn can_extend_by_signedness() {
+        let ops = |src: &str| gen(src).into_iter().map(|ir| ir.op).collect::<Vec<IRType>>();
+        let loads = ops("int main() { char a; unsigned char b; short c; unsigned short d; int e; unsigned f; long g; \
+                         return a + b + c + d + e + f + g; }");
+        let loads: Vec<IRType> = loads.into_iter().filter(|op| format!("{:?}", op).contains("LOAD")).collect();
+        assert_eq!(loads, [IRType::SLOAD8, IRType::LOAD8, IRType::SLOAD16, IRType::LOAD16, IRType::SLOAD32, IRType::LOAD32, IRType::LOAD64]);
+        let stores = ops("int main() { short s = 1; _Bool b = 1; return 0; }");
+        assert!(stores.contains(&IRType::STORE16) && stores.contains(&IRType::STORE8));
+
+        // Division, comparison and right shift go by the signedness of their operands.
+        let signed = ops("int main() { int a; int b; return a / b + a % b + (a < b) + (a <= b); }");
+        assert!([IRType::DIV, IRType::MOD, IRType::LT, IRType::LE].iter().all(|op| signed.contains(op)));
+        let unsigned = ops("int main() { unsigned a; int b; int *p; return a / b + a % b + (a < b) + (p <= p); }");
+        assert!([IRType::UDIV, IRType::UMOD, IRType::ULT, IRType::ULE].iter().all(|op| unsigned.contains(op)));
+        assert!(!unsigned.contains(&IRType::DIV) && !unsigned.contains(&IRType::LT));
+        assert!(ops("int main() { int a; return a >> 1; }").contains(&IRType::SHR));
+        assert!(ops("int main() { unsigned a; return a >> 1; }").contains(&IRType::USHR));
+
+        // int to unsigned zero-extends; char to int keeps the value as loaded.
+        let casts = gen("int main() { int i; char c; unsigned u = i; long l = u; return c; }");
+        let ext: Vec<(IRType, usize)> = casts.iter().filter(|ir| ir.op == IRType::SEXT || ir.op == IRType::ZEXT)
+            .map(|ir| (ir.op, ir.rhs)).collect();
+        assert_eq!(ext, [(IRType::ZEXT, 4)]);
+        // Int arithmetic is extended again, since it is done in 64 bits.
+        let add = gen("int main() { int a; return a + 1; }");
+        let i = add.iter().position(|ir| ir.op == IRType::ADD).unwrap();
+        assert_eq!((add[i + 2].op, add[i + 2].rhs), (IRType::SEXT, 4));
+    }
+
+    # [test]
+    fn can_choose_switch_lowering() {
+        let dense = gen("int main() { int x; switch (x) { case 2: x=1; case 3: x=2; case 5: x=3; case 6: x=4; } return x; }");
+        let table: Vec<&IR> = dense.iter().filter(|ir| ir.op == IRType::JMP_TABLE).collect();
+        assert_eq!(table.len(), 1);
+        // Cases 2..=6 with 4 missing, which goes to the end of the switch like other values.
+        let t = &table[0].targets;
+        assert_eq!(t.len(), 5);
+        assert_eq!(t[2], table[0].rhs);
+        assert!(t[0] != t[1] && t[1] != t[3] && t[3] != t[4] && t[0] != table[0].rhs);
+        assert!(dense.iter().all(|ir| ir.op != IRType::UNLESS));
+
+        let sparse = gen("int main() { int x; switch (x) { case 1: x=1; case 100: x=2; case 1000: x=3; case 7: x=4; } return x; }");
+        assert!(sparse.iter().all(|ir| ir.op != IRType::JMP_TABLE));
+        assert_eq!(sparse.iter().filter(|ir| ir.op == IRType::UNLESS).count(), 4);
+    }
+
     # [test]
     fn can_gen_ir_arithmetic_expr() {
         let input = [
@@ -279,24 +1266,24 @@ mod tests {
                                         op: ND::OPE('+'),
                                         lhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "2".to_string(), ..Default::default() })),
+                                            num: 2, ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::OPE('*'),
                                             lhs: Some(Box::new(Node {
                                                 op: ND::NUM,
-                                                val: "2".to_string(), ..Default::default()})),
+                                                num: 2, ..Default::default()})),
                                             rhs: Some(Box::new(Node {
                                                 op: ND::NUM,
-                                                val: "3".to_string(), ..Default::default()})),
+                                                num: 3, ..Default::default()})),
                                             ..Default::default()})),
                                         ..Default::default()})),
                                     rhs: Some(Box::new(Node {
                                         op: ND::NUM,
-                                        val: "2".to_string(), ..Default::default()
+                                        num: 2, ..Default::default()
                                     })),  ..Default::default()})),
                                 rhs: Some(Box::new(Node {
                                     op: ND::NUM,
-                                    val: "1".to_string(), ..Default::default() })),
+                                    num: 1, ..Default::default() })),
                                 ..Default::default()
                             })),
                             ..Default::default()
@@ -304,7 +1291,7 @@ mod tests {
                     ..Default::default() })),
                 ..Default::default()}];
 
-        let result = IrGenerator::new().gen_ir(input.to_vec());
+        let result = IrGenerator::new().gen_ir(input.to_vec()).unwrap();
 
         let expect = [
             Function {
@@ -315,14 +1302,18 @@ mod tests {
                     IR { op: IRType::IMM, lhs: 3, rhs: 3, ..Default::default() },
                     IR { op: IRType::MUL, lhs: 2, rhs: 3, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 3, ..Default::default() },
+                    IR { op: IRType::SEXT, lhs: 2, rhs: 4, ..Default::default() },
                     IR { op: IRType::ADD, lhs: 1, rhs: 2, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 2, ..Default::default() },
+                    IR { op: IRType::SEXT, lhs: 1, rhs: 4, ..Default::default() },
                     IR { op: IRType::IMM, lhs: 4, rhs: 2, ..Default::default() },
                     IR { op: IRType::DIV, lhs: 1, rhs: 4, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 4, ..Default::default() },
+                    IR { op: IRType::SEXT, lhs: 1, rhs: 4, ..Default::default() },
                     IR { op: IRType::IMM, lhs: 5, rhs: 1, ..Default::default() },
                     IR { op: IRType::SUB, lhs: 1, rhs: 5, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 5, ..Default::default() },
+                    IR { op: IRType::SEXT, lhs: 1, rhs: 4, ..Default::default() },
                     IR { op: IRType::RETURN, lhs: 1, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 1, ..Default::default() }].to_vec(),
                 stack_size: 0,
@@ -372,8 +1363,8 @@ mod tests {
                                 op: ND::CALL,
                                 val: "add".to_string(),
                                 args: [
-                                    Node { op: ND::NUM, val: "1".to_string(), ..Default::default()},
-                                    Node { op: ND::NUM, val: "2".to_string(), ..Default::default() }
+                                    Node { op: ND::NUM, num: 1, ..Default::default()},
+                                    Node { op: ND::NUM, num: 2, ..Default::default() }
                                 ].to_vec(), ..Default::default() })),
                             ..Default::default() }
                     ].to_vec(),
@@ -381,7 +1372,7 @@ mod tests {
                 ..Default::default()
             }];
 
-        let result = IrGenerator::new().gen_ir(input.to_vec());
+        let result = IrGenerator::new().gen_ir(input.to_vec()).unwrap();
 
         let expect = [
             Function {
@@ -391,12 +1382,13 @@ mod tests {
                     IR { op: IRType::STORE32_ARG, lhs: 8, rhs: 1, ..Default::default() },
                     IR { op: IRType::MOV, lhs: 1, rhs: 0, ..Default::default() },
                     IR { op: IRType::SUB_IMM, lhs: 1, rhs: 4, ..Default::default() },
-                    IR { op: IRType::LOAD32, lhs: 1, rhs: 1, ..Default::default() },
+                    IR { op: IRType::SLOAD32, lhs: 1, rhs: 1, ..Default::default() },
                     IR { op: IRType::MOV, lhs: 2, rhs: 0, ..Default::default() },
                     IR { op: IRType::SUB_IMM, lhs: 2, rhs: 8, ..Default::default() },
-                    IR { op: IRType::LOAD32, lhs: 2, rhs: 2, ..Default::default() },
+                    IR { op: IRType::SLOAD32, lhs: 2, rhs: 2, ..Default::default() },
                     IR { op: IRType::ADD, lhs: 1, rhs: 2, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 2, rhs: 0, ..Default::default() },
+                    IR { op: IRType::SEXT, lhs: 1, rhs: 4, ..Default::default() },
                     IR { op: IRType::RETURN, lhs: 1, rhs: 0, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 1, rhs: 0, ..Default::default() }].to_vec(),
                 stack_size: 8,
@@ -406,9 +1398,12 @@ mod tests {
                 irs: [
                     IR { op: IRType::IMM, lhs: 1, rhs: 1, ..Default::default() },
                     IR { op: IRType::IMM, lhs: 2, rhs: 2, ..Default::default() },
-                    IR { op: IRType::CALL, lhs: 3, rhs: 0, name: "add".to_string(), args: [1, 2].to_vec() },
+                    IR { op: IRType::ARG, lhs: 1, rhs: 0, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 1, rhs: 0, ..Default::default() },
+                    IR { op: IRType::ARG, lhs: 2, rhs: 1, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 2, rhs: 0, ..Default::default() },
+                    IR { op: IRType::CALL, lhs: 3, rhs: 2, name: "add".to_string(), ..Default::default() },
+                    IR { op: IRType::SEXT, lhs: 3, rhs: 4, ..Default::default() },
                     IR { op: IRType::RETURN, lhs: 3, rhs: 0, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 3, rhs: 0, ..Default::default() }].to_vec(),
                 stack_size: 0,
@@ -452,7 +1447,7 @@ mod tests {
                                     ..Default::default() })),
                                 rhs: Some(Box::new(Node {
                                     op: ND::NUM,
-                                    val: "3".to_string(), ..Default::default() })), ..Default::default()})),
+                                    num: 3, ..Default::default() })), ..Default::default()})),
                             ..Default::default()},
                         Node {
                             op: ND::EXPR_STMT,
@@ -473,11 +1468,11 @@ mod tests {
                                             ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "1".to_string(),
+                                            num: 1,
                                             ..Default::default()})),
                                         ..Default::default()})),
                                     ..Default::default()})),
-                                rhs: Some(Box::new(Node { op: ND::NUM, val: "7".to_string(), ..Default::default() })),
+                                rhs: Some(Box::new(Node { op: ND::NUM, num: 7, ..Default::default() })),
                                 ..Default::default()})),
                             ..Default::default()},
                         Node {
@@ -510,7 +1505,7 @@ mod tests {
                                             ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "1".to_string(), ..Default::default() })),
+                                            num: 1, ..Default::default() })),
                                         ..Default::default() })),
                                     ..Default::default() })),
                                 ..Default::default() })),
@@ -520,7 +1515,7 @@ mod tests {
                 ..Default::default() }
         ];
 
-        let result = IrGenerator::new().gen_ir(input.to_vec());
+        let result = IrGenerator::new().gen_ir(input.to_vec()).unwrap();
 
         let expect = [
             Function { name: "main".to_string(),
@@ -529,8 +1524,8 @@ mod tests {
                     IR { op: IRType::MOV, lhs: 2, rhs: 0, ..Default::default() },
                     IR { op: IRType::SUB_IMM, lhs: 2, rhs: 8, ..Default::default() },
                     IR { op: IRType::STORE32, lhs: 2, rhs: 1, ..Default::default() },
-                    IR { op: IRType::KILL, lhs: 1, rhs: 0, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 2, rhs: 0, ..Default::default() },
+                    IR { op: IRType::KILL, lhs: 1, rhs: 0, ..Default::default() },
                     IR { op: IRType::IMM, lhs: 3, rhs: 7, ..Default::default() },
                     IR { op: IRType::IMM, lhs: 4, rhs: 1, ..Default::default() },
                     IR { op: IRType::IMM, lhs: 5, rhs: 4, ..Default::default() },
@@ -541,11 +1536,11 @@ mod tests {
                     IR { op: IRType::ADD, lhs: 6, rhs: 4, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 4, rhs: 0, ..Default::default() },
                     IR { op: IRType::STORE32, lhs: 6, rhs: 3, ..Default::default() },
-                    IR { op: IRType::KILL, lhs: 3, rhs: 0, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 6, rhs: 0, ..Default::default() },
+                    IR { op: IRType::KILL, lhs: 3, rhs: 0, ..Default::default() },
                     IR { op: IRType::MOV, lhs: 7, rhs: 0, ..Default::default() },
                     IR { op: IRType::SUB_IMM, lhs: 7, rhs: 8, ..Default::default() },
-                    IR { op: IRType::LOAD64, lhs: 7, rhs: 7, ..Default::default() },
+                    IR { op: IRType::SLOAD32, lhs: 7, rhs: 7, ..Default::default() },
                     IR { op: IRType::IMM, lhs: 8, rhs: 1, ..Default::default() },
                     IR { op: IRType::IMM, lhs: 9, rhs: 4, ..Default::default() },
                     IR { op: IRType::MUL, lhs: 8, rhs: 9, ..Default::default() },
@@ -554,9 +1549,10 @@ mod tests {
                     IR { op: IRType::SUB_IMM, lhs: 10, rhs: 8, ..Default::default() },
                     IR { op: IRType::ADD, lhs: 10, rhs: 8, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 8, rhs: 0, ..Default::default() },
-                    IR { op: IRType::LOAD64, lhs: 10, rhs: 10, ..Default::default() },
+                    IR { op: IRType::SLOAD32, lhs: 10, rhs: 10, ..Default::default() },
                     IR { op: IRType::ADD, lhs: 7, rhs: 10, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 10, rhs: 0, ..Default::default() },
+                    IR { op: IRType::SEXT, lhs: 7, rhs: 4, ..Default::default() },
                     IR { op: IRType::RETURN, lhs: 7, rhs: 0, ..Default::default() },
                     IR { op: IRType::KILL, lhs: 7, rhs: 0, ..Default::default() }].to_vec(),
                 stack_size: 8,
@@ -0,0 +1,186 @@
+extern crate libc;
+extern crate rugcc;
+use self::rugcc::common::align_to;
+use assembler::{Object, RelocKind, RelocTarget, SECTION_NAMES, TEXT};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::mem;
+use std::ptr;
+
+// Loads assembled objects into executable memory and calls their `main`.
+// Calls to functions outside the objects, such as libc's printf, go through
+// a jump stub per symbol since the host library may be beyond rel32 reach.
+
+const STUB_SIZE: usize = 16;  // jmp [rip+0]; .quad addr; padding
+
+fn dlerror() -> String {
+    unsafe {
+        let msg = libc::dlerror();
+        if msg.is_null() {
+            return "unknown error".to_string();
+        }
+        return CStr::from_ptr(msg).to_string_lossy().into_owned()
+    }
+}
+
+// Makes a shared library's symbols visible to dlsym(RTLD_DEFAULT).
+pub fn load_library(path: &str) -> Result<(), String> {
+    let name = CString::new(path).map_err(|_| format!("bad library path {}", path))?;
+    let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_GLOBAL) };
+    if !handle.is_null() {
+        return Ok(());
+    }
+    let err = dlerror();
+    // glibc installs some libraries, like libm.so, as linker scripts that
+    // name the real shared objects: GROUP ( /lib/.../libm.so.6 ... )
+    let script = match fs::read_to_string(path) {
+        Ok(ref text) if text.contains("GROUP") => text.clone(),
+        _ => return Err(err),
+    };
+    let group = &script[script.find("GROUP").unwrap()..];
+    for lib in group.split(|c: char| c.is_whitespace() || c == '(' || c == ')').filter(|s| s.contains(".so")) {
+        load_library(lib)?;
+    }
+    return Ok(())
+}
+
+fn host_symbol(name: &str) -> Result<usize, String> {
+    let cname = CString::new(name).map_err(|_| format!("bad symbol {}", name))?;
+    let addr = unsafe { libc::dlsym(libc::RTLD_DEFAULT, cname.as_ptr()) };
+    if addr.is_null() {
+        return Err(format!("undefined symbol: {}", name));
+    }
+    return Ok(addr as usize)
+}
+
+struct Image {
+    base: *mut u8,
+    size: usize,
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base as *mut libc::c_void, self.size); }
+    }
+}
+
+fn write_at(addr: usize, bytes: &[u8]) {
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, bytes.len()); }
+}
+
+// Lays out every object's sections, applies relocations and runs main,
+// returning its exit status.
+pub fn run(objs: &[Object]) -> Result<i32, String> {
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+
+    // Code comes first, followed by one stub per external function; data
+    // sections follow on their own pages so code can be made read-only.
+    let mut bases: Vec<Vec<usize>> = vec![vec![0; SECTION_NAMES.len()]; objs.len()];
+    let mut size = 0;
+    for (i, obj) in objs.iter().enumerate() {
+        size = align_to(size, 16);
+        bases[i][TEXT] = size;
+        size += obj.sections[TEXT].len();
+    }
+
+    let mut globals: HashMap<&str, (usize, usize, usize)> = HashMap::new();
+    for (i, obj) in objs.iter().enumerate() {
+        for s in obj.symbols.iter().filter(|s| s.global) {
+            if globals.insert(&s.name, (i, s.section, s.offset)).is_some() {
+                return Err(format!("multiple definition of {}", s.name));
+            }
+        }
+    }
+    let mut stubs: HashMap<&str, usize> = HashMap::new();
+    for obj in objs {
+        for r in &obj.relocs {
+            if let RelocTarget::Symbol(ref name) = r.target {
+                let local = obj.symbols.iter().any(|s| &s.name == name);
+                if !local && !globals.contains_key(name.as_str()) && !stubs.contains_key(name.as_str()) {
+                    size = align_to(size, 16);
+                    stubs.insert(name, size);
+                    size += STUB_SIZE;
+                }
+            }
+        }
+    }
+    let code_size = align_to(size.max(1), page);
+    size = code_size;
+    for (i, obj) in objs.iter().enumerate() {
+        for section in (0..SECTION_NAMES.len()).filter(|&s| s != TEXT) {
+            size = align_to(size, 16);
+            bases[i][section] = size;
+            size += obj.sections[section].len();
+        }
+    }
+    size = align_to(size.max(code_size + 1), page);
+
+    let base = unsafe {
+        libc::mmap(ptr::null_mut(), size, libc::PROT_READ | libc::PROT_WRITE,
+                   libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+    };
+    if base == libc::MAP_FAILED {
+        return Err("cannot map memory for code".to_string());
+    }
+    let image = Image{base: base as *mut u8, size};
+    let addr = |offset: usize| image.base as usize + offset;
+
+    for (i, obj) in objs.iter().enumerate() {
+        for (section, data) in obj.sections.iter().enumerate() {
+            // .bss stays zero, as mmap left it.
+            write_at(addr(bases[i][section]), data);
+        }
+    }
+    let mut externals: HashMap<&str, usize> = HashMap::new();
+    for (&name, &offset) in &stubs {
+        let target = host_symbol(name)?;
+        externals.insert(name, target);
+        let mut stub = vec![0xff, 0x25, 0, 0, 0, 0];
+        stub.extend_from_slice(&(target as u64).to_le_bytes());
+        write_at(addr(offset), &stub);
+    }
+
+    for (i, obj) in objs.iter().enumerate() {
+        for r in &obj.relocs {
+            let s = match r.target {
+                RelocTarget::Section(section) => addr(bases[i][section]),
+                RelocTarget::Symbol(ref name) => {
+                    let local = obj.symbols.iter().find(|s| &s.name == name).map(|s| (i, s.section, s.offset));
+                    match local.or_else(|| globals.get(name.as_str()).cloned()) {
+                        Some((j, section, offset)) => addr(bases[j][section] + offset),
+                        None if r.kind == RelocKind::Plt32 => addr(stubs[name.as_str()]),
+                        None => externals[name.as_str()],
+                    }
+                },
+            };
+            let p = addr(bases[i][r.section] + r.offset);
+            let value = (s as i64).wrapping_add(r.addend);
+            match r.kind {
+                RelocKind::Abs64 => write_at(p, &value.to_le_bytes()),
+                RelocKind::Pc32 | RelocKind::Plt32 => {
+                    let rel = value.wrapping_sub(p as i64);
+                    if rel < i32::min_value() as i64 || rel > i32::max_value() as i64 {
+                        return Err(format!("relocation out of range at {}+{}", SECTION_NAMES[r.section], r.offset));
+                    }
+                    write_at(p, &(rel as i32).to_le_bytes());
+                },
+            }
+        }
+    }
+
+    if unsafe { libc::mprotect(image.base as *mut libc::c_void, code_size, libc::PROT_READ | libc::PROT_EXEC) } != 0 {
+        return Err("cannot make code executable".to_string());
+    }
+    let main = match globals.get("main") {
+        Some(&(i, section, offset)) if section == TEXT => addr(bases[i][TEXT] + offset),
+        _ => return Err("undefined symbol: main".to_string()),
+    };
+    // The code may still be running from atexit handlers after main
+    // returns, so the mapping lives until the process exits.
+    mem::forget(image);
+    let argv0 = CString::new("rugcc").unwrap();
+    let argv = [argv0.as_ptr(), ptr::null()];
+    let main: extern "C" fn(i32, *const *const libc::c_char) -> i32 = unsafe { mem::transmute(main) };
+    return Ok(main(1, argv.as_ptr()))
+}
@@ -1,4 +1,32 @@
 pub mod common {
+    use std::fmt;
+    use std::rc::Rc;
+
+    #[derive(PartialEq, Debug)]
+    pub struct SourceFile {
+        pub name: String,
+        pub text: String,
+    }
+
+    // Where a token or node came from. `line` and `col` are 1-based, `offset`
+    // and `len` are in bytes.
+    #[derive(Clone, Default, PartialEq)]
+    pub struct Span {
+        pub file: Option<Rc<SourceFile>>,
+        pub line: usize,
+        pub col: usize,
+        pub offset: usize,
+        pub len: usize,
+    }
+    impl fmt::Debug for Span {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.file {
+                Some(ref file) => write!(f, "{}:{}:{}", file.name, self.line, self.col),
+                None => write!(f, "<unknown>"),
+            }
+        }
+    }
+
     #[derive(PartialEq, Debug, Clone)]
     pub enum TK {
         NUM,
@@ -16,6 +44,74 @@ pub mod common {
         LOGAND,
         FOR,
         END_LINE,
+        EQ,        // ==
+        NE,        // !=
+        LE,        // <=
+        GE,        // >=
+        SHL,       // <<
+        SHR,       // >>
+        HASHHASH,  // ## in macro bodies
+        CHAR_CONST,  // 'a'; val is the spelling, quotes included
+        INC,       // ++
+        DEC,       // --
+        ADD_EQ,    // +=
+        SUB_EQ,    // -=
+        MUL_EQ,    // *=
+        DIV_EQ,    // /=
+        MOD_EQ,    // %=
+        AND_EQ,    // &=
+        OR_EQ,     // |=
+        XOR_EQ,    // ^=
+        SHL_EQ,    // <<=
+        SHR_EQ,    // >>=
+        WHILE,
+        DO,
+        BREAK,
+        CONTINUE,
+        GOTO,
+        SWITCH,
+        CASE,
+        DEFAULT,
+        EXTERN,
+        STATIC,
+        STRUCT,
+        UNION,
+        ARROW,     // ->
+        SHORT,
+        LONG,
+        SIGNED,
+        UNSIGNED,
+        BOOL,      // _Bool
+        FLOAT,
+        DOUBLE,
+        ELLIPSIS,  // ...
+    }
+
+    #[derive(PartialEq, Debug, Clone, Copy)]
+    pub enum ErrorKind {
+        Lex,       // malformed token
+        Preprocess,  // bad directive or macro use
+        Parse,     // syntax error
+        Sema,      // type or name error
+        Codegen,   // valid program we cannot compile
+        Internal,  // broken invariant inside the compiler
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct CompileError {
+        pub kind: ErrorKind,
+        pub span: Span,
+        pub msg: String,
+    }
+    impl CompileError {
+        pub fn new(kind: ErrorKind, span: Span, msg: String) -> CompileError {
+            return CompileError{kind, span, msg}
+        }
+    }
+    impl fmt::Display for CompileError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{:?}: error: {}", self.span, self.msg)
+        }
     }
 
     #[derive(Debug, Clone, PartialEq)]
@@ -23,9 +119,10 @@ pub mod common {
         pub ty: TK,
         pub val: String,
         pub str: String,
+        pub span: Span,
     }
     impl Default for Token {
-        fn default() -> Self { Token{ty: TK::INT, val: String::new(), str: String::new()} }
+        fn default() -> Self { Token{ty: TK::INT, val: String::new(), str: String::new(), span: Span::default()} }
     }
 
 
@@ -35,17 +132,37 @@ pub mod common {
         CHAR,
         PTR,
         ARY,
+        LONG,
+        LLONG,  // long long
+        SHORT,
+        BOOL,   // _Bool
+        FLOAT,
+        DOUBLE,
+        STRUCT,  // also unions
+    }
+    #[derive(PartialEq, Debug, Clone)]
+    pub struct Member {
+        pub name: String,
+        pub ty: Type,
+        pub offset: usize,
     }
     #[derive(PartialEq, Debug, Clone)]
     pub struct Type {
         pub ty: TY,
+        pub is_unsigned: bool,
         pub ptr_of: Option<Box<Type>>,
         pub ary_of: Option<Box<Type>>,
-        pub len: usize,
+        pub len: usize,  // 0 for an array of unknown length, as in `int a[]`
+        // Struct and union
+        pub tag: String,
+        pub is_union: bool,
+        pub id: usize,  // tells apart structs with the same tag; set by sema
+        pub members: Option<Vec<Member>>,  // None while the type is incomplete
     }
     impl Default for Type {
         fn default() -> Self {
-            Type{ ty: TY::INT, ptr_of: None, ary_of: None, len: 0}
+            Type{ ty: TY::INT, is_unsigned: false, ptr_of: None, ary_of: None, len: 0,
+                  tag: String::new(), is_union: false, id: 0, members: None}
         }
     }
     impl Type {
@@ -56,8 +173,23 @@ pub mod common {
             match self.ty {
                 TY::INT => return 4,
                 TY::ARY => return self.ary_of.clone().unwrap().size_of() * self.len,
-                TY::PTR => return 8,
-                TY::CHAR => return 1,
+                TY::PTR | TY::LONG | TY::LLONG | TY::DOUBLE => return 8,
+                TY::FLOAT => return 4,
+                TY::SHORT => return 2,
+                TY::CHAR | TY::BOOL => return 1,
+                // The end of the last member, padded so that arrays of the struct stay aligned.
+                TY::STRUCT => {
+                    let end = self.members.iter().flatten().map(|m| m.offset + m.ty.size_of()).max().unwrap_or(0);
+                    let align = self.align_of();
+                    return align_to(end, align)
+                },
+            }
+        }
+        pub fn align_of(&self) -> usize {
+            match self.ty {
+                TY::ARY => return self.ary_of.clone().unwrap().align_of(),
+                TY::STRUCT => return self.members.iter().flatten().map(|m| m.ty.align_of()).max().unwrap_or(1),
+                _ => return self.size_of(),
             }
         }
         pub fn ary_of(&self, len: usize) -> Type {
@@ -66,6 +198,14 @@ pub mod common {
         pub fn ptr_of(&self) -> Type {
             return Type{ty: TY::PTR, ptr_of: Some(Box::new(self.clone())), ..Default::default()};
         }
+        pub fn is_flonum(&self) -> bool {
+            return self.ty == TY::FLOAT || self.ty == TY::DOUBLE
+        }
+    }
+
+    // Rounds n up to a multiple of align.
+    pub fn align_to(n: usize, align: usize) -> usize {
+        return n.div_ceil(align) * align
     }
 
     #[derive(PartialEq, Debug, Clone)]
@@ -89,6 +229,30 @@ pub mod common {
         RETURN,
         COMP_STMT,
         EXPR_STMT,
+        EQ,        // ==
+        NE,        // !=
+        LE,        // <=; `a >= b` is `b <= a`, as `a > b` is `b < a`
+        SHL,       // <<
+        SHR,       // >>
+        NEG,       // unary -
+        NOT,       // !
+        BITNOT,    // ~
+        ASSIGN_OP(Box<ND>),  // `lhs op= rhs`, holding the binary op; also prefix ++ and --
+        POST_INC,
+        POST_DEC,
+        TERNARY,   // cond ? then : els
+        COMMA,
+        DO_WHILE,
+        BREAK,
+        CONTINUE,
+        GOTO,      // val is the label name
+        LABEL,     // `val: body`
+        SWITCH,    // "switch" ( cond ) body
+        CASE,      // "case" expr: body; sema folds expr into num
+        DEFAULT,   // "default": body
+        INIT_LIST, // { args... }, the initializer of an array or struct
+        DOT,       // expr.val; `p->x` is `(*p).x`
+        CAST,      // (ty) expr; sema also adds them for implicit conversions
     }
     #[derive(Debug, PartialEq, Clone)]
     pub struct Node {
@@ -109,26 +273,38 @@ pub mod common {
         pub args: Vec<Node>,
         pub body: Option<Box<Node>>,
         pub strings: Vec<Node>,
-        pub str: String,
+        pub num: i64,  // value of ND::NUM; unsigned values keep their bits
+        pub fnum: f64,  // value of ND::NUM of a floating type
+        pub cases: Vec<i64>,  // case values of a switch, in source order
+        pub str: Vec<u8>,  // string literal contents, without the terminating NUL
         // Function definition
         pub stack_size: usize,
         // Local variable
         pub offset: usize,
+        // Storage class of a variable definition
+        pub is_static: bool,
+        pub is_extern: bool,
+        // Function whose parameters end in `...`
+        pub is_variadic: bool,
+        pub span: Span,
     }
     impl Default for Node {
         fn default() -> Self {
             Self { op: ND::NUM, ty: Type{..Default::default()}, lhs: None, rhs: None, val: String::new(), expr: None,
                 cond: None, then: None, els: None, init: None, inc: None, stmts: Vec::new(),
-                args: Vec::new(), body: None, strings: Vec::new(), str: String::new(), stack_size: 0, offset: 0}
+                args: Vec::new(), body: None, strings: Vec::new(), num: 0, fnum: 0.0, cases: Vec::new(), str: Vec::new(), stack_size: 0, offset: 0,
+                is_static: false, is_extern: false, is_variadic: false, span: Span::default()}
         }
     }
     impl Node {
-        pub fn get_ope(&self) -> char {
+        pub fn get_ope(&self) -> String {
             match self.op {
-                ND::OPE(c) => return c,
+                ND::OPE(c) => return c.to_string(),
+                ND::SHL => return "<<".to_string(),
+                ND::SHR => return ">>".to_string(),
                 _ => {
                     assert!(false);
-                    return 'a'
+                    return String::new()
                 },
             }
         }
@@ -137,6 +313,18 @@ pub mod common {
         }
     }
 
+    // A variable with static storage: a global, or a static local under a
+    // private name.
+    #[derive(PartialEq, Debug, Clone)]
+    pub struct GlobalVar {
+        pub name: String,
+        pub size: usize,
+        pub align: usize,
+        pub is_static: bool,  // not visible to other files
+        pub data: Option<Vec<u8>>,  // None for zero-initialized variables
+        pub relocs: Vec<(usize, String, i64)>,  // 8-byte addresses in data: offset, symbol, addend
+    }
+
     #[derive(PartialEq, Debug, Clone)]
     pub struct Function {
         pub name: String,
@@ -158,18 +346,27 @@ pub mod common {
         LABEL,
         LABEL_ADDR,
         UNLESS,
-        LOAD8,
+        LOAD8,       // zero-extending, as are LOAD16 and LOAD32
+        LOAD16,
         LOAD32,
         LOAD64,
+        SLOAD8,      // sign-extending, as are SLOAD16 and SLOAD32
+        SLOAD16,
+        SLOAD32,
         STORE8,
+        STORE16,
         STORE32,
         STORE64,
         STORE8_ARG,
+        STORE16_ARG,
         STORE32_ARG,
         STORE64_ARG,
         RETURN,
+        ARG,
         CALL,
         JMP,
+        LOAD_SPILL,
+        STORE_SPILL,
         KILL,
         NOP,
         LT,
@@ -177,6 +374,73 @@ pub mod common {
         SUB,
         MUL,
         DIV,
+        MOD,
+        EQ,
+        NE,
+        LE,
+        AND,
+        OR,
+        XOR,
+        SHL,
+        SHR,
+        NEG,
+        JMP_TABLE,
+        STACK_ARGS,  // lhs words of arguments are pushed before the next CALL
+        PUSH_ARG,
+        GET_RDX,     // the second eightbyte of a struct returned by the last CALL
+        SET_RDX,     // the second eightbyte of a struct about to be returned
+        SEXT,        // sign-extends the low rhs bytes of lhs to the whole register
+        ZEXT,        // zero-extends them
+        UDIV,
+        UMOD,
+        ULT,
+        ULE,
+        USHR,
+        // Floating point. xmm registers hold doubles; float values are
+        // rounded to float precision but kept as doubles.
+        FIMM,        // rhs holds the bits of a double
+        FMOV,
+        FADD,
+        FSUB,
+        FMUL,
+        FDIV,
+        FLT,         // lhs becomes 1.0 if the comparison holds and 0.0 if not
+        FLE,
+        FEQ,
+        FNE,
+        FNEG,
+        FROUND,      // rounds lhs to float precision
+        I2F,         // from a signed 64-bit integer in rhs
+        F2I,         // to a signed 64-bit integer in lhs, truncating
+        FLOAD32,
+        FLOAD64,
+        FSTORE32,
+        FSTORE64,
+        FSTORE32_ARG,
+        FSTORE64_ARG,
+        FARG32,
+        FARG64,
+        FPUSH_ARG,   // rhs is the size of the value
+        FRETURN,     // rhs is the size of the value
+        FRESULT,     // the floating result of the last CALL; rhs is its size
+        SET_XMM,     // moves an eightbyte of a struct into xmm rhs, for a call or return
+        GET_XMM,     // the eightbyte returned in xmm rhs by the last CALL
+        FLOAD_SPILL,
+        FSTORE_SPILL,
+    }
+
+    // Which of lhs and rhs are xmm registers rather than general-purpose
+    // ones. Operands that are not registers at all are false.
+    pub fn xmm_operands(op: IRType) -> (bool, bool) {
+        match op {
+            IRType::FMOV | IRType::FADD | IRType::FSUB | IRType::FMUL | IRType::FDIV |
+            IRType::FLT | IRType::FLE | IRType::FEQ | IRType::FNE => return (true, true),
+            IRType::FIMM | IRType::FNEG | IRType::FROUND | IRType::I2F | IRType::FLOAD32 | IRType::FLOAD64 |
+            IRType::FARG32 | IRType::FARG64 | IRType::FPUSH_ARG | IRType::FRETURN | IRType::FRESULT |
+            IRType::FLOAD_SPILL | IRType::FSTORE_SPILL => return (true, false),
+            IRType::F2I | IRType::FSTORE32 | IRType::FSTORE64 => return (false, true),
+            _ => return (false, false),
+        }
     }
 
     #[derive(Clone, Debug, PartialEq)]
@@ -185,11 +449,12 @@ pub mod common {
         pub lhs: usize,
         pub rhs: usize,
         pub name: String,
-        pub args: Vec<usize>,
+        // JMP_TABLE: the label for each index in lhs; rhs is taken when it is out of range
+        pub targets: Vec<usize>,
     }
     impl Default for IR {
         fn default() -> Self {
-            Self { op: IRType::NOP, lhs: 0, rhs: 0, name: String::new(), args: Vec::new() }
+            Self { op: IRType::NOP, lhs: 0, rhs: 0, name: String::new(), targets: Vec::new() }
         }
     }
     impl IR {
@@ -212,7 +477,7 @@ pub mod common {
                 IRInfoType::REG_IMN => return format!("{} r{}, {}", info.name, self.lhs, self.rhs),
                 IRInfoType::REG_LABEL => return format!("{} r{}, .L{}", info.name, self.lhs, self.rhs),
                 IRInfoType::NOARG => return format!("{}", info.name),
-                IRInfoType::CALL => return format!("r{} = {}(", self.lhs, self.name),
+                IRInfoType::CALL => return format!("r{} = {}({} args)", self.lhs, self.name, self.rhs),
                 IRInfoType::IMM => return format!("{} {}\n", info.name, self.lhs),
                 IRInfoType::IMM_IMM =>return format!("{} {}, {}", info.name, self.lhs, self.rhs),
                 IRInfoType::JMP => return format!("  {} .L{}", info.name, self.lhs),
@@ -242,32 +507,92 @@ pub mod common {
         pub ty: IRInfoType,
     }
 
-    const IRINFO: [IRInfo; 25] = [
+    const IRINFO: [IRInfo; 85] = [
         IRInfo{op: IRType::ADD, name: "ADD", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::SUB, name: "SUB", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::MUL, name: "MUL", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::DIV, name: "DIV", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::MOD, name: "MOD", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::UDIV, name: "UDIV", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::UMOD, name: "UMOD", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::EQ, name: "EQ", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::NE, name: "NE", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::LE, name: "LE", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::ULE, name: "ULE", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::AND, name: "AND", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::OR, name: "OR", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::XOR, name: "XOR", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::SHL, name: "SHL", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::SHR, name: "SHR", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::USHR, name: "USHR", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::NEG, name: "NEG", ty: IRInfoType::REG},
         IRInfo{op: IRType::IMM, name: "MOV", ty: IRInfoType::REG_IMN},
         IRInfo{op: IRType::SUB_IMM, name: "SUB", ty: IRInfoType::REG_IMN},
         IRInfo{op: IRType::MOV, name: "MOV", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::LABEL, name: "", ty: IRInfoType::LABEL},
         IRInfo{op: IRType::LABEL_ADDR, name: "", ty: IRInfoType::LABEL_ADDR},
         IRInfo{op: IRType::UNLESS, name: "UNLESS", ty: IRInfoType::REG_LABEL},
+        IRInfo{op: IRType::JMP_TABLE, name: "JMP_TABLE", ty: IRInfoType::REG_LABEL},
         IRInfo{op: IRType::RETURN, name: "RET", ty: IRInfoType::REG},
         IRInfo{op: IRType::LOAD8, name: "LOAD8", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::LOAD16, name: "LOAD16", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::LOAD32, name: "LOAD32", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::LOAD64, name: "LOAD64", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::SLOAD8, name: "SLOAD8", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::SLOAD16, name: "SLOAD16", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::SLOAD32, name: "SLOAD32", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::STORE8, name: "STORE8", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::STORE16, name: "STORE16", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::STORE32, name: "STORE32", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::STORE64, name: "STORE32", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::STORE8_ARG, name: "STORE8_ARG", ty: IRInfoType::IMM_IMM},
+        IRInfo{op: IRType::STORE16_ARG, name: "STORE16_ARG", ty: IRInfoType::IMM_IMM},
         IRInfo{op: IRType::STORE32_ARG, name: "STORE32_ARG", ty: IRInfoType::IMM_IMM},
         IRInfo{op: IRType::STORE64_ARG, name: "STORE64_ARG", ty: IRInfoType::IMM_IMM},
         IRInfo{op: IRType::LT, name: "LT", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::ULT, name: "ULT", ty: IRInfoType::REG_REG},
         IRInfo{op: IRType::KILL, name: "KILL", ty: IRInfoType::NOARG},
         IRInfo{op: IRType::NOP, name: "NOP", ty: IRInfoType::NOARG},
         IRInfo{op: IRType::JMP, name: "JMP", ty: IRInfoType::JMP},
+        IRInfo{op: IRType::ARG, name: "ARG", ty: IRInfoType::REG_IMN},
         IRInfo{op: IRType::CALL, name: "CALL", ty: IRInfoType::CALL},
+        IRInfo{op: IRType::LOAD_SPILL, name: "LOAD_SPILL", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::STORE_SPILL, name: "STORE_SPILL", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::STACK_ARGS, name: "STACK_ARGS", ty: IRInfoType::IMM},
+        IRInfo{op: IRType::PUSH_ARG, name: "PUSH_ARG", ty: IRInfoType::REG},
+        IRInfo{op: IRType::GET_RDX, name: "GET_RDX", ty: IRInfoType::REG},
+        IRInfo{op: IRType::SET_RDX, name: "SET_RDX", ty: IRInfoType::REG},
+        IRInfo{op: IRType::SEXT, name: "SEXT", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::ZEXT, name: "ZEXT", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::FIMM, name: "FMOV", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::FMOV, name: "FMOV", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FADD, name: "FADD", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FSUB, name: "FSUB", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FMUL, name: "FMUL", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FDIV, name: "FDIV", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FLT, name: "FLT", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FLE, name: "FLE", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FEQ, name: "FEQ", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FNE, name: "FNE", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FNEG, name: "FNEG", ty: IRInfoType::REG},
+        IRInfo{op: IRType::FROUND, name: "FROUND", ty: IRInfoType::REG},
+        IRInfo{op: IRType::I2F, name: "I2F", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::F2I, name: "F2I", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FLOAD32, name: "FLOAD32", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FLOAD64, name: "FLOAD64", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FSTORE32, name: "FSTORE32", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FSTORE64, name: "FSTORE64", ty: IRInfoType::REG_REG},
+        IRInfo{op: IRType::FSTORE32_ARG, name: "FSTORE32_ARG", ty: IRInfoType::IMM_IMM},
+        IRInfo{op: IRType::FSTORE64_ARG, name: "FSTORE64_ARG", ty: IRInfoType::IMM_IMM},
+        IRInfo{op: IRType::FARG32, name: "FARG32", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::FARG64, name: "FARG64", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::FPUSH_ARG, name: "FPUSH_ARG", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::FRETURN, name: "FRET", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::FRESULT, name: "FRESULT", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::SET_XMM, name: "SET_XMM", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::GET_XMM, name: "GET_XMM", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::FLOAD_SPILL, name: "FLOAD_SPILL", ty: IRInfoType::REG_IMN},
+        IRInfo{op: IRType::FSTORE_SPILL, name: "FSTORE_SPILL", ty: IRInfoType::REG_IMN},
     ];
 
     pub fn dump_ir(fns: &Vec<Function>) {
@@ -317,12 +642,10 @@ pub mod common {
     }
 }
 
+// Returns early with a `CompileError` of the given kind located at `span`.
 #[macro_export]
-#[allow(unused)]
 macro_rules! error {
-        ( $m:expr, $t:ty ) => {
-            eprintln!("{:?}", $m);
-            std::process::exit(1);
-            return <$t as Default>::default() as $t
-        }
+    ( $kind:ident, $span:expr, $($arg:tt)* ) => {
+        return Err($crate::common::CompileError::new($crate::common::ErrorKind::$kind, $span.clone(), format!($($arg)*)))
     }
+}
@@ -1,26 +1,262 @@
 extern crate clap;
 use clap::{App, Arg};
+#[macro_use]
 extern crate rugcc;
-use self::rugcc::common::{dump_ir, dump_nodes};
+use self::rugcc::common::{dump_ir, dump_nodes, SourceFile, CompileError, ErrorKind};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+mod diag;
 mod node;
 mod token;
+mod preprocess;
 mod ir;
 mod sema;
 mod regalloc;
 mod codegen;
+mod target;
+mod driver;
+mod assembler;
+mod elf;
+mod jit;
 
-const REGS: [&str; 8] = ["rbp", "r10", "r11", "r9", "r12", "r13", "r14", "r15"];
+use target::Target;
+use driver::{Input, Mode, TempDir};
+
+const REGS: [&str; 8] = ["rbp", "r10", "r11", "rbx", "r12", "r13", "r14", "r15"];
 const REGS8: [&str; 8] = ["bpl", "r10b", "r11b", "bl", "r12b", "r13b", "r14b", "r15b"];
+const REGS16: [&str; 8] = ["bp", "r10w", "r11w", "bx", "r12w", "r13w", "r14w", "r15w"];
 const REGS32: [&str; 8] = ["ebp", "r10d", "r11d", "ebx", "r12d", "r13d", "r14d", "r15d"];
+// xmm0 to xmm7 pass arguments, so floating values live in the upper half.
+const XMMS: [&str; 8] = ["xmm8", "xmm9", "xmm10", "xmm11", "xmm12", "xmm13", "xmm14", "xmm15"];
+
+struct Options {
+    mode: Mode,
+    max_errors: usize,
+    target: Target,
+    integrated_as: bool,
+    include_dirs: Vec<String>,
+    defines: Vec<String>,  // -D, applied before -U
+    undefs: Vec<String>,
+    dump_macros: bool,  // -dM
+    dump_token: bool,
+    dump_node: bool,
+    dump_ir1: bool,
+    dump_ir2: bool,
+}
+
+fn read_source(path: &str) -> String {
+    let result = if path == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).map(|_| text)
+    } else {
+        fs::read_to_string(path)
+    };
+    match result {
+        Ok(text) => return text,
+        Err(e) => {
+            eprintln!("rugcc: cannot read {}: {}", path, e);
+            process::exit(1);
+        },
+    }
+}
+
+fn source_name(path: &str) -> &str {
+    return if path == "-" { "<stdin>" } else { path }
+}
+
+fn compile(name: &str, text: String, opts: &Options) -> Result<String, Vec<CompileError>> {
+    let file = Rc::new(SourceFile{name: name.to_string(), text});
+    let mut pp = preprocess::Preprocessor::new(opts.include_dirs.clone());
+    for def in &opts.defines {
+        pp.define(def).map_err(|e| vec![e])?;
+    }
+    for name in &opts.undefs {
+        pp.undef(name);
+    }
+    let mut tokens = pp.preprocess(file).map_err(|e| vec![e])?;
+    if opts.mode == Mode::Preprocess {
+        return Ok(if opts.dump_macros { pp.dump_macros() } else { preprocess::print_tokens(&tokens) });
+    }
+    if opts.dump_token {eprintln!("{:?}", tokens);}
+    let mut nodes =  node::parse(&mut tokens)?;
+    if opts.dump_node { dump_nodes(&nodes); }
+    let mut sema = sema::SemaGenerator::new();
+    nodes = sema.sema(nodes).map_err(|e| vec![e])?;
+
+    let mut fns = ir::IrGenerator::new().gen_ir(nodes).map_err(|e| vec![e])?;
+
+    if opts.dump_ir1 {dump_ir(&fns)}
+    regalloc::alloc_regs(&mut fns).map_err(|e| vec![e])?;
+    if opts.dump_ir2 {dump_ir(&fns)}
+    return codegen::gen_x86(fns, sema.globals, opts.target).map_err(|e| vec![e])
+}
+
+// 1: the program is wrong, 2: rugcc cannot compile it, 3: rugcc is wrong.
+fn exit_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::Lex | ErrorKind::Preprocess | ErrorKind::Parse | ErrorKind::Sema => return 1,
+        ErrorKind::Codegen => return 2,
+        ErrorKind::Internal => return 3,
+    }
+}
+
+fn compile_or_exit(name: &str, text: String, opts: &Options) -> String {
+    match compile(name, text, opts) {
+        Ok(asm) => return asm,
+        Err(errors) => {
+            for e in errors.iter().take(if opts.max_errors == 0 { errors.len() } else { opts.max_errors }) {
+                diag::report(e);
+            }
+            if opts.max_errors != 0 && errors.len() > opts.max_errors {
+                eprintln!("rugcc: compilation terminated due to -fmax-errors={}.", opts.max_errors);
+            }
+            process::exit(exit_code(errors[0].kind));
+        },
+    }
+}
+
+fn write_output(path: &str, asm: &str) {
+    let result = if path == "-" {
+        io::stdout().write_all(asm.as_bytes())
+    } else {
+        fs::write(path, asm)
+    };
+    if let Err(e) = result {
+        eprintln!("rugcc: cannot write {}: {}", path, e);
+        process::exit(1);
+    }
+}
+
+// Reports a failure of the assembler or linker and exits. Temporary files
+// are removed first, since process::exit skips destructors.
+fn driver_error(msg: String, temps: Option<TempDir>) -> ! {
+    drop(temps);
+    eprintln!("rugcc: {}", msg);
+    process::exit(1);
+}
+
+// --run: assembles in memory and calls main, with shared libraries given
+// as inputs or with -l loaded into the process first.
+fn run(inputs: &[Input], asms: Vec<(Input, String)>, lib_dirs: &[&str], libs: &[&str], opts: &Options) -> Result<i32, String> {
+    if opts.target != Target::Linux {
+        return Err("--run is only supported for x86_64-linux-gnu".to_string());
+    }
+    let mut objs = Vec::new();
+    for (_, asm) in asms {
+        objs.push(assembler::assemble(&asm).map_err(|e| format!("internal assembler: {}", e))?);
+    }
+    for &input in inputs {
+        match input {
+            Input::Asm(path) => {
+                let asm = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+                objs.push(assembler::assemble(&asm).map_err(|e| format!("{}: {}", path, e))?);
+            },
+            Input::Linker(path) if path.ends_with(".so") => jit::load_library(path)?,
+            Input::Linker(path) => return Err(format!("{}: --run can only load shared libraries", path)),
+            _ => {},
+        }
+    }
+    let system_dirs = ["/usr/local/lib", "/lib/x86_64-linux-gnu", "/usr/lib/x86_64-linux-gnu", "/lib64", "/usr/lib64", "/lib", "/usr/lib"];
+    for lib in libs {
+        let name = format!("lib{}.so", lib);
+        let path = lib_dirs.iter().chain(system_dirs.iter()).map(|dir| Path::new(dir).join(&name)).find(|path| path.exists());
+        jit::load_library(&path.map(|p| p.to_string_lossy().into_owned()).unwrap_or(name))?;
+    }
+    return jit::run(&objs)
+}
 
 fn main() {
     let app = App::new("rugcc")
         .version("0.0.1")
         .author("care0717")
         .about("Toy clang compiler")
-        .arg(Arg::with_name("code")
-            .help("enter code")
-            .required(true)
+        .arg(Arg::with_name("input")
+            .help("input files (`-` reads stdin)")
+            .multiple(true)
+            .required_unless("code")
+        ).arg(Arg::with_name("code")
+            .help("compile code given on the command line")
+            .short("e")
+            .takes_value(true)
+            .value_name("code")
+        ).arg(Arg::with_name("output")
+            .help("write output to <file> (`-` is stdout)")
+            .short("o")
+            .takes_value(true)
+            .value_name("file")
+        ).arg(Arg::with_name("preprocess")
+            .help("only preprocess; write the expanded source")
+            .short("E")
+        ).arg(Arg::with_name("dump")
+            .help("with -E, -dM writes the macros defined at the end instead")
+            .short("d")
+            .takes_value(true)
+            .possible_values(&["M"])
+            .requires("preprocess")
+            .value_name("M")
+        ).arg(Arg::with_name("assembly")
+            .help("only compile; write assembly")
+            .short("S")
+            .conflicts_with("preprocess")
+        ).arg(Arg::with_name("object")
+            .help("compile and assemble, but do not link")
+            .short("c")
+            .conflicts_with_all(&["assembly", "preprocess"])
+        ).arg(Arg::with_name("run")
+            .help("compile in memory and run main, exiting with its status")
+            .long("run")
+            .conflicts_with_all(&["preprocess", "assembly", "object", "output"])
+        ).arg(Arg::with_name("lib")
+            .help("link with library <name>")
+            .short("l")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("name")
+        ).arg(Arg::with_name("lib-dir")
+            .help("add <dir> to the library search path")
+            .short("L")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("dir")
+        ).arg(Arg::with_name("include-dir")
+            .help("add <dir> to the #include search path")
+            .short("I")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("dir")
+        ).arg(Arg::with_name("define")
+            .help("define <macro> as 1, or as <value> with <macro>=<value>")
+            .short("D")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("macro")
+        ).arg(Arg::with_name("undef")
+            .help("undefine <macro>")
+            .short("U")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("macro")
+        ).arg(Arg::with_name("flag")
+            .help("code generation and diagnostic options: -fmax-errors=N (0 is no limit), -f[no-]integrated-as")
+            .short("f")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("option")
+        ).arg(Arg::with_name("target")
+            .help("generate code for <triple> (default: the host)")
+            .long("target")
+            .takes_value(true)
+            .possible_values(&target::TRIPLES)
+            .value_name("triple")
         ).arg(Arg::with_name("dump-token")
             .help("dump token vec")
             .long("dump-token")
@@ -35,23 +271,157 @@ fn main() {
             .long("dump-ir2")
         );
     let matches = app.get_matches();
-    let input = matches.value_of("code").unwrap();
-    let dump_ir1 = matches.is_present("dump-ir1");
-    let dump_ir2 = matches.is_present("dump-ir2");
-    let dump_node = matches.is_present("dump-node");
-    let dump_token = matches.is_present("dump-token");
-
-    let mut tokens = token::tokenize(input.chars().collect());
-    if dump_token {eprintln!("{:?}", tokens);}
-    let mut nodes =  node::parse(&mut tokens);
-    if dump_node { dump_nodes(&nodes); }
-    let mut sema = sema::SemaGenerator::new();
-    nodes = sema.sema(nodes);
+    let mut max_errors = 0;
+    let target = matches.value_of("target").and_then(Target::from_triple).unwrap_or_else(Target::host);
+    let mut integrated_as = target == Target::Linux;
+    for flag in matches.values_of("flag").into_iter().flatten() {
+        if flag == "integrated-as" || flag == "no-integrated-as" {
+            integrated_as = flag == "integrated-as";
+            if integrated_as && target != Target::Linux {
+                eprintln!("rugcc: the integrated assembler only writes ELF objects");
+                process::exit(1);
+            }
+            continue;
+        }
+        let parsed = if flag.starts_with("max-errors=") { flag["max-errors=".len()..].parse().ok() } else { None };
+        match parsed {
+            Some(n) => max_errors = n,
+            None => {
+                eprintln!("rugcc: unknown option -f{}", flag);
+                process::exit(1);
+            },
+        }
+    }
+    let mode = if matches.is_present("preprocess") {
+        Mode::Preprocess
+    } else if matches.is_present("assembly") {
+        Mode::Assembly
+    } else if matches.is_present("object") {
+        Mode::Object
+    } else {
+        Mode::Executable
+    };
+    let opts = Options {
+        mode,
+        max_errors,
+        target,
+        integrated_as,
+        include_dirs: matches.values_of("include-dir").into_iter().flatten().map(String::from).collect(),
+        defines: matches.values_of("define").into_iter().flatten().map(String::from).collect(),
+        undefs: matches.values_of("undef").into_iter().flatten().map(String::from).collect(),
+        dump_macros: matches.value_of("dump") == Some("M"),
+        dump_token: matches.is_present("dump-token"),
+        dump_node: matches.is_present("dump-node"),
+        dump_ir1: matches.is_present("dump-ir1"),
+        dump_ir2: matches.is_present("dump-ir2"),
+    };
+
+    let mut inputs: Vec<Input> = matches.value_of("code").map(Input::Code).into_iter().collect();
+    inputs.extend(matches.values_of("input").into_iter().flatten().map(driver::classify));
+
+    // Compile every C source before running any tools, so a compile error
+    // leaves no half-written outputs behind.
+    let mut asms: Vec<(Input, String)> = Vec::new();
+    for &input in &inputs {
+        match input {
+            Input::Code(code) => asms.push((input, compile_or_exit("<command line>", code.to_string(), &opts))),
+            Input::C(path) => {
+                let src = read_source(path);
+                asms.push((input, compile_or_exit(source_name(path), src, &opts)));
+            },
+            _ => {},
+        }
+    }
+
+    let output = matches.value_of("output");
+    if mode == Mode::Preprocess {
+        for &input in &inputs {
+            if let Input::Asm(path) | Input::Linker(path) = input {
+                eprintln!("rugcc: warning: {}: input file unused because -E was given", path);
+            }
+        }
+        let text: String = asms.into_iter().map(|(_, text)| text).collect();
+        write_output(output.unwrap_or("-"), &text);
+        return
+    }
+
+    if matches.is_present("run") {
+        let lib_dirs: Vec<&str> = matches.values_of("lib-dir").into_iter().flatten().collect();
+        let libs: Vec<&str> = matches.values_of("lib").into_iter().flatten().collect();
+        match run(&inputs, asms, &lib_dirs, &libs, &opts) {
+            Ok(status) => process::exit(status),
+            Err(e) => driver_error(e, None),
+        }
+    }
+
+    if mode == Mode::Assembly {
+        for &input in &inputs {
+            if let Input::Asm(path) | Input::Linker(path) = input {
+                eprintln!("rugcc: warning: {}: input file unused because -S was given", path);
+            }
+        }
+        if asms.len() > 1 && output.is_some() {
+            eprintln!("rugcc: cannot specify -o with multiple input files");
+            process::exit(1);
+        }
+        if asms.len() == 1 {
+            write_output(output.unwrap_or("-"), &asms[0].1);
+            return
+        }
+        for (input, asm) in asms {
+            write_output(&driver::output_path(input, "s"), &asm);
+        }
+        return
+    }
+
+    if mode == Mode::Object {
+        for &input in &inputs {
+            if let Input::Linker(path) = input {
+                eprintln!("rugcc: warning: {}: linker input file unused because linking not done", path);
+            }
+        }
+        if inputs.iter().filter(|input| match **input { Input::Linker(_) => false, _ => true }).count() > 1 && output.is_some() {
+            eprintln!("rugcc: cannot specify -o with multiple input files");
+            process::exit(1);
+        }
+    }
 
-    let mut fns = ir::IrGenerator::new().gen_ir(nodes);
+    let mut temps = match TempDir::new() {
+        Ok(temps) => temps,
+        Err(e) => driver_error(e, None),
+    };
+    let mut asms = asms.into_iter();
+    let mut objs: Vec<String> = Vec::new();
+    for &input in &inputs {
+        if let Input::Linker(path) = input {
+            if mode == Mode::Executable { objs.push(path.to_string()); }
+            continue;
+        }
+        let obj = match mode {
+            Mode::Object => output.map(str::to_string).unwrap_or_else(|| driver::output_path(input, "o")),
+            _ => temps.file("o"),
+        };
+        let result = match input {
+            Input::Code(_) | Input::C(_) if opts.integrated_as => driver::assemble_integrated(&asms.next().unwrap().1, &obj),
+            Input::Code(_) | Input::C(_) => {
+                let path = temps.file("s");
+                write_output(&path, &asms.next().unwrap().1);
+                driver::assemble(&path, &obj)
+            },
+            Input::Asm(path) | Input::Linker(path) => driver::assemble(path, &obj),
+        };
+        if let Err(e) = result {
+            driver_error(e, Some(temps));
+        }
+        objs.push(obj);
+    }
+    if mode == Mode::Object {
+        return
+    }
 
-    if dump_ir1 {dump_ir(&fns)}
-    regalloc::alloc_regs(&mut fns);
-    if dump_ir2 {dump_ir(&fns)}
-    codegen::gen_x86(fns);
+    let lib_dirs: Vec<&str> = matches.values_of("lib-dir").into_iter().flatten().collect();
+    let libs: Vec<&str> = matches.values_of("lib").into_iter().flatten().collect();
+    if let Err(e) = driver::link(&objs, &lib_dirs, &libs, output.unwrap_or("a.out")) {
+        driver_error(e, Some(temps));
+    }
 }
@@ -1,20 +1,40 @@
 extern crate rugcc;
-use self::rugcc::common::{TK, Token, ND,  Node, Type};
+use self::rugcc::common::{TK, Token, ND,  Node, Type, TY, Member, Span, CompileError, ErrorKind};
+use token::{int_literal, is_float_literal, float_literal, unescape};
 
-fn new_binop(op: ND, lhs: Node, rhs: Node) -> Node {
-    return Node{ op, lhs: Some(Box::new(lhs)), rhs: Some(Box::new(rhs)), ..Default::default()};
+fn new_binop(op: ND, lhs: Node, rhs: Node, span: Span) -> Node {
+    return Node{ op, lhs: Some(Box::new(lhs)), rhs: Some(Box::new(rhs)), span, ..Default::default()};
 }
 
-fn new_expr(op: ND, expr: Node) -> Node {
-    return  Node{op, expr: Some(Box::new(expr)), ..Default::default()}
+fn new_expr(op: ND, expr: Node, span: Span) -> Node {
+    return  Node{op, expr: Some(Box::new(expr)), span, ..Default::default()}
 }
 
-fn expect(ty: TK, tokens: &mut Vec<Token>) {
+fn describe(ty: &TK) -> String {
+    match *ty {
+        TK::OPE(c) => return format!("'{}'", c),
+        TK::END_LINE => return "';'".to_string(),
+        TK::WHILE => return "'while'".to_string(),
+        _ => return format!("{:?}", ty),
+    }
+}
+
+// Builds a "<what> expected" error and puts the offending token back, so that
+// recovery starts skipping from it.
+fn unexpected(token: Token, what: &str, tokens: &mut Vec<Token>) -> CompileError {
+    let err = CompileError::new(ErrorKind::Parse, token.span.clone(), format!("{} expected, but got {}", what, token.val));
+    tokens.push(token);
+    return err
+}
+
+fn expect(ty: TK, tokens: &mut Vec<Token>) -> Result<(), CompileError> {
     let token = tokens.pop();
-    if token.is_some() && token.unwrap().ty != ty {
-        unreachable!("{:?} expected, but not", ty);
+    if let Some(t) = token {
+        if t.ty != ty {
+            return Err(unexpected(t, &describe(&ty), tokens));
+        }
     }
-    return
+    return Ok(())
 }
 
 fn consume(ope: TK, tokens: &mut Vec<Token>) -> bool {
@@ -32,294 +52,527 @@ fn consume(ope: TK, tokens: &mut Vec<Token>) -> bool {
     }
 }
 
-fn is_typename(tokens: &Vec<Token>) -> bool {
-    return tokens[tokens.len()-1].ty==TK::INT || tokens[tokens.len()-1].ty==TK::CHAR
+fn peek_span(tokens: &Vec<Token>) -> Span {
+    return tokens[tokens.len()-1].span.clone()
 }
 
-fn primary(tokens: &mut Vec<Token>) -> Node {
+fn is_arith_specifier(ty: &TK) -> bool {
+    match *ty {
+        TK::INT | TK::CHAR | TK::SHORT | TK::LONG | TK::SIGNED | TK::UNSIGNED | TK::BOOL | TK::FLOAT | TK::DOUBLE => return true,
+        _ => return false,
+    }
+}
+
+fn is_typename(tokens: &[Token]) -> bool {
+    let ty = &tokens[tokens.len()-1].ty;
+    return is_arith_specifier(ty) || *ty == TK::STRUCT || *ty == TK::UNION
+}
+
+fn primary(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
     let token = tokens.pop().unwrap();
 
     match token.ty {
         TK::OPE('(') => {
-            let node = assign(tokens);
-            expect(TK::OPE(')'), tokens);
-            return node
+            let node = expr(tokens)?;
+            expect(TK::OPE(')'), tokens)?;
+            return Ok(node)
         },
         TK::NUM => {
-            return Node{ op: ND::NUM, val: token.val, ..Default::default()};
+            // The lexer has checked the literal already.
+            if is_float_literal(&token.val) {
+                let (fnum, ty) = float_literal(&token.val).unwrap();
+                return Ok(Node{ op: ND::NUM, fnum, ty, span: token.span, ..Default::default()});
+            }
+            let (num, ty) = int_literal(&token.val).unwrap();
+            return Ok(Node{ op: ND::NUM, num: num as i64, ty, span: token.span, ..Default::default()});
         },
         TK::IDENT => {
-            let mut node = Node{ op: ND::IDENT, val: token.val, ..Default::default()};
+            let mut node = Node{ op: ND::IDENT, val: token.val, span: token.span, ..Default::default()};
             if !consume(TK::OPE('('), tokens) {
-                return node
+                return Ok(node)
             }
             node.op = ND::CALL;
-            if consume(TK::OPE(')'), tokens) {return node}
-            node.args.push(assign(tokens));
+            if consume(TK::OPE(')'), tokens) {return Ok(node)}
+            node.args.push(assign(tokens)?);
             while consume(TK::OPE(','), tokens) {
-                node.args.push(assign(tokens));
+                node.args.push(assign(tokens)?);
             }
-            expect(TK::OPE(')'), tokens);
-            return node
+            expect(TK::OPE(')'), tokens)?;
+            return Ok(node)
         },
-        TK::STR => {
-            return Node{ op: ND::STR, str: token.str.clone(), ty: Type::new_char().ary_of(token.str.len()), ..Default::default()}
+        TK::CHAR_CONST => {
+            // Character constants are ints; char is signed, so '\377' is -1.
+            let c = unescape(&token.val[1..token.val.len() - 1])[0] as i8;
+            return Ok(Node{ op: ND::NUM, num: c as i64, span: token.span, ..Default::default()});
         },
-        _ => {
-            unreachable!("number expected, but got {}", token.val);
+        TK::STR => {
+            // Adjacent literals are one string: "ab" "c" is "abc".
+            let mut str = unescape(&token.str);
+            while tokens.last().map(|t| t.ty == TK::STR).unwrap_or(false) {
+                str.extend(unescape(&tokens.pop().unwrap().str));
+            }
+            let ty = Type::new_char().ary_of(str.len() + 1);
+            return Ok(Node{ op: ND::STR, str, ty, span: token.span, ..Default::default()})
         },
+        _ => return Err(unexpected(token, "number", tokens)),
     }
 }
 
-fn postfix(tokens: &mut Vec<Token>) -> Node {
-    let mut lhs = primary(tokens);
-    while consume(TK::OPE('['), tokens) {
-        lhs = new_expr(ND::DEREF, new_binop(ND::OPE('+'), lhs, assign(tokens)));
-        expect(TK::OPE(']'), tokens);
+fn postfix(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    let mut lhs = primary(tokens)?;
+    loop {
+        let span = peek_span(tokens);
+        if consume(TK::OPE('['), tokens) {
+            lhs = new_expr(ND::DEREF, new_binop(ND::OPE('+'), lhs, expr(tokens)?, span.clone()), span);
+            expect(TK::OPE(']'), tokens)?;
+        } else if consume(TK::INC, tokens) {
+            lhs = new_expr(ND::POST_INC, lhs, span);
+        } else if consume(TK::DEC, tokens) {
+            lhs = new_expr(ND::POST_DEC, lhs, span);
+        } else if tokens.last().map(|t| t.ty == TK::OPE('.') || t.ty == TK::ARROW).unwrap_or(false) {
+            if tokens.pop().unwrap().ty == TK::ARROW {
+                lhs = new_expr(ND::DEREF, lhs, span.clone());
+            }
+            let token = tokens.pop().unwrap();
+            if token.ty != TK::IDENT { return Err(unexpected(token, "member name", tokens)) }
+            lhs = Node{ op: ND::DOT, val: token.val, expr: Some(Box::new(lhs)), span: token.span, ..Default::default()};
+        } else {
+            break
+        }
     }
-    return lhs
+    return Ok(lhs)
 }
 
-fn unary(tokens: &mut Vec<Token>) -> Node {
+fn new_assign_op(op: ND, lhs: Node, rhs: Node, span: Span) -> Node {
+    return new_binop(ND::ASSIGN_OP(Box::new(op)), lhs, rhs, span)
+}
+
+fn unary(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    let span = peek_span(tokens);
     if consume(TK::OPE('*'), tokens) {
-        return new_expr(ND::DEREF, mul(tokens))
+        return Ok(new_expr(ND::DEREF, unary(tokens)?, span))
     } else if consume(TK::OPE('&'), tokens) {
-        return new_expr(ND::ADDR, mul(tokens))
+        return Ok(new_expr(ND::ADDR, unary(tokens)?, span))
+    } else if consume(TK::OPE('-'), tokens) {
+        return Ok(new_expr(ND::NEG, unary(tokens)?, span))
+    } else if consume(TK::OPE('+'), tokens) {
+        return unary(tokens)
+    } else if consume(TK::OPE('!'), tokens) {
+        return Ok(new_expr(ND::NOT, unary(tokens)?, span))
+    } else if consume(TK::OPE('~'), tokens) {
+        return Ok(new_expr(ND::BITNOT, unary(tokens)?, span))
+    } else if tokens.last().map(|t| t.ty == TK::INC || t.ty == TK::DEC).unwrap_or(false) {
+        // `++x` is `x += 1` and `--x` is `x -= 1`.
+        let op = if tokens.pop().unwrap().ty == TK::INC { ND::OPE('+') } else { ND::OPE('-') };
+        let one = Node{op: ND::NUM, num: 1, span: span.clone(), ..Default::default()};
+        return Ok(new_assign_op(op, unary(tokens)?, one, span))
+    } else if tokens[tokens.len()-1].ty == TK::OPE('(') && tokens.len() > 1 && is_typename(&tokens[..tokens.len()-1]) {
+        tokens.pop();
+        let ty = get_type(tokens)?;
+        expect(TK::OPE(')'), tokens)?;
+        return Ok(Node{ op: ND::CAST, ty, expr: Some(Box::new(unary(tokens)?)), span, ..Default::default()})
     } else if consume(TK::SIZEOF, tokens) {
-        return new_expr(ND::SIZEOF, unary(tokens))
+        // `sizeof(type)` leaves the size to sema, which knows the struct types.
+        if tokens[tokens.len()-1].ty == TK::OPE('(') && tokens.len() > 1 && is_typename(&tokens[..tokens.len()-1]) {
+            tokens.pop();
+            let ty = get_type(tokens)?;
+            let ty = read_array(ty, tokens)?;
+            expect(TK::OPE(')'), tokens)?;
+            return Ok(Node{ op: ND::SIZEOF, ty, span, ..Default::default()})
+        }
+        return Ok(new_expr(ND::SIZEOF, unary(tokens)?, span))
     } else {
         return postfix(tokens)
     }
 }
 
-fn mul(tokens: &mut Vec<Token>) -> Node {
-    let mut lhs = unary(tokens);
+// One level of left-associative binary operators. Each entry maps a token to
+// the node it builds; `swap` entries build it with the operands reversed.
+fn binary(tokens: &mut Vec<Token>, ops: &[(TK, ND, bool)], next: fn(&mut Vec<Token>) -> Result<Node, CompileError>) -> Result<Node, CompileError> {
+    let mut lhs = next(tokens)?;
     loop {
-        let token = tokens.pop().unwrap();
-        match token.ty {
-            TK::OPE(o) => {
-                match o {
-                    '*' | '/' => lhs = new_binop(ND::OPE(o), lhs, unary(tokens)),
-                    _ => {
-                        tokens.push(token);
-                        break
-                    },
-                }
-            },
-            _ => {
-                tokens.push(token);
-                break
+        let op = match tokens.last() {
+            Some(t) => ops.iter().find(|o| o.0 == t.ty).cloned(),
+            None => None,
+        };
+        match op {
+            Some((_, nd, swap)) => {
+                let span = tokens.pop().unwrap().span;
+                let rhs = next(tokens)?;
+                lhs = if swap { new_binop(nd, rhs, lhs, span) } else { new_binop(nd, lhs, rhs, span) };
             },
+            None => return Ok(lhs),
         }
     }
-    return lhs;
 }
 
-fn add(tokens: &mut Vec<Token>) -> Node {
-    let mut lhs = mul(tokens);
-    loop {
-        let token = tokens.pop().unwrap();
-        match token.ty {
-            TK::OPE(o) => {
-                match o {
-                    '+' | '-' => lhs = new_binop(ND::OPE(o), lhs, mul(tokens)),
-                    _ => {
-                        tokens.push(token);
-                        break
-                    },
-                }
-            },
-            _ => {
-                tokens.push(token);
-                break
-            },
-        }
-    }
-    return lhs;
+fn mul(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::OPE('*'), ND::OPE('*'), false), (TK::OPE('/'), ND::OPE('/'), false), (TK::OPE('%'), ND::OPE('%'), false)], unary)
 }
 
-fn rel(tokens: &mut Vec<Token>) -> Node {
-    let mut lhs = add(tokens);
-    loop {
-        let token = tokens.pop();
-        match token {
-            Some(t) => {
-                let op = t.clone().ty;
-                match op {
-                    TK::OPE('<') => {
-                        lhs = new_binop(ND::OPE('<'), lhs, add(tokens));
-                    },
-                    TK::OPE('>') => {
-                        lhs = new_binop(ND::OPE('<'), add(tokens), lhs);
-                    },
-                    _ => {
-                        tokens.push(t);
-                        break
-                    },
-                }
-            },
-            None => break,
-        }
-    }
-    return lhs;
+fn add(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::OPE('+'), ND::OPE('+'), false), (TK::OPE('-'), ND::OPE('-'), false)], mul)
 }
 
-fn logand(tokens: &mut Vec<Token>) -> Node {
-    let mut lhs = rel(tokens);
-    loop {
-        let token = tokens.pop();
-        match token {
-            Some(t) => {
-                let op = t.clone().ty;
-                match op {
-                    TK::LOGAND => {
-                        lhs = new_binop(ND::LOGAND, lhs, rel(tokens));
-                    },                    _ => {
-                        tokens.push(t);
-                        break
-                    },
-                }
-            },
-            None => break,
-        }
-    }
-    return lhs;
+fn shift(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::SHL, ND::SHL, false), (TK::SHR, ND::SHR, false)], add)
 }
 
-fn logor(tokens: &mut Vec<Token>) -> Node {
-    let mut lhs = logand(tokens);
-    loop {
-        let token = tokens.pop();
-        match token {
-            Some(t) => {
-                let op = t.clone().ty;
-                match op {
-                    TK::LOGOR => {
-                        lhs = new_binop(ND::LOGOR, lhs, logand(tokens));
-                    },                    _ => {
-                        tokens.push(t);
-                        break
-                    },
-                }
-            },
-            None => break,
-        }
+fn rel(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::OPE('<'), ND::OPE('<'), false), (TK::OPE('>'), ND::OPE('<'), true),
+                            (TK::LE, ND::LE, false), (TK::GE, ND::LE, true)], shift)
+}
+
+fn equality(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::EQ, ND::EQ, false), (TK::NE, ND::NE, false)], rel)
+}
+
+fn bitand(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::OPE('&'), ND::OPE('&'), false)], equality)
+}
+
+fn bitxor(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::OPE('^'), ND::OPE('^'), false)], bitand)
+}
+
+fn bitor(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::OPE('|'), ND::OPE('|'), false)], bitxor)
+}
+
+fn logand(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::LOGAND, ND::LOGAND, false)], bitor)
+}
+
+fn logor(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::LOGOR, ND::LOGOR, false)], logand)
+}
+
+fn conditional(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    let cond = logor(tokens)?;
+    let span = peek_span(tokens);
+    if !consume(TK::OPE('?'), tokens) {
+        return Ok(cond);
     }
-    return lhs;
+    let mut node = Node{op: ND::TERNARY, cond: Some(Box::new(cond)), span, ..Default::default()};
+    node.then = Some(Box::new(expr(tokens)?));
+    expect(TK::OPE(':'), tokens)?;
+    node.els = Some(Box::new(conditional(tokens)?));
+    return Ok(node);
 }
 
-fn assign(tokens: &mut Vec<Token>) -> Node {
-    let lhs = logor(tokens);
+// Assignment is right-associative: `a = b = c` is `a = (b = c)`.
+fn assign(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    let lhs = conditional(tokens)?;
+    let span = peek_span(tokens);
     if consume(TK::OPE('='), tokens) {
-        return new_binop(ND::OPE('='), lhs, logor(tokens));
-    } else {
-        return lhs;
+        return Ok(new_binop(ND::OPE('='), lhs, assign(tokens)?, span));
+    }
+    let ops = [(TK::ADD_EQ, ND::OPE('+')), (TK::SUB_EQ, ND::OPE('-')), (TK::MUL_EQ, ND::OPE('*')), (TK::DIV_EQ, ND::OPE('/')),
+               (TK::MOD_EQ, ND::OPE('%')), (TK::AND_EQ, ND::OPE('&')), (TK::OR_EQ, ND::OPE('|')), (TK::XOR_EQ, ND::OPE('^')),
+               (TK::SHL_EQ, ND::SHL), (TK::SHR_EQ, ND::SHR)];
+    let op = match tokens.last() {
+        Some(t) => ops.iter().find(|o| o.0 == t.ty).map(|o| o.1.clone()),
+        None => None,
+    };
+    if let Some(op) = op {
+        tokens.pop();
+        return Ok(new_assign_op(op, lhs, assign(tokens)?, span));
     }
+    return Ok(lhs);
 }
 
-fn get_type(tokens: &mut Vec<Token>) -> Type {
-    let token = tokens.pop().unwrap();
-    let mut ty = match token.ty {
-        TK::INT => Type { ..Default::default() },
-        TK::CHAR => Type::new_char(),
-        _ => unreachable!("typename expected, but got {:?}", token.ty),
+fn expr(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    return binary(tokens, &[(TK::OPE(','), ND::COMMA, false)], assign)
+}
+
+fn get_type(tokens: &mut Vec<Token>) -> Result<Type, CompileError> {
+    let mut ty = if is_arith_specifier(&tokens[tokens.len()-1].ty) {
+        arith_type(tokens)?
+    } else {
+        let token = tokens.pop().unwrap();
+        match token.ty {
+            TK::STRUCT | TK::UNION => struct_type(token.ty == TK::UNION, tokens)?,
+            _ => return Err(unexpected(token, "typename", tokens)),
+        }
     };
     while consume(TK::OPE('*'), tokens) {
         ty = ty.ptr_of();
     }
-    return ty
+    return Ok(ty)
+}
+
+// Arithmetic type specifiers may come in any order, as in `long unsigned int`.
+fn arith_type(tokens: &mut Vec<Token>) -> Result<Type, CompileError> {
+    let span = peek_span(tokens);
+    let mut specs = Vec::new();
+    while is_arith_specifier(&tokens[tokens.len()-1].ty) {
+        specs.push(tokens.pop().unwrap().ty);
+    }
+    let n = |ty: TK| specs.iter().filter(|&t| *t == ty).count();
+    let sign = n(TK::SIGNED) + n(TK::UNSIGNED);
+    if sign > 1 {
+        error!(Parse, span, "invalid combination of type specifiers");
+    }
+    if n(TK::FLOAT) + n(TK::DOUBLE) > 0 {
+        if specs.len() == 2 && n(TK::DOUBLE) == 1 && n(TK::LONG) == 1 {
+            error!(Parse, span, "long double is not supported");
+        }
+        if specs.len() > 1 {
+            error!(Parse, span, "invalid combination of type specifiers");
+        }
+        let ty = if n(TK::FLOAT) == 1 { TY::FLOAT } else { TY::DOUBLE };
+        return Ok(Type{ty, ..Default::default()})
+    }
+    let ty = match (n(TK::CHAR), n(TK::SHORT), n(TK::INT), n(TK::LONG), n(TK::BOOL)) {
+        (1, 0, 0, 0, 0) => TY::CHAR,
+        (0, 1, 0..=1, 0, 0) => TY::SHORT,
+        (0, 0, 0..=1, 0, 0) => TY::INT,
+        (0, 0, 0..=1, 1, 0) => TY::LONG,
+        (0, 0, 0..=1, 2, 0) => TY::LLONG,
+        (0, 0, 0, 0, 1) if sign == 0 => TY::BOOL,
+        _ => error!(Parse, span, "invalid combination of type specifiers"),
+    };
+    return Ok(Type{ty, is_unsigned: n(TK::UNSIGNED) > 0, ..Default::default()})
+}
+
+// `struct tag`, `struct tag { members }` or `struct { members }`. Tags are
+// left for sema to resolve, as it knows their scopes.
+fn struct_type(is_union: bool, tokens: &mut Vec<Token>) -> Result<Type, CompileError> {
+    let mut ty = Type{ty: TY::STRUCT, is_union, ..Default::default()};
+    if tokens[tokens.len()-1].ty == TK::IDENT {
+        ty.tag = tokens.pop().unwrap().val;
+    }
+    if !consume(TK::OPE('{'), tokens) {
+        if ty.tag.is_empty() {
+            let token = tokens.pop().unwrap();
+            return Err(unexpected(token, "'{'", tokens))
+        }
+        return Ok(ty)
+    }
+    let mut members = Vec::new();
+    while !consume(TK::OPE('}'), tokens) {
+        let mty = get_type(tokens)?;
+        let token = tokens.pop().unwrap();
+        if token.ty != TK::IDENT { return Err(unexpected(token, "member name", tokens)) }
+        let mty = read_array(mty, tokens)?;
+        expect(TK::END_LINE, tokens)?;
+        members.push(Member{name: token.val, ty: mty, offset: 0});
+    }
+    ty.members = Some(members);
+    return Ok(ty)
 }
 
-fn read_array(mut ty: Type, tokens: &mut Vec<Token>) -> Type {
-    let mut ary_size: Vec<Node> = Vec::new();
+fn read_array(mut ty: Type, tokens: &mut Vec<Token>) -> Result<Type, CompileError> {
+    let mut ary_size: Vec<usize> = Vec::new();
     while consume(TK::OPE('['), tokens) {
-        let len = primary(tokens);
-        if len.op != ND::NUM {unreachable!("number expected")}
-        ary_size.push(len);
-        expect(TK::OPE(']'), tokens);
+        // Only the outermost length may be left out, as in `int a[][2]`.
+        if ary_size.is_empty() && consume(TK::OPE(']'), tokens) {
+            ary_size.push(0);
+            continue
+        }
+        let len = primary(tokens)?;
+        if len.op != ND::NUM { error!(Parse, len.span, "number expected") }
+        ary_size.push(len.num as usize);
+        expect(TK::OPE(']'), tokens)?;
+    }
+    // `int a[2][3]` is an array of 2 arrays of 3 ints.
+    for len in ary_size.into_iter().rev() {
+        ty = ty.ary_of(len);
     }
-    for len in ary_size {
-        ty = ty.ary_of(len.val.parse().unwrap());
+    return Ok(ty)
+}
+
+// An initializer is an expression, or a braced list of initializers with an
+// optional trailing comma.
+fn initializer(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    let span = peek_span(tokens);
+    if !consume(TK::OPE('{'), tokens) {
+        return assign(tokens)
+    }
+    let mut node = Node{ op: ND::INIT_LIST, span, ..Default::default()};
+    while !consume(TK::OPE('}'), tokens) {
+        node.args.push(initializer(tokens)?);
+        if !consume(TK::OPE(','), tokens) {
+            expect(TK::OPE('}'), tokens)?;
+            break
+        }
     }
-    return ty
+    return Ok(node)
 }
 
-fn decl(tokens: &mut Vec<Token>) -> Node {
+fn decl(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    let mut node = Node { op: ND::VARDEF, ..Default::default()};
+    // Read the storage class.
+    loop {
+        if consume(TK::STATIC, tokens) {
+            node.is_static = true;
+        } else if consume(TK::EXTERN, tokens) {
+            node.is_extern = true;
+        } else {
+            break
+        }
+    }
     // Read the first half of type name (e.g. `int *`).
-    let mut node = Node { op: ND::VARDEF, ty: get_type(tokens), ..Default::default()};
+    node.span = peek_span(tokens);
+    node.ty = get_type(tokens)?;
+    // `struct tag { ... };` declares only the type.
+    if consume(TK::END_LINE, tokens) {
+        return Ok(node)
+    }
     // Read an identifier.
     let token = tokens.pop().unwrap();
-    if token.ty != TK::IDENT { unreachable!("variable name expected, but got {}", token.val)}
+    if token.ty != TK::IDENT { return Err(unexpected(token, "variable name", tokens)) }
     node.val = token.val;
+    node.span = token.span;
     // Read the second half of type name (e.g. `[3][5]`).
-    node.ty = read_array(node.ty.clone(), tokens);
+    node.ty = read_array(node.ty.clone(), tokens)?;
     // Read an initializer.
-    if consume(TK::OPE('='), tokens) {node.init = Some(Box::new(assign(tokens)));}
-    expect(TK::END_LINE, tokens);
+    if consume(TK::OPE('='), tokens) {node.init = Some(Box::new(initializer(tokens)?));}
+    expect(TK::END_LINE, tokens)?;
 
-    return node
+    return Ok(node)
 }
 
-fn param(tokens: &mut Vec<Token>) -> Node {
-    let mut node = Node { op: ND::VARDEF, ty: get_type(tokens), ..Default::default()};
+fn param(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    let span = peek_span(tokens);
+    let mut node = Node { op: ND::VARDEF, ty: get_type(tokens)?, span, ..Default::default()};
+    // Names are optional in prototypes; `function` checks definitions.
+    if tokens.last().map(|t| t.ty == TK::OPE(',') || t.ty == TK::OPE(')')).unwrap_or(false) {
+        return Ok(node)
+    }
     let token = tokens.pop().unwrap();
-    if token.ty != TK::IDENT { unreachable!("parameter name expected, but got {}", token.val); }
+    if token.ty != TK::IDENT { return Err(unexpected(token, "parameter name", tokens)) }
     node.val = token.val;
-    return node
+    node.span = token.span;
+    return Ok(node)
 }
 
-fn expr_stmt(tokens: &mut Vec<Token>) -> Node {
-    let node = new_expr(ND::EXPR_STMT, assign(tokens));
-    expect(TK::END_LINE, tokens);
-    return node;
+fn expr_stmt(tokens: &mut Vec<Token>) -> Result<Node, CompileError> {
+    let span = peek_span(tokens);
+    let node = new_expr(ND::EXPR_STMT, expr(tokens)?, span);
+    expect(TK::END_LINE, tokens)?;
+    return Ok(node);
 }
 
-fn stmt(tokens: &mut Vec<Token>) -> Node {
+fn stmt(tokens: &mut Vec<Token>, errors: &mut Vec<CompileError>) -> Result<Node, CompileError> {
     let token = tokens.pop().unwrap();
-    let mut node = Node { op: ND::EXPR_STMT, ..Default::default()};
+    let mut node = Node { op: ND::EXPR_STMT, span: token.span.clone(), ..Default::default()};
 
     match token.ty {
-        TK::INT | TK::CHAR => {
+        TK::STRUCT | TK::UNION | TK::STATIC | TK::EXTERN => {
+            tokens.push(token);
+            decl(tokens)
+        },
+        _ if is_arith_specifier(&token.ty) => {
             tokens.push(token);
             decl(tokens)
         },
         TK::IF => {
             node.op = ND::IF;
-            expect(TK::OPE('('), tokens);
-            node.cond = Some(Box::new(assign(tokens)));
-            expect(TK::OPE(')'), tokens);
-            node.then = Some(Box::new(stmt(tokens)));
-            if consume(TK::ELSE, tokens) {node.els = Some(Box::new(stmt(tokens)));}
-            return node
+            expect(TK::OPE('('), tokens)?;
+            node.cond = Some(Box::new(expr(tokens)?));
+            expect(TK::OPE(')'), tokens)?;
+            node.then = Some(Box::new(stmt(tokens, errors)?));
+            if consume(TK::ELSE, tokens) {node.els = Some(Box::new(stmt(tokens, errors)?));}
+            return Ok(node)
         },
         TK::OPE('{') => {
             node.op = ND::COMP_STMT;
-            while !consume(TK::OPE('}'), tokens) {
-                node.stmts.push(stmt(tokens));
-            }
-            return node;
+            node.stmts = block_items(tokens, errors);
+            return Ok(node);
         },
+        // Every clause of a for is optional; a missing condition is always true.
         TK::FOR => {
             node.op = ND::FOR;
-            expect(TK::OPE('('), tokens);
+            expect(TK::OPE('('), tokens)?;
             if is_typename(tokens) {
-                node.init = Some(Box::new(decl(tokens)));
-            } else {
-                node.init = Some(Box::new(expr_stmt(tokens)));
+                node.init = Some(Box::new(decl(tokens)?));
+            } else if !consume(TK::END_LINE, tokens) {
+                node.init = Some(Box::new(expr_stmt(tokens)?));
             }
-            node.cond = Some(Box::new(assign(tokens)));
-            expect(TK::END_LINE, tokens);
-            node.inc = Some(Box::new(assign(tokens)));
-            expect(TK::OPE(')'), tokens);
-            node.body = Some(Box::new(stmt(tokens)));
-            return node;
+            if tokens[tokens.len()-1].ty != TK::END_LINE {
+                node.cond = Some(Box::new(expr(tokens)?));
+            }
+            expect(TK::END_LINE, tokens)?;
+            if tokens[tokens.len()-1].ty != TK::OPE(')') {
+                node.inc = Some(Box::new(expr(tokens)?));
+            }
+            expect(TK::OPE(')'), tokens)?;
+            node.body = Some(Box::new(stmt(tokens, errors)?));
+            return Ok(node);
+        },
+        // `while (c) s` is `for (; c;) s`.
+        TK::WHILE => {
+            node.op = ND::FOR;
+            expect(TK::OPE('('), tokens)?;
+            node.cond = Some(Box::new(expr(tokens)?));
+            expect(TK::OPE(')'), tokens)?;
+            node.body = Some(Box::new(stmt(tokens, errors)?));
+            return Ok(node);
+        },
+        TK::DO => {
+            node.op = ND::DO_WHILE;
+            node.body = Some(Box::new(stmt(tokens, errors)?));
+            expect(TK::WHILE, tokens)?;
+            expect(TK::OPE('('), tokens)?;
+            node.cond = Some(Box::new(expr(tokens)?));
+            expect(TK::OPE(')'), tokens)?;
+            expect(TK::END_LINE, tokens)?;
+            return Ok(node);
+        },
+        TK::BREAK | TK::CONTINUE => {
+            node.op = if token.ty == TK::BREAK { ND::BREAK } else { ND::CONTINUE };
+            expect(TK::END_LINE, tokens)?;
+            return Ok(node)
+        },
+        TK::GOTO => {
+            node.op = ND::GOTO;
+            let label = tokens.pop().unwrap();
+            if label.ty != TK::IDENT { return Err(unexpected(label, "label name", tokens)) }
+            node.val = label.val;
+            expect(TK::END_LINE, tokens)?;
+            return Ok(node)
+        },
+        TK::SWITCH => {
+            node.op = ND::SWITCH;
+            expect(TK::OPE('('), tokens)?;
+            node.cond = Some(Box::new(expr(tokens)?));
+            expect(TK::OPE(')'), tokens)?;
+            node.body = Some(Box::new(stmt(tokens, errors)?));
+            return Ok(node)
+        },
+        TK::CASE => {
+            node.op = ND::CASE;
+            node.expr = Some(Box::new(conditional(tokens)?));
+            expect(TK::OPE(':'), tokens)?;
+            node.body = Some(Box::new(stmt(tokens, errors)?));
+            return Ok(node)
+        },
+        TK::DEFAULT => {
+            node.op = ND::DEFAULT;
+            expect(TK::OPE(':'), tokens)?;
+            node.body = Some(Box::new(stmt(tokens, errors)?));
+            return Ok(node)
+        },
+        TK::IDENT if tokens[tokens.len()-1].ty == TK::OPE(':') => {
+            tokens.pop();
+            node.op = ND::LABEL;
+            node.val = token.val;
+            node.body = Some(Box::new(stmt(tokens, errors)?));
+            return Ok(node)
+        },
+        // A lone `;` is an empty statement.
+        TK::END_LINE => {
+            node.op = ND::COMP_STMT;
+            return Ok(node)
         },
         TK::RETURN => {
             node.op = ND::RETURN;
-            node.expr = Some(Box::new(assign(tokens)));
-            expect(TK::END_LINE, tokens);
-            return node
+            node.expr = Some(Box::new(expr(tokens)?));
+            expect(TK::END_LINE, tokens)?;
+            return Ok(node)
         },
+        TK::EOF => return Err(unexpected(token, "statement", tokens)),
         _ => {
             tokens.push(token);
             return expr_stmt(tokens)
@@ -327,59 +580,142 @@ fn stmt(tokens: &mut Vec<Token>) -> Node {
     }
 }
 
-fn compound_stmt(tokens: &mut Vec<Token>) -> Node{
-    let mut node = Node{ op: ND::COMP_STMT, ..Default::default()};
+// Skips the rest of a broken statement: up to and including the next `;` or
+// balanced `{ ... }`, or up to a `}` closing the enclosing block.
+fn synchronize(tokens: &mut Vec<Token>) {
+    let mut depth = 0;
+    loop {
+        match tokens[tokens.len()-1].ty {
+            TK::EOF => return,
+            TK::OPE('{') => depth += 1,
+            TK::OPE('}') => {
+                if depth == 0 { return }
+                depth -= 1;
+                if depth == 0 {
+                    tokens.pop();
+                    return
+                }
+            },
+            TK::END_LINE => {
+                if depth == 0 {
+                    tokens.pop();
+                    return
+                }
+            },
+            _ => {},
+        }
+        tokens.pop();
+    }
+}
+
+// Reads statements up to the closing `}`. A statement that fails to parse is
+// recorded in `errors` and skipped, so that one typo does not hide the rest.
+fn block_items(tokens: &mut Vec<Token>, errors: &mut Vec<CompileError>) -> Vec<Node> {
+    let mut stmts = Vec::new();
     while !consume(TK::OPE('}'), tokens) {
-        let optoken = tokens.pop();
-        if optoken.is_none() {return node}
-        let token = optoken.unwrap();
-        if token.ty == TK::EOF { return node }
-        tokens.push(token);
-        node.stmts.push(stmt(tokens));
+        if tokens[tokens.len()-1].ty == TK::EOF {
+            errors.push(CompileError::new(ErrorKind::Parse, peek_span(tokens), "'}' expected, but got EOF".to_string()));
+            return stmts
+        }
+        match stmt(tokens, errors) {
+            Ok(node) => stmts.push(node),
+            Err(e) => {
+                let at_eof = tokens[tokens.len()-1].ty == TK::EOF;
+                errors.push(e);
+                if at_eof { return stmts }
+                synchronize(tokens);
+            },
+        }
     }
-    return node
+    return stmts
 }
 
-fn function(tokens: &mut Vec<Token>) -> Node{
-    // 関数宣言の最初のintは読み飛ばす
-    let t = tokens.pop().unwrap();
-    if t.ty != TK::INT {
-        unreachable!("function return type expected, but got {}", t.val);
+fn compound_stmt(tokens: &mut Vec<Token>, errors: &mut Vec<CompileError>) -> Node {
+    let span = peek_span(tokens);
+    return Node{ op: ND::COMP_STMT, stmts: block_items(tokens, errors), span, ..Default::default()}
+}
+
+fn function(tokens: &mut Vec<Token>, errors: &mut Vec<CompileError>) -> Result<Node, CompileError> {
+    if !is_typename(tokens) {
+        let t = tokens.pop().unwrap();
+        return Err(unexpected(t, "function return type", tokens));
     }
+    let ty = get_type(tokens)?;
 
     let token = tokens.pop().unwrap();
-    if token.ty != TK::IDENT {unreachable!("function name expected, but got {}", token.val)}
-    expect(TK::OPE('('), tokens);
-    let mut node = Node{ op: ND::FUNC, val: token.val, ..Default::default()};
+    if token.ty != TK::IDENT { return Err(unexpected(token, "function name", tokens)) }
+    expect(TK::OPE('('), tokens)?;
+    let mut node = Node{ op: ND::FUNC, val: token.val, ty, span: token.span, ..Default::default()};
     if !consume(TK::OPE(')'), tokens) {
-        node.args.push(param(tokens));
+        node.args.push(param(tokens)?);
         while consume(TK::OPE(','), tokens){
-            node.args.push(param(tokens));
+            if consume(TK::ELLIPSIS, tokens) {
+                node.is_variadic = true;
+                break
+            }
+            node.args.push(param(tokens)?);
         }
-        expect(TK::OPE(')'), tokens);
+        expect(TK::OPE(')'), tokens)?;
     }
 
-    expect(TK::OPE('{'), tokens);
-    node.body = Some(Box::new(compound_stmt(tokens)));
-    return node;
+    // A prototype, which only declares the return type.
+    if consume(TK::END_LINE, tokens) {
+        return Ok(node)
+    }
+    expect(TK::OPE('{'), tokens)?;
+    node.body = Some(Box::new(compound_stmt(tokens, errors)));
+    if let Some(arg) = node.args.iter().find(|arg| arg.val.is_empty()) {
+        error!(Parse, arg.span, "parameter name omitted");
+    }
+    return Ok(node);
+}
+
+// A top-level declaration is a function if its name is followed by `(`.
+fn is_function(tokens: &[Token]) -> bool {
+    let mut i = tokens.len();
+    while i > 1 {
+        i -= 1;
+        match tokens[i].ty {
+            TK::STATIC | TK::EXTERN | TK::OPE('*') => {},
+            ref ty if is_arith_specifier(ty) => {},
+            // Skip the tag; a member list makes it a type or variable declaration.
+            TK::STRUCT | TK::UNION => if tokens[i - 1].ty == TK::IDENT { i -= 1 },
+            TK::IDENT => return tokens[i - 1].ty == TK::OPE('('),
+            _ => return false,
+        }
+    }
+    return false
 }
 
-pub fn parse(tokens: &mut Vec<Token>) -> Vec<Node> {
+// Parses the whole translation unit, reporting every syntax error found
+// rather than only the first.
+pub fn parse(tokens: &mut Vec<Token>) -> Result<Vec<Node>, Vec<CompileError>> {
     let mut nodes = Vec::new();
-    let mut token = tokens.pop().unwrap();
-    while token.ty != TK::EOF {
-        tokens.push(token);
-        nodes.push(function(tokens));
-        token = tokens.pop().unwrap();
+    let mut errors = Vec::new();
+    while tokens[tokens.len()-1].ty != TK::EOF {
+        let node = if is_function(tokens) { function(tokens, &mut errors) } else { decl(tokens) };
+        match node {
+            Ok(node) => nodes.push(node),
+            Err(e) => {
+                errors.push(e);
+                synchronize(tokens);
+                consume(TK::OPE('}'), tokens);
+            },
+        }
     }
-    return nodes
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+    return Ok(nodes)
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use self::rugcc::common::{TY};
+    use self::rugcc::common::{TY, ErrorKind, SourceFile};
+    use token::tokenize;
+    use std::rc::Rc;
     # [test]
     fn can_parse_arithmetic_expr() {
         let input = [
@@ -394,7 +730,7 @@ mod tests {
             Token { ty: TK::OPE(')'), val: ")".to_string(), ..Default::default() }, Token { ty: TK::OPE('('), val: "(".to_string(), ..Default::default() },
             Token { ty: TK::IDENT, val: "main".to_string(), ..Default::default() }, Token { ty: TK::INT, val: "int".to_string(), ..Default::default() }
         ];
-        let result = parse(&mut input.to_vec());
+        let result = parse(&mut input.to_vec()).unwrap();
 
         let expect = [
             Node {
@@ -413,24 +749,24 @@ mod tests {
                                         op: ND::OPE('+'),
                                         lhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "2".to_string(), ..Default::default() })),
+                                            num: 2, ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::OPE('*'),
                                             lhs: Some(Box::new(Node {
                                                 op: ND::NUM,
-                                                val: "2".to_string(), ..Default::default()})),
+                                                num: 2, ..Default::default()})),
                                             rhs: Some(Box::new(Node {
                                                 op: ND::NUM,
-                                                val: "3".to_string(), ..Default::default()})),
+                                                num: 3, ..Default::default()})),
                                             ..Default::default()})),
                                         ..Default::default()})),
                                     rhs: Some(Box::new(Node {
                                         op: ND::NUM,
-                                        val: "2".to_string(), ..Default::default()
+                                        num: 2, ..Default::default()
                                          })),  ..Default::default()})),
                                 rhs: Some(Box::new(Node {
                                     op: ND::NUM,
-                                    val: "1".to_string(), ..Default::default() })),
+                                    num: 1, ..Default::default() })),
                                 ..Default::default()
                                 })),
                             ..Default::default()
@@ -464,7 +800,7 @@ mod tests {
             Token { ty: TK::IDENT, val: "add".to_string(), ..Default::default() }, Token { ty: TK::INT, val: "int".to_string(), ..Default::default() }
         ];
 
-        let result = parse(&mut input.to_vec());
+        let result = parse(&mut input.to_vec()).unwrap();
 
         let expect = [
             Node {
@@ -501,8 +837,8 @@ mod tests {
                                 op: ND::CALL,
                                 val: "add".to_string(),
                                 args: [
-                                    Node { op: ND::NUM, val: "1".to_string(), ..Default::default()},
-                                    Node { op: ND::NUM, val: "2".to_string(), ..Default::default() }
+                                    Node { op: ND::NUM, num: 1, ..Default::default()},
+                                    Node { op: ND::NUM, num: 2, ..Default::default() }
                                 ].to_vec(), ..Default::default() })),
                             ..Default::default() }
                     ].to_vec(),
@@ -540,7 +876,7 @@ mod tests {
             Token { ty: TK::IDENT, val: "main".to_string(), ..Default::default() }, Token { ty: TK::INT, val: "int".to_string(), ..Default::default() }
         ];
 
-        let result = parse(&mut input.to_vec());
+        let result = parse(&mut input.to_vec()).unwrap();
         
         let expect = [
             Node {
@@ -566,7 +902,7 @@ mod tests {
                                     ..Default::default() })),
                                 rhs: Some(Box::new(Node {
                                     op: ND::NUM,
-                                    val: "3".to_string(), ..Default::default() })), ..Default::default()})),
+                                    num: 3, ..Default::default() })), ..Default::default()})),
                             ..Default::default()},
                         Node {
                             op: ND::EXPR_STMT,
@@ -581,11 +917,11 @@ mod tests {
                                             val: "ary".to_string(), ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "1".to_string(),
+                                            num: 1,
                                             ..Default::default()})),
                                         ..Default::default()})),
                                     ..Default::default()})),
-                                rhs: Some(Box::new(Node { op: ND::NUM, val: "7".to_string(), ..Default::default() })),
+                                rhs: Some(Box::new(Node { op: ND::NUM, num: 7, ..Default::default() })),
                                 ..Default::default()})),
                             ..Default::default()},
                         Node {
@@ -607,7 +943,7 @@ mod tests {
                                             val: "ary".to_string(), ..Default::default()})),
                                         rhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "1".to_string(), ..Default::default() })),
+                                            num: 1, ..Default::default() })),
                                         ..Default::default() })),
                                     ..Default::default() })),
                                 ..Default::default() })),
@@ -622,4 +958,189 @@ mod tests {
         }
     }
 
+    # [test]
+    fn cannot_parse_missing_semicolon() {
+        let input = [
+            Token { ty: TK::EOF, val: "EOF".to_string(), ..Default::default() }, Token { ty: TK::OPE('}'), val: "}".to_string(), ..Default::default() },
+            Token { ty: TK::NUM, val: "1".to_string(), ..Default::default() }, Token { ty: TK::RETURN, val: "return".to_string(), ..Default::default() },
+            Token { ty: TK::OPE('{'), val: "{".to_string(), ..Default::default() }, Token { ty: TK::OPE(')'), val: ")".to_string(), ..Default::default() },
+            Token { ty: TK::OPE('('), val: "(".to_string(), ..Default::default() }, Token { ty: TK::IDENT, val: "main".to_string(), ..Default::default() },
+            Token { ty: TK::INT, val: "int".to_string(), ..Default::default() }
+        ];
+
+        let result = parse(&mut input.to_vec());
+
+        let errs = result.unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].kind, ErrorKind::Parse);
+        assert_eq!(errs[0].msg, "';' expected, but got }");
+    }
+
+    // Renders an expression as an s-expression to check how it was grouped.
+    fn sexp(node: &Node) -> String {
+        let sub = |n: &Option<Box<Node>>| sexp(n.as_ref().unwrap());
+        match node.op {
+            ND::NUM => return node.num.to_string(),
+            ND::IDENT => return node.val.clone(),
+            ND::ASSIGN_OP(ref op) => return format!("({:?}= {} {})", op, sub(&node.lhs), sub(&node.rhs)),
+            ND::TERNARY => return format!("(? {} {} {})", sub(&node.cond), sub(&node.then), sub(&node.els)),
+            ND::NEG | ND::NOT | ND::BITNOT | ND::POST_INC | ND::POST_DEC | ND::DEREF =>
+                return format!("({:?} {})", node.op, sub(&node.expr)),
+            ND::DOT => return format!("(. {} {})", sub(&node.expr), node.val),
+            _ => return format!("({:?} {} {})", node.op, sub(&node.lhs), sub(&node.rhs)),
+        }
+    }
+
+    # [test]
+    fn can_parse_struct_types() {
+        let src = "struct P { int x; struct P *next; char s[2]; } p; union { int i; char c; } u; \
+                   struct P f(struct P a); int main() { struct P; return p.x + a->next->s[1] + sizeof(struct P *); }";
+        let mut input = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+
+        let result = parse(&mut input).unwrap();
+
+        let p = &result[0].ty;
+        assert_eq!((p.ty.clone(), &p.tag[..], p.is_union), (TY::STRUCT, "P", false));
+        let members = p.members.as_ref().unwrap();
+        assert_eq!(members.iter().map(|m| &m.name[..]).collect::<Vec<&str>>(), ["x", "next", "s"]);
+        // Tags are only resolved by sema.
+        assert_eq!(members[1].ty.ptr_of.as_ref().unwrap().members, None);
+        assert!(result[1].ty.is_union && result[1].ty.tag.is_empty());
+        // A prototype has no body.
+        assert_eq!((result[2].op.clone(), result[2].ty.tag.clone(), result[2].body.is_none()), (ND::FUNC, "P".to_string(), true));
+        let stmts = &result[3].body.as_ref().unwrap().stmts;
+        assert!(stmts[0].op == ND::VARDEF && stmts[0].val.is_empty());
+        let ret = stmts[1].expr.as_ref().unwrap();
+        assert_eq!(sexp(ret.lhs.as_ref().unwrap()), "(OPE('+') (. p x) (DEREF (OPE('+') (. (DEREF (. (DEREF a) next)) s) 1)))");
+        let size = ret.rhs.as_ref().unwrap();
+        assert!(size.op == ND::SIZEOF && size.expr.is_none() && size.ty.ty == TY::PTR);
+    }
+
+    # [test]
+    fn can_parse_integer_types() {
+        let src = "unsigned a; long unsigned int b; short int c; signed char d; long long e; _Bool f; unsigned short g; \
+                   int main() { return (long)(unsigned char *)a; }";
+        let mut input = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+
+        let result = parse(&mut input).unwrap();
+
+        let tys: Vec<(TY, bool)> = result[..7].iter().map(|n| (n.ty.ty.clone(), n.ty.is_unsigned)).collect();
+        assert_eq!(tys, [(TY::INT, true), (TY::LONG, true), (TY::SHORT, false), (TY::CHAR, false),
+                         (TY::LLONG, false), (TY::BOOL, false), (TY::SHORT, true)]);
+        let cast = result[7].body.as_ref().unwrap().stmts[0].expr.as_ref().unwrap();
+        assert_eq!((cast.op.clone(), cast.ty.ty.clone()), (ND::CAST, TY::LONG));
+        let inner = cast.expr.as_ref().unwrap();
+        assert_eq!((inner.op.clone(), inner.ty.ptr_of.as_ref().unwrap().is_unsigned), (ND::CAST, true));
+
+        for src in &["short long x;", "unsigned signed x;", "char int x;", "long long long x;", "unsigned _Bool x;"] {
+            let mut input = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+            assert_eq!(parse(&mut input).unwrap_err()[0].msg, "invalid combination of type specifiers");
+        }
+    }
+
+    # [test]
+    fn can_parse_floating_types() {
+        let src = "float a; double b; int printf(char *, ...); double f(float x) { return x * 2.5e1 + .5f; }";
+        let mut input = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+
+        let result = parse(&mut input).unwrap();
+
+        assert_eq!((result[0].ty.ty.clone(), result[1].ty.ty.clone()), (TY::FLOAT, TY::DOUBLE));
+        assert!(result[2].is_variadic && result[2].args.len() == 1);
+        assert!(!result[3].is_variadic);
+        let add = result[3].body.as_ref().unwrap().stmts[0].expr.as_ref().unwrap();
+        let mul = add.lhs.as_ref().unwrap();
+        let (lit, flit) = (mul.rhs.as_ref().unwrap(), add.rhs.as_ref().unwrap());
+        assert_eq!((lit.fnum, lit.ty.ty.clone()), (25.0, TY::DOUBLE));
+        assert_eq!((flit.fnum, flit.ty.ty.clone()), (0.5, TY::FLOAT));
+
+        for &(src, msg) in &[("long double x;", "long double is not supported"), ("unsigned float x;", "invalid combination of type specifiers"),
+                             ("int double x;", "invalid combination of type specifiers"), ("int f(...);", "typename expected, but got ..."),
+                             ("int f(int) { return 0; }", "parameter name omitted")] {
+            let mut input = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+            assert_eq!(parse(&mut input).unwrap_err()[0].msg, msg);
+        }
+    }
+
+    # [test]
+    fn can_parse_global_declarations() {
+        let src = "static int a[][2] = {{1, 2}, {3,},}; extern char *s; int main() { static int n = 1; return n; }";
+        let mut input = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+
+        let result = parse(&mut input).unwrap();
+
+        assert_eq!(result.iter().map(|n| n.op.clone()).collect::<Vec<ND>>(), [ND::VARDEF, ND::VARDEF, ND::FUNC]);
+        let a = &result[0];
+        assert!(a.is_static && !a.is_extern);
+        // The outer length is left for the initializer to fill in.
+        assert_eq!(a.ty, Type::default().ary_of(2).ary_of(0));
+        let init = a.init.as_ref().unwrap();
+        assert_eq!(init.op, ND::INIT_LIST);
+        assert_eq!(init.args.iter().map(|l| l.args.len()).collect::<Vec<usize>>(), [2, 1]);
+        assert!(result[1].is_extern && result[1].init.is_none());
+        assert!(result[2].body.as_ref().unwrap().stmts[0].is_static);
+    }
+
+    # [test]
+    fn can_parse_operator_precedence() {
+        let src = "int main() { return a -= b || c && d | e ^ f & g != h < i << j + k % -l++; a > b; a >= b; !~--*p; }";
+        let mut input = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+
+        let result = parse(&mut input).unwrap();
+
+        let stmts = &result[0].body.as_ref().unwrap().stmts;
+        let exprs: Vec<String> = stmts.iter().map(|s| sexp(s.expr.as_ref().unwrap())).collect();
+        assert_eq!(exprs, [
+            "(OPE('-')= a (LOGOR b (LOGAND c (OPE('|') d (OPE('^') e (OPE('&') f (NE g (OPE('<') h (SHL i (OPE('+') j (OPE('%') k (NEG (POST_INC l)))))))))))))",
+            "(OPE('<') b a)",
+            "(LE b a)",
+            "(NOT (BITNOT (OPE('-')= (DEREF p) 1)))",
+        ]);
+    }
+
+    # [test]
+    fn can_parse_assignment_chains() {
+        let src = "int main() { a = b += c = 3; a ? b : c ? d : e; x = a, b ? c, d : e; }";
+        let mut input = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+
+        let result = parse(&mut input).unwrap();
+
+        let stmts = &result[0].body.as_ref().unwrap().stmts;
+        let exprs: Vec<String> = stmts.iter().map(|s| sexp(s.expr.as_ref().unwrap())).collect();
+        assert_eq!(exprs, [
+            "(OPE('=') a (OPE('+')= b (OPE('=') c 3)))",
+            "(? a b (? c d e))",
+            "(COMMA (OPE('=') x a) (? b (COMMA c d) e))",
+        ]);
+    }
+
+    # [test]
+    fn can_report_every_broken_statement() {
+        // int main() { a = ; b = 1 c = 2; { d = ; } return ) ; }
+        let input = [
+            Token { ty: TK::EOF, val: "EOF".to_string(), ..Default::default() }, Token { ty: TK::OPE('}'), val: "}".to_string(), ..Default::default() },
+            Token { ty: TK::END_LINE, val: ";".to_string(), ..Default::default() }, Token { ty: TK::OPE(')'), val: ")".to_string(), ..Default::default() },
+            Token { ty: TK::RETURN, val: "return".to_string(), ..Default::default() }, Token { ty: TK::OPE('}'), val: "}".to_string(), ..Default::default() },
+            Token { ty: TK::END_LINE, val: ";".to_string(), ..Default::default() }, Token { ty: TK::OPE('='), val: "=".to_string(), ..Default::default() },
+            Token { ty: TK::IDENT, val: "d".to_string(), ..Default::default() }, Token { ty: TK::OPE('{'), val: "{".to_string(), ..Default::default() },
+            Token { ty: TK::END_LINE, val: ";".to_string(), ..Default::default() }, Token { ty: TK::NUM, val: "2".to_string(), ..Default::default() },
+            Token { ty: TK::OPE('='), val: "=".to_string(), ..Default::default() }, Token { ty: TK::IDENT, val: "c".to_string(), ..Default::default() },
+            Token { ty: TK::NUM, val: "1".to_string(), ..Default::default() }, Token { ty: TK::OPE('='), val: "=".to_string(), ..Default::default() },
+            Token { ty: TK::IDENT, val: "b".to_string(), ..Default::default() }, Token { ty: TK::END_LINE, val: ";".to_string(), ..Default::default() },
+            Token { ty: TK::OPE('='), val: "=".to_string(), ..Default::default() }, Token { ty: TK::IDENT, val: "a".to_string(), ..Default::default() },
+            Token { ty: TK::OPE('{'), val: "{".to_string(), ..Default::default() }, Token { ty: TK::OPE(')'), val: ")".to_string(), ..Default::default() },
+            Token { ty: TK::OPE('('), val: "(".to_string(), ..Default::default() }, Token { ty: TK::IDENT, val: "main".to_string(), ..Default::default() },
+            Token { ty: TK::INT, val: "int".to_string(), ..Default::default() }
+        ];
+
+        let result = parse(&mut input.to_vec());
+
+        let msgs: Vec<String> = result.unwrap_err().iter().map(|e| e.msg.clone()).collect();
+        assert_eq!(msgs, [
+            "number expected, but got ;",
+            "';' expected, but got c",
+            "number expected, but got ;",
+            "number expected, but got )",
+        ]);
+    }
 }
@@ -0,0 +1,874 @@
+extern crate rugcc;
+use self::rugcc::common::{TK, Token, SourceFile, Span, CompileError};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use token::{int_literal, tokenize, unescape};
+
+// Headers shipped with rugcc, searched after the -I directories. They are
+// built into the binary, so it does not depend on where the source was.
+const BUILTIN_HEADERS: [(&str, &str); 3] = [
+    ("stddef.h", include_str!("../include/stddef.h")),
+    ("stdio.h", include_str!("../include/stdio.h")),
+    ("stdlib.h", include_str!("../include/stdlib.h")),
+];
+
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+#[derive(Clone, Debug)]
+struct Macro {
+    params: Option<Vec<String>>,  // None for object-like macros
+    body: Vec<Token>,
+}
+
+// A token on its way through the preprocessor.
+#[derive(Clone, Debug)]
+struct PPToken {
+    tok: Token,
+    bol: bool,          // first token of a line in its source file
+    hide: Vec<String>,  // macros this token came out of, which must not expand it again
+}
+
+// One #if ... #endif group.
+struct Cond {
+    active: bool,   // the current branch is compiled
+    taken: bool,    // some branch of the group has been compiled
+    in_else: bool,
+    span: Span,
+}
+
+pub struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    include_dirs: Vec<String>,
+    depth: usize,
+}
+
+fn same_file(a: &Span, b: &Span) -> bool {
+    match (a.file.as_ref(), b.file.as_ref()) {
+        (Some(x), Some(y)) => return Rc::ptr_eq(x, y),
+        _ => return false,
+    }
+}
+
+// Whether whitespace separates `b` from the token `a` before it.
+fn has_space(a: &Token, b: &Token) -> bool {
+    return !same_file(&a.span, &b.span) || a.span.offset + a.span.len != b.span.offset
+}
+
+// Whether a line break that is not a backslash-newline lies between `a` and
+// `b`. Line breaks inside a block comment don't count.
+fn newline_between(a: &Token, b: &Token) -> bool {
+    let file = match b.span.file {
+        Some(ref file) if same_file(&a.span, &b.span) => file,
+        _ => return true,
+    };
+    let gap = &file.text.as_bytes()[a.span.offset + a.span.len..b.span.offset];
+    let mut i = 0;
+    while i < gap.len() {
+        if gap[i..].starts_with(b"//") {
+            while i < gap.len() && gap[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if gap[i..].starts_with(b"/*") {
+            i += 2;
+            while !gap[i..].starts_with(b"*/") {
+                i += 1;
+            }
+            i += 2;
+            continue;
+        }
+        if gap[i] == b'\n' && (i == 0 || gap[i - 1] != b'\\') {
+            return true;
+        }
+        i += 1;
+    }
+    return false
+}
+
+// The stack of a file's tokens, next token last, ending with its EOF.
+fn read_tokens(file: Rc<SourceFile>) -> Result<Vec<PPToken>, CompileError> {
+    let mut tokens = tokenize(file)?;
+    tokens.reverse();
+    let mut stack = Vec::with_capacity(tokens.len());
+    for i in 0..tokens.len() {
+        let bol = i == 0 || newline_between(&tokens[i - 1], &tokens[i]);
+        stack.push(PPToken{tok: tokens[i].clone(), bol, hide: Vec::new()});
+    }
+    stack.reverse();
+    return Ok(stack)
+}
+
+fn spelling(tok: &Token) -> String {
+    if tok.ty == TK::STR {
+        return format!("\"{}\"", tok.str);
+    }
+    return tok.val.clone()
+}
+
+// Empty stand-in for an empty macro argument next to ##. Dropped after expansion.
+fn placemarker(span: &Span) -> PPToken {
+    return PPToken{tok: Token{ty: TK::IDENT, val: String::new(), span: span.clone(), ..Default::default()}, bol: false, hide: Vec::new()}
+}
+
+fn is_placemarker(t: &PPToken) -> bool {
+    return t.tok.ty == TK::IDENT && t.tok.val.is_empty()
+}
+
+// `#param`: the argument's spelling as a string literal.
+fn stringize(arg: &[PPToken], span: &Span) -> PPToken {
+    let mut text = String::new();
+    for (i, t) in arg.iter().enumerate() {
+        if i > 0 && has_space(&arg[i - 1].tok, &t.tok) {
+            text.push(' ');
+        }
+        let s = spelling(&t.tok);
+        if t.tok.ty == TK::STR || t.tok.ty == TK::CHAR_CONST {
+            text.push_str(&s.replace('\\', "\\\\").replace('"', "\\\""));
+        } else {
+            text.push_str(&s);
+        }
+    }
+    return PPToken{tok: Token{ty: TK::STR, val: "\"".to_string(), str: text, span: span.clone()}, bol: false, hide: Vec::new()}
+}
+
+// `a ## b`: the single token spelled by both together.
+fn paste(lhs: PPToken, rhs: PPToken) -> Result<PPToken, CompileError> {
+    if is_placemarker(&lhs) {
+        return Ok(rhs);
+    }
+    if is_placemarker(&rhs) {
+        return Ok(lhs);
+    }
+    let text = spelling(&lhs.tok) + &spelling(&rhs.tok);
+    let file = Rc::new(SourceFile{name: "<paste>".to_string(), text: text.clone()});
+    match tokenize(file) {
+        Ok(ref tokens) if tokens.len() == 2 => {
+            let mut tok = tokens[1].clone();
+            tok.span = lhs.tok.span.clone();
+            return Ok(PPToken{tok, bol: false, hide: lhs.hide})
+        },
+        _ => error!(Preprocess, lhs.tok.span, "pasting \"{}\" and \"{}\" does not give a valid token", spelling(&lhs.tok), spelling(&rhs.tok)),
+    }
+}
+
+fn is_ident_spelling(s: &str) -> bool {
+    return s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// #if arithmetic is done in intmax_t or uintmax_t, which are 64 bits. Values
+// carry whether they are unsigned.
+type Value = (i64, bool);
+
+fn parse_int(tok: &Token) -> Result<Value, CompileError> {
+    match int_literal(&tok.val) {
+        Ok((n, ty)) => return Ok((n as i64, ty.is_unsigned)),
+        Err(msg) => error!(Preprocess, tok.span, "{}", msg),
+    }
+}
+
+// Constant expressions of #if, after `defined` and macros are replaced.
+// `live` is false in operands that are not evaluated, such as the right of
+// `0 && ...`, where division by zero is not an error.
+struct CondExpr<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    span: Span,
+}
+
+impl<'a> CondExpr<'a> {
+    fn peek(&self) -> Option<&TK> {
+        return self.tokens.get(self.pos).map(|t| &t.ty)
+    }
+
+    fn consume(&mut self, ty: TK) -> bool {
+        if self.peek() == Some(&ty) {
+            self.pos += 1;
+            return true;
+        }
+        return false
+    }
+
+    fn ternary(&mut self, live: bool) -> Result<Value, CompileError> {
+        let cond = self.binary(0, live)?;
+        if !self.consume(TK::OPE('?')) {
+            return Ok(cond);
+        }
+        let cond = cond.0;
+        let then = self.ternary(live && cond != 0)?;
+        if !self.consume(TK::OPE(':')) {
+            error!(Preprocess, self.span, "':' expected in #if");
+        }
+        let els = self.ternary(live && cond == 0)?;
+        let unsigned = then.1 || els.1;
+        return Ok(if cond != 0 { (then.0, unsigned) } else { (els.0, unsigned) })
+    }
+
+    // Binary operators by precedence level, loosest first.
+    fn binary(&mut self, level: usize, live: bool) -> Result<Value, CompileError> {
+        let levels: [&[TK]; 10] = [
+            &[TK::LOGOR], &[TK::LOGAND], &[TK::OPE('|')], &[TK::OPE('^')], &[TK::OPE('&')],
+            &[TK::EQ, TK::NE], &[TK::OPE('<'), TK::OPE('>'), TK::LE, TK::GE], &[TK::SHL, TK::SHR],
+            &[TK::OPE('+'), TK::OPE('-')], &[TK::OPE('*'), TK::OPE('/'), TK::OPE('%')],
+        ];
+        if level == levels.len() {
+            return self.unary(live);
+        }
+        let mut lhs = self.binary(level + 1, live)?;
+        loop {
+            let op = match self.peek() {
+                Some(ty) if levels[level].contains(ty) => ty.clone(),
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs_live = live && match op { TK::LOGOR => lhs.0 == 0, TK::LOGAND => lhs.0 != 0, _ => true };
+            let (r, r_unsigned) = self.binary(level + 1, rhs_live)?;
+            let (l, l_unsigned) = lhs;
+            // Either operand being unsigned makes both unsigned, except
+            // that a shift has the type of its left operand.
+            let unsigned = l_unsigned || r_unsigned;
+            let (ul, ur) = (l as u64, r as u64);
+            lhs = match op {
+                TK::LOGOR => ((l != 0 || r != 0) as i64, false),
+                TK::LOGAND => ((l != 0 && r != 0) as i64, false),
+                TK::OPE('|') => (l | r, unsigned),
+                TK::OPE('^') => (l ^ r, unsigned),
+                TK::OPE('&') => (l & r, unsigned),
+                TK::EQ => ((l == r) as i64, false),
+                TK::NE => ((l != r) as i64, false),
+                TK::OPE('<') => (if unsigned { ul < ur } else { l < r } as i64, false),
+                TK::OPE('>') => (if unsigned { ul > ur } else { l > r } as i64, false),
+                TK::LE => (if unsigned { ul <= ur } else { l <= r } as i64, false),
+                TK::GE => (if unsigned { ul >= ur } else { l >= r } as i64, false),
+                TK::SHL => (l.wrapping_shl(r as u32), l_unsigned),
+                TK::SHR if l_unsigned => (ul.wrapping_shr(r as u32) as i64, true),
+                TK::SHR => (l.wrapping_shr(r as u32), false),
+                TK::OPE('+') => (l.wrapping_add(r), unsigned),
+                TK::OPE('-') => (l.wrapping_sub(r), unsigned),
+                TK::OPE('*') => (l.wrapping_mul(r), unsigned),
+                _ if r == 0 => {
+                    if live { error!(Preprocess, self.span, "division by zero in #if"); }
+                    (0, unsigned)
+                },
+                TK::OPE('/') if unsigned => ((ul / ur) as i64, true),
+                TK::OPE('/') => (l.wrapping_div(r), false),
+                _ if unsigned => ((ul % ur) as i64, true),
+                _ => (l.wrapping_rem(r), false),
+            };
+        }
+    }
+
+    fn unary(&mut self, live: bool) -> Result<Value, CompileError> {
+        if self.consume(TK::OPE('+')) { return self.unary(live); }
+        if self.consume(TK::OPE('-')) {
+            let (n, unsigned) = self.unary(live)?;
+            return Ok((n.wrapping_neg(), unsigned));
+        }
+        if self.consume(TK::OPE('!')) { return Ok(((self.unary(live)?.0 == 0) as i64, false)); }
+        if self.consume(TK::OPE('~')) {
+            let (n, unsigned) = self.unary(live)?;
+            return Ok((!n, unsigned));
+        }
+        if self.consume(TK::OPE('(')) {
+            let n = self.ternary(live)?;
+            if !self.consume(TK::OPE(')')) {
+                error!(Preprocess, self.span, "')' expected in #if");
+            }
+            return Ok(n);
+        }
+        let tok = match self.tokens.get(self.pos) {
+            Some(tok) => tok.clone(),
+            None => error!(Preprocess, self.span, "#if expression ends unexpectedly"),
+        };
+        self.pos += 1;
+        match tok.ty {
+            TK::NUM => return parse_int(&tok),
+            TK::CHAR_CONST => return Ok((unescape(&tok.val[1..tok.val.len() - 1])[0] as i8 as i64, false)),
+            // Identifiers left over after macro expansion are 0, keywords
+            // included, as the preprocessor doesn't know them.
+            _ if is_ident_spelling(&tok.val) => return Ok((0, false)),
+            _ => error!(Preprocess, tok.span, "token \"{}\" is not valid in #if", spelling(&tok)),
+        }
+    }
+}
+
+impl Preprocessor {
+    pub fn new(include_dirs: Vec<String>) -> Preprocessor {
+        let mut pp = Preprocessor{macros: HashMap::new(), include_dirs, depth: 0};
+        for name in &["__STDC__", "__rugcc__", "__x86_64__"] {
+            pp.define(name).unwrap();
+        }
+        return pp
+    }
+
+    // -D: `NAME`, `NAME=VALUE` or `NAME(args)=VALUE`.
+    pub fn define(&mut self, def: &str) -> Result<(), CompileError> {
+        let text = match def.find('=') {
+            Some(i) => format!("{} {}", &def[..i], &def[i + 1..]),
+            None => format!("{} 1", def),
+        };
+        let mut stack = read_tokens(Rc::new(SourceFile{name: "<command line>".to_string(), text}))?;
+        stack.remove(0);
+        stack.reverse();
+        return self.define_macro(&stack)
+    }
+
+    // -U
+    pub fn undef(&mut self, name: &str) {
+        self.macros.remove(name);
+    }
+
+    // -dM: every macro in effect, as #define lines sorted by name.
+    pub fn dump_macros(&self) -> String {
+        let mut names: Vec<&String> = self.macros.keys().collect();
+        names.sort();
+        let mut out = String::new();
+        for name in names {
+            let m = &self.macros[name];
+            out.push_str("#define ");
+            out.push_str(name);
+            if let Some(ref params) = m.params {
+                out.push_str(&format!("({})", params.join(", ")));
+            }
+            for (i, tok) in m.body.iter().enumerate() {
+                if i == 0 || has_space(&m.body[i - 1], tok) {
+                    out.push(' ');
+                }
+                out.push_str(&spelling(tok));
+            }
+            out.push('\n');
+        }
+        return out
+    }
+
+    // Runs the preprocessor over a file, giving tokens in the form
+    // `tokenize` does: last token first, EOF at the front.
+    pub fn preprocess(&mut self, file: Rc<SourceFile>) -> Result<Vec<Token>, CompileError> {
+        let mut stack = read_tokens(file)?;
+        let mut out = Vec::new();
+        self.expand(&mut stack, &mut out, true)?;
+        let mut tokens: Vec<Token> = out.into_iter().filter(|t| !is_placemarker(t)).map(|t| t.tok).collect();
+        tokens.push(stack.pop().unwrap().tok);
+        tokens.reverse();
+        return Ok(tokens)
+    }
+
+    // Expands macros in `stack` until it is empty or reaches an EOF, which
+    // is left on the stack. Directives are only run for file tokens.
+    fn expand(&mut self, stack: &mut Vec<PPToken>, out: &mut Vec<PPToken>, directives: bool) -> Result<(), CompileError> {
+        let mut conds: Vec<Cond> = Vec::new();
+        while let Some(t) = stack.pop() {
+            if t.tok.ty == TK::EOF {
+                stack.push(t);
+                break;
+            }
+            if directives && t.bol && t.tok.ty == TK::OPE('#') {
+                self.directive(t, stack, out, &mut conds)?;
+                continue;
+            }
+            if conds.iter().any(|c| !c.active) {
+                continue;
+            }
+            if t.tok.ty == TK::IDENT && self.expand_macro(&t, stack)? {
+                continue;
+            }
+            out.push(t);
+        }
+        if let Some(c) = conds.last() {
+            error!(Preprocess, c.span, "unterminated conditional directive");
+        }
+        return Ok(())
+    }
+
+    // Replaces a macro use at the top of the stack with its expansion,
+    // which is then rescanned. Returns false if `t` is not a macro use.
+    fn expand_macro(&mut self, t: &PPToken, stack: &mut Vec<PPToken>) -> Result<bool, CompileError> {
+        let name = &t.tok.val;
+        if t.hide.contains(name) {
+            return Ok(false);
+        }
+        let builtin = match name.as_str() {
+            "__LINE__" => Some(Token{ty: TK::NUM, val: t.tok.span.line.to_string(), ..Default::default()}),
+            "__FILE__" => {
+                let file = t.tok.span.file.as_ref().map(|f| f.name.clone()).unwrap_or_default();
+                Some(Token{ty: TK::STR, val: "\"".to_string(), str: file.replace('\\', "\\\\").replace('"', "\\\""), ..Default::default()})
+            },
+            _ => None,
+        };
+        if let Some(mut tok) = builtin {
+            tok.span = t.tok.span.clone();
+            stack.push(PPToken{tok, bol: false, hide: t.hide.clone()});
+            return Ok(true);
+        }
+        let m = match self.macros.get(name) {
+            Some(m) => m.clone(),
+            None => return Ok(false),
+        };
+        let mut body = match m.params {
+            None => m.body.iter().map(|tok| PPToken{tok: tok.clone(), bol: false, hide: Vec::new()}).collect(),
+            Some(ref params) => {
+                // A function-like macro name without arguments is left alone.
+                if stack.last().map(|n| n.tok.ty != TK::OPE('(')).unwrap_or(true) {
+                    return Ok(false);
+                }
+                stack.pop();
+                let args = read_args(t, stack, params.len())?;
+                self.substitute(&m.body, params, args)?
+            },
+        };
+        for b in body.iter_mut() {
+            // Expanded tokens are reported at the macro use.
+            b.tok.span = t.tok.span.clone();
+            b.hide.extend(t.hide.iter().cloned());
+            b.hide.push(name.clone());
+        }
+        stack.extend(body.into_iter().rev());
+        return Ok(true)
+    }
+
+    fn substitute(&mut self, body: &[Token], params: &[String], args: Vec<Vec<PPToken>>) -> Result<Vec<PPToken>, CompileError> {
+        let param = |tok: &Token| if tok.ty == TK::IDENT { params.iter().position(|p| *p == tok.val) } else { None };
+        let mut out: Vec<PPToken> = Vec::new();
+        let mut i = 0;
+        while i < body.len() {
+            let tok = &body[i];
+            if tok.ty == TK::OPE('#') {
+                match body.get(i + 1).and_then(&param) {
+                    Some(n) => out.push(stringize(&args[n], &tok.span)),
+                    None => error!(Preprocess, tok.span, "'#' is not followed by a macro parameter"),
+                }
+                i += 2;
+                continue;
+            }
+            if tok.ty == TK::HASHHASH {
+                let rhs = &body[i + 1];
+                let mut rhs = match param(rhs) {
+                    Some(n) if args[n].is_empty() => vec![placemarker(&rhs.span)],
+                    Some(n) => args[n].clone(),
+                    None => vec![PPToken{tok: rhs.clone(), bol: false, hide: Vec::new()}],
+                };
+                let lhs = out.pop().unwrap();
+                let rest = rhs.split_off(1);
+                out.push(paste(lhs, rhs.pop().unwrap())?);
+                out.extend(rest);
+                i += 2;
+                continue;
+            }
+            if let Some(n) = param(tok) {
+                // Operands of ## are used as written; others are expanded first.
+                if body.get(i + 1).map(|t| t.ty == TK::HASHHASH).unwrap_or(false) {
+                    if args[n].is_empty() {
+                        out.push(placemarker(&tok.span));
+                    } else {
+                        out.extend(args[n].iter().cloned());
+                    }
+                } else {
+                    let mut arg = args[n].clone();
+                    arg.reverse();
+                    self.expand(&mut arg, &mut out, false)?;
+                }
+                i += 1;
+                continue;
+            }
+            out.push(PPToken{tok: tok.clone(), bol: false, hide: Vec::new()});
+            i += 1;
+        }
+        return Ok(out)
+    }
+
+    // `line` is the directive after `#define`: a name, an optional
+    // parameter list and the body.
+    fn define_macro(&mut self, line: &[PPToken]) -> Result<(), CompileError> {
+        let name = match line.first() {
+            Some(t) if t.tok.ty == TK::IDENT => t,
+            Some(t) => error!(Preprocess, t.tok.span, "macro name must be an identifier"),
+            None => error!(Preprocess, Span::default(), "macro name missing"),
+        };
+        let mut rest = &line[1..];
+        let mut params = None;
+        // `NAME(` with no space between is a function-like macro.
+        if rest.first().map(|t| t.tok.ty == TK::OPE('(') && !has_space(&name.tok, &t.tok)).unwrap_or(false) {
+            let mut names = Vec::new();
+            let mut i = 1;
+            if rest.get(i).map(|t| t.tok.ty == TK::OPE(')')).unwrap_or(false) {
+                i += 1;
+            } else {
+                loop {
+                    match rest.get(i) {
+                        Some(t) if t.tok.ty == TK::IDENT => names.push(t.tok.val.clone()),
+                        Some(t) => error!(Preprocess, t.tok.span, "macro parameter name expected, but got {}", spelling(&t.tok)),
+                        None => error!(Preprocess, name.tok.span, "missing ')' in macro parameter list"),
+                    }
+                    i += 1;
+                    match rest.get(i) {
+                        Some(t) if t.tok.ty == TK::OPE(',') => i += 1,
+                        Some(t) if t.tok.ty == TK::OPE(')') => { i += 1; break; },
+                        Some(t) => error!(Preprocess, t.tok.span, "',' or ')' expected in macro parameter list"),
+                        None => error!(Preprocess, name.tok.span, "missing ')' in macro parameter list"),
+                    }
+                }
+            }
+            rest = &rest[i..];
+            params = Some(names);
+        }
+        let body: Vec<Token> = rest.iter().map(|t| t.tok.clone()).collect();
+        for &end in &[body.first(), body.last()] {
+            if let Some(t) = end {
+                if t.ty == TK::HASHHASH {
+                    error!(Preprocess, t.span, "'##' cannot appear at either end of a macro expansion");
+                }
+            }
+        }
+        self.macros.insert(name.tok.val.clone(), Macro{params, body});
+        return Ok(())
+    }
+
+    fn directive(&mut self, hash: PPToken, stack: &mut Vec<PPToken>, out: &mut Vec<PPToken>, conds: &mut Vec<Cond>) -> Result<(), CompileError> {
+        let mut line = Vec::new();
+        while stack.last().map(|t| !t.bol && t.tok.ty != TK::EOF).unwrap_or(false) {
+            line.push(stack.pop().unwrap());
+        }
+        // A lone `#` is a null directive.
+        let name = match line.first() {
+            Some(t) => t.tok.val.clone(),
+            None => return Ok(()),
+        };
+        let skipping = conds.iter().any(|c| !c.active);
+        match name.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                let active = !skipping && self.condition(&name, &line)?;
+                conds.push(Cond{active, taken: active, in_else: false, span: hash.tok.span.clone()});
+            },
+            "elif" | "else" => {
+                let n = conds.len();
+                if n == 0 {
+                    error!(Preprocess, hash.tok.span, "#{} without #if", name);
+                }
+                if conds[n - 1].in_else {
+                    error!(Preprocess, hash.tok.span, "#{} after #else", name);
+                }
+                let parent_active = conds[..n - 1].iter().all(|c| c.active);
+                let active = parent_active && !conds[n - 1].taken && (name == "else" || self.condition("if", &line)?);
+                let c = &mut conds[n - 1];
+                c.active = active;
+                c.taken = c.taken || active;
+                c.in_else = name == "else";
+            },
+            "endif" => {
+                if conds.pop().is_none() {
+                    error!(Preprocess, hash.tok.span, "#endif without #if");
+                }
+            },
+            _ if skipping => {},
+            "define" => self.define_macro(&line[1..])?,
+            "undef" => {
+                match line.get(1) {
+                    Some(t) if t.tok.ty == TK::IDENT => { self.macros.remove(&t.tok.val); },
+                    _ => error!(Preprocess, hash.tok.span, "macro name missing in #undef"),
+                }
+            },
+            "include" => {
+                let file = self.find_include(&hash, &line[1..])?;
+                if self.depth >= MAX_INCLUDE_DEPTH {
+                    error!(Preprocess, hash.tok.span, "#include nested too deeply");
+                }
+                let mut tokens = read_tokens(file)?;
+                self.depth += 1;
+                self.expand(&mut tokens, out, true)?;
+                self.depth -= 1;
+            },
+            "error" => {
+                let msg = match (line.get(1), line.last()) {
+                    (Some(first), Some(last)) if same_file(&first.tok.span, &last.tok.span) => {
+                        let text = &first.tok.span.file.as_ref().unwrap().text;
+                        text[first.tok.span.offset..last.tok.span.offset + last.tok.span.len].to_string()
+                    },
+                    _ => String::new(),
+                };
+                error!(Preprocess, hash.tok.span, "#error {}", msg);
+            },
+            "line" => self.line_directive(&hash, &line, stack)?,
+            "pragma" => {},
+            _ => error!(Preprocess, line[0].tok.span, "invalid preprocessing directive #{}", name),
+        }
+        return Ok(())
+    }
+
+    // `#line N` or `#line N "file"`: renumbers the rest of the file's lines
+    // so the next one is N, and renames it.
+    fn line_directive(&mut self, hash: &PPToken, line: &[PPToken], stack: &mut [PPToken]) -> Result<(), CompileError> {
+        let mut args: Vec<PPToken> = line[1..].iter().rev().cloned().collect();
+        let mut expanded = Vec::new();
+        self.expand(&mut args, &mut expanded, false)?;
+        let n = match expanded.first() {
+            Some(t) if t.tok.ty == TK::NUM && t.tok.val.chars().all(|c| c.is_ascii_digit()) => match t.tok.val.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => error!(Preprocess, t.tok.span, "line number out of range"),
+            },
+            Some(t) => error!(Preprocess, t.tok.span, "\"{}\" after #line is not a positive integer", spelling(&t.tok)),
+            None => error!(Preprocess, hash.tok.span, "#line directive requires a line number"),
+        };
+        let file = match (expanded.get(1), &hash.tok.span.file) {
+            (Some(t), Some(file)) if t.tok.ty == TK::STR && expanded.len() == 2 =>
+                Some(Rc::new(SourceFile{name: t.tok.str.clone(), text: file.text.clone()})),
+            (None, _) => None,
+            (Some(t), _) => error!(Preprocess, t.tok.span, "invalid filename \"{}\" in #line", spelling(&t.tok)),
+        };
+        // The directive may go on over backslash-newlines.
+        let last = line.last().unwrap().tok.span.line;
+        for t in stack.iter_mut().filter(|t| same_file(&t.tok.span, &hash.tok.span)) {
+            t.tok.span.line = n + t.tok.span.line - last - 1;
+            if let Some(ref file) = file {
+                t.tok.span.file = Some(file.clone());
+            }
+        }
+        return Ok(())
+    }
+
+    fn condition(&mut self, name: &str, line: &[PPToken]) -> Result<bool, CompileError> {
+        let span = line[0].tok.span.clone();
+        if name != "if" {
+            match line.get(1) {
+                Some(t) if t.tok.ty == TK::IDENT => return Ok(self.macros.contains_key(&t.tok.val) == (name == "ifdef")),
+                _ => error!(Preprocess, span, "macro name missing in #{}", name),
+            }
+        }
+        // `defined X` and `defined(X)` are replaced before macro expansion.
+        let mut tokens: Vec<PPToken> = Vec::new();
+        let mut i = 1;
+        while i < line.len() {
+            let t = &line[i];
+            if t.tok.ty == TK::IDENT && t.tok.val == "defined" {
+                let paren = line.get(i + 1).map(|t| t.tok.ty == TK::OPE('(')).unwrap_or(false);
+                let ident = if paren { i + 2 } else { i + 1 };
+                let defined = match line.get(ident) {
+                    Some(t) if t.tok.ty == TK::IDENT => self.macros.contains_key(&t.tok.val),
+                    _ => error!(Preprocess, t.tok.span, "macro name missing after 'defined'"),
+                };
+                if paren && line.get(ident + 1).map(|t| t.tok.ty != TK::OPE(')')).unwrap_or(true) {
+                    error!(Preprocess, t.tok.span, "')' expected after 'defined({}'", line[ident].tok.val);
+                }
+                let tok = Token{ty: TK::NUM, val: (defined as i32).to_string(), span: t.tok.span.clone(), ..Default::default()};
+                tokens.push(PPToken{tok, bol: false, hide: Vec::new()});
+                i = if paren { ident + 2 } else { ident + 1 };
+                continue;
+            }
+            tokens.push(t.clone());
+            i += 1;
+        }
+        if tokens.is_empty() {
+            error!(Preprocess, span, "#if with no expression");
+        }
+        tokens.reverse();
+        let mut expanded = Vec::new();
+        self.expand(&mut tokens, &mut expanded, false)?;
+        let expr: Vec<Token> = expanded.into_iter().map(|t| t.tok).collect();
+        let mut e = CondExpr{tokens: &expr, pos: 0, span: span.clone()};
+        let (n, _) = e.ternary(true)?;
+        if e.pos != expr.len() {
+            error!(Preprocess, expr[e.pos].span, "missing binary operator before \"{}\"", spelling(&expr[e.pos]));
+        }
+        return Ok(n != 0)
+    }
+
+    // Resolves `"file"` (beside the including file first) or `<file>`
+    // against -I directories and then rugcc's own headers.
+    fn find_include(&mut self, hash: &PPToken, line: &[PPToken]) -> Result<Rc<SourceFile>, CompileError> {
+        let mut line = line.to_vec();
+        if line.first().map(|t| t.tok.ty == TK::IDENT).unwrap_or(false) {
+            line.reverse();
+            let mut expanded = Vec::new();
+            self.expand(&mut line, &mut expanded, false)?;
+            line = expanded;
+        }
+        let (name, quoted, span) = match line.first() {
+            Some(t) if t.tok.ty == TK::STR && line.len() == 1 => (t.tok.str.clone(), true, t.tok.span.clone()),
+            Some(t) if t.tok.ty == TK::OPE('<') => {
+                let close = line.iter().position(|t| t.tok.ty == TK::OPE('>'));
+                match close {
+                    Some(close) if same_file(&t.tok.span, &line[close].tok.span) && close == line.len() - 1 => {
+                        let text = &t.tok.span.file.as_ref().unwrap().text;
+                        (text[t.tok.span.offset + 1..line[close].tok.span.offset].to_string(), false, t.tok.span.clone())
+                    },
+                    _ => error!(Preprocess, t.tok.span, "expected \"FILENAME\" or <FILENAME>"),
+                }
+            },
+            _ => error!(Preprocess, hash.tok.span, "expected \"FILENAME\" or <FILENAME>"),
+        };
+        let mut dirs: Vec<String> = Vec::new();
+        if quoted {
+            let current = hash.tok.span.file.as_ref().map(|f| f.name.clone()).unwrap_or_default();
+            let dir = Path::new(&current).parent().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+            dirs.push(if dir.is_empty() { ".".to_string() } else { dir });
+        }
+        dirs.extend(self.include_dirs.iter().cloned());
+        for dir in dirs {
+            let path = Path::new(&dir).join(&name);
+            if let Ok(text) = fs::read_to_string(&path) {
+                let name = if dir == "." { name.clone() } else { path.to_string_lossy().into_owned() };
+                return Ok(Rc::new(SourceFile{name, text}));
+            }
+        }
+        if let Some(&(_, text)) = BUILTIN_HEADERS.iter().find(|h| h.0 == name) {
+            return Ok(Rc::new(SourceFile{name: format!("<rugcc>/{}", name), text: text.to_string()}));
+        }
+        error!(Preprocess, span, "'{}' file not found", name);
+    }
+}
+
+// Tokens from one macro use share its span, so spacing between them can't
+// be recovered; they are only separated where they would lex as one.
+fn needs_space(prev: &Token, tok: &Token) -> bool {
+    let (a, b) = (&prev.span, &tok.span);
+    if !same_file(a, b) || a.offset != b.offset || a.len != b.len {
+        return has_space(prev, tok);
+    }
+    let text = spelling(prev) + &spelling(tok);
+    let file = Rc::new(SourceFile{name: String::new(), text});
+    return tokenize(file).map(|t| t.len() != 3).unwrap_or(true)
+}
+
+// Renders preprocessed tokens, as `preprocess` returns them, back into
+// source text for -E. Tokens keep their lines; a `# <line> "<file>"` marker
+// is written when the output enters another file or skips many lines.
+pub fn print_tokens(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    let mut prev: Option<&Token> = None;
+    for tok in tokens.iter().rev().filter(|t| t.ty != TK::EOF) {
+        let line = prev.map(|p| p.span.line).unwrap_or(0);
+        let moved = match prev {
+            Some(p) => !same_file(&p.span, &tok.span) || tok.span.line < line || tok.span.line > line + 8,
+            None => true,
+        };
+        if moved {
+            if prev.is_some() {
+                out.push('\n');
+            }
+            let name = tok.span.file.as_ref().map(|f| f.name.as_str()).unwrap_or("");
+            out.push_str(&format!("# {} \"{}\"\n", tok.span.line, name.replace('\\', "\\\\").replace('"', "\\\"")));
+        } else if tok.span.line > line {
+            out.push_str(&"\n".repeat(tok.span.line - line));
+        }
+        if moved || tok.span.line > line {
+            out.push_str(&" ".repeat(tok.span.col - 1));
+        } else if needs_space(prev.unwrap(), tok) {
+            out.push(' ');
+        }
+        out.push_str(&spelling(tok));
+        prev = Some(tok);
+    }
+    if prev.is_some() {
+        out.push('\n');
+    }
+    return out
+}
+
+// Reads the arguments of a function-like macro up to the closing `)`.
+fn read_args(name: &PPToken, stack: &mut Vec<PPToken>, nparams: usize) -> Result<Vec<Vec<PPToken>>, CompileError> {
+    let mut args: Vec<Vec<PPToken>> = vec![Vec::new()];
+    let mut depth = 0;
+    loop {
+        let t = match stack.pop() {
+            Some(ref t) if t.tok.ty == TK::EOF => {
+                stack.push(t.clone());
+                error!(Preprocess, name.tok.span, "unterminated argument list invoking macro '{}'", name.tok.val);
+            },
+            Some(t) => t,
+            None => error!(Preprocess, name.tok.span, "unterminated argument list invoking macro '{}'", name.tok.val),
+        };
+        match t.tok.ty {
+            TK::OPE('(') => depth += 1,
+            TK::OPE(')') if depth == 0 => break,
+            TK::OPE(')') => depth -= 1,
+            TK::OPE(',') if depth == 0 => {
+                args.push(Vec::new());
+                continue;
+            },
+            _ => {},
+        }
+        args.last_mut().unwrap().push(t);
+    }
+    if nparams == 0 && args.len() == 1 && args[0].is_empty() {
+        args.clear();
+    }
+    if args.len() != nparams {
+        error!(Preprocess, name.tok.span, "macro '{}' requires {} arguments, but {} given", name.tok.val, nparams, args.len());
+    }
+    return Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use self::rugcc::common::ErrorKind;
+
+    fn run(text: &str) -> Result<String, CompileError> {
+        let file = Rc::new(SourceFile{name: "test.c".to_string(), text: text.to_string()});
+        let mut tokens = Preprocessor::new(Vec::new()).preprocess(file)?;
+        tokens.reverse();
+        tokens.pop();
+        return Ok(tokens.iter().map(spelling).collect::<Vec<String>>().join(" "))
+    }
+
+    # [test]
+    fn can_expand_macros() {
+        assert_eq!(run("#define N 3\n#define ADD(a, b) ((a)+(b))\nADD(N, ADD(1, 2))").unwrap(), "( ( 3 ) + ( ( ( 1 ) + ( 2 ) ) ) )");
+        assert_eq!(run("#define F (x)\nF").unwrap(), "( x )");
+        assert_eq!(run("#define F(x) x\nF + F(1)").unwrap(), "F + 1");
+        assert_eq!(run("#define x x+1\nx").unwrap(), "x + 1");
+        assert_eq!(run("#define N 1\n#undef N\nN").unwrap(), "N");
+        assert_eq!(run("#define LONG 1 \\\n + 2\nLONG").unwrap(), "1 + 2");
+        assert_eq!(run("#define C 1 /* a\n b */ + 2 // c /* d\nC").unwrap(), "1 + 2");
+    }
+
+    # [test]
+    fn can_stringize_and_paste() {
+        assert_eq!(run("#define S(x) #x\nS(a  +  \"b\")").unwrap(), "\"a + \\\"b\\\"\"");
+        assert_eq!(run(r#"#define S(x) #x
+S('\\' "\n")"#).unwrap(), r#""'\\\\' \"\\n\"""#);
+        assert_eq!(run("#define CAT(a, b) a ## b\nCAT(foo, 12) CAT(, x) CAT(<, =)").unwrap(), "foo12 x <=");
+        assert_eq!(run("#define CAT(a, b) a ## b\nCAT(+, /)").unwrap_err().kind, ErrorKind::Preprocess);
+    }
+
+    # [test]
+    fn can_evaluate_conditionals() {
+        let text = "#define A 2\n#if A * 2 == 4 && 'a' == 97 && defined(A) && !defined B\nyes\n#elif 1\nno\n#else\nno\n#endif\n\
+                    #ifdef B\nno\n#elif A > 1 ? 1 : 1/0\nelif\n#endif\n#ifndef B\n#if 0\n#error never\n#endif\nok\n#endif";
+        assert_eq!(run(text).unwrap(), "yes elif ok");
+        assert_eq!(run("#if long || while\nno\n#else\nyes\n#endif").unwrap(), "yes");
+        // An unsigned operand makes the other one unsigned too.
+        assert_eq!(run("#if -1 < 0u\nyes\n#else\nno\n#endif").unwrap(), "no");
+        assert_eq!(run("#if 0xFFFFFFFFFFFFFFFF > 0\nyes\n#else\nno\n#endif").unwrap(), "yes");
+        assert_eq!(run("#if -1 >> 63 == -1 && -1u >> 63 == 1 && -7 / 2 == -3 && -2u / 2 > 0\nyes\n#endif").unwrap(), "yes");
+    }
+
+    # [test]
+    fn can_renumber_lines() {
+        assert_eq!(run("#line 10\n__LINE__ __FILE__\n#define F \"foo.c\"\n#line 20 F\n\n__LINE__ __FILE__").unwrap(), "10 \"test.c\" 21 \"foo.c\"");
+        assert_eq!(run("#line x\n").unwrap_err().msg, "\"x\" after #line is not a positive integer");
+        assert_eq!(run("#line 1 2\n").unwrap_err().msg, "invalid filename \"2\" in #line");
+    }
+
+    # [test]
+    fn can_print_tokens_with_line_markers() {
+        let file = Rc::new(SourceFile{name: "test.c".to_string(), text: "#define N 1\nint x =  N;\n\n  x;\n".to_string()});
+        let mut pp = Preprocessor::new(Vec::new());
+        let tokens = pp.preprocess(file).unwrap();
+        assert_eq!(print_tokens(&tokens), "# 2 \"test.c\"\nint x = 1;\n\n  x;\n");
+        pp.define("F(a, b)=a+b * 2").unwrap();
+        assert!(pp.dump_macros().contains("#define F(a, b) a+b * 2\n#define N 1\n"));
+    }
+
+    # [test]
+    fn cannot_preprocess_bad_directives() {
+        let err = run("int x;\n#error stop here\n").unwrap_err();
+        assert_eq!((err.kind, err.msg.as_str(), err.span.line), (ErrorKind::Preprocess, "#error stop here", 2));
+        assert_eq!(run("#if 1\nx\n").unwrap_err().msg, "unterminated conditional directive");
+        assert_eq!(run("#foo\n").unwrap_err().msg, "invalid preprocessing directive #foo");
+        assert_eq!(run("#define F(a) a\nF(1, 2)").unwrap_err().msg, "macro 'F' requires 1 arguments, but 2 given");
+        assert_eq!(run("#include \"no_such_file.h\"").unwrap_err().msg, "'no_such_file.h' file not found");
+        assert_eq!(run("#include <stdlib.h>\nEXIT_FAILURE").unwrap(), "1");
+    }
+}
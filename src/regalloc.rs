@@ -1,71 +1,273 @@
 extern crate rugcc;
-use self::rugcc::common::{IR, IRType, IRInfoType, Function};
-use REGS;
+use self::rugcc::common::{IR, IRType, IRInfoType, Function, CompileError, Span, xmm_operands, align_to};
+use std::collections::{HashMap, HashSet};
+use {REGS, XMMS};
 
-fn alloc(ir_reg: usize, reg_map: &mut Vec<i32>, used: &mut Vec<bool>) -> usize{
-    if reg_map[ir_reg] != -1 {
-        let r = reg_map[ir_reg] as usize;
-        assert!(used[r]);
-        return r;
+// The last two registers are never allocated. They hold spilled values for
+// the duration of the single instruction that uses them.
+const SCRATCH: [usize; 2] = [REGS.len() - 2, REGS.len() - 1];
+const XMM_SCRATCH: [usize; 2] = [XMMS.len() - 2, XMMS.len() - 1];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Loc {
+    Unassigned,
+    Reg(usize),
+    Stack(usize),  // spilled to [rbp-offset]
+}
+
+// The range of instructions over which a virtual register is live.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Interval {
+    vreg: usize,
+    start: usize,
+    end: usize,
+}
+
+// Whether an instruction reads and whether it writes its `lhs` register.
+// `rhs`, when it is a register, is only ever read.
+fn lhs_use_def(op: IRType) -> (bool, bool) {
+    match op {
+        IRType::IMM | IRType::MOV | IRType::LABEL_ADDR | IRType::CALL | IRType::GET_RDX |
+        IRType::LOAD8 | IRType::LOAD16 | IRType::LOAD32 | IRType::LOAD64 |
+        IRType::SLOAD8 | IRType::SLOAD16 | IRType::SLOAD32 |
+        IRType::FIMM | IRType::FMOV | IRType::FLOAD32 | IRType::FLOAD64 | IRType::I2F | IRType::F2I |
+        IRType::FRESULT | IRType::GET_XMM => return (false, true),
+        IRType::STORE8 | IRType::STORE16 | IRType::STORE32 | IRType::STORE64 | IRType::UNLESS | IRType::JMP_TABLE |
+        IRType::RETURN | IRType::ARG | IRType::PUSH_ARG | IRType::SET_RDX |
+        IRType::FSTORE32 | IRType::FSTORE64 | IRType::FARG32 | IRType::FARG64 | IRType::FPUSH_ARG |
+        IRType::FRETURN | IRType::SET_XMM => return (true, false),
+        _ => return (true, true),
     }
+}
 
-    for i in 0..REGS.len() {
-        if used[i] {
-            continue;
-        }
-        used[i] = true;
-        reg_map[ir_reg] = i as i32;
-        return i;
+// Virtual registers read and written by an instruction. r0 (rbp) is left out.
+fn uses_defs(ir: &IR) -> (Vec<usize>, Vec<usize>) {
+    let mut uses = Vec::new();
+    let mut defs = Vec::new();
+    let (is_use, is_def) = lhs_use_def(ir.op);
+    match ir.get_irinfo().ty {
+        IRInfoType::REG | IRInfoType::REG_IMN | IRInfoType::REG_LABEL | IRInfoType::LABEL_ADDR | IRInfoType::CALL => {
+            if is_use { uses.push(ir.lhs); }
+            if is_def { defs.push(ir.lhs); }
+        },
+        IRInfoType::REG_REG => {
+            if is_use { uses.push(ir.lhs); }
+            if is_def { defs.push(ir.lhs); }
+            uses.push(ir.rhs);
+        },
+        _ => {}
     }
-    unreachable!("register exhausted")
+    uses.retain(|&r| r != 0);
+    defs.retain(|&r| r != 0);
+    return (uses, defs)
 }
 
-fn visit(irs: &mut Vec<IR>, reg_map: &mut Vec<i32>, used: &mut Vec<bool>) {
-    for i in 0..irs.len() {
-        let ir = irs[i].clone();
-        let info = ir.get_irinfo();
-        //eprintln!("{:?}", info);
-        match info.ty {
-            IRInfoType::REG | IRInfoType::REG_IMN | IRInfoType::REG_LABEL | IRInfoType::LABEL_ADDR => {
-                irs[i].lhs = alloc(ir.lhs,  reg_map,  used);
-            },
-            IRInfoType::REG_REG  => {
-                irs[i].lhs = alloc(ir.lhs,  reg_map,  used);
-                irs[i].rhs = alloc(ir.rhs,  reg_map,  used);
-            },
-            IRInfoType::CALL => {
-                irs[i].lhs = alloc(ir.lhs,  reg_map,  used);
-                for j in 0..ir.args.clone().len() {
-                    irs[i].args[j] = alloc(ir.args[j],  reg_map,  used);
-                }
-            },
-            _ => {}
+// Instructions control can reach directly after `irs[i]`.
+fn successors(irs: &[IR], i: usize, labels: &HashMap<usize, usize>) -> Vec<usize> {
+    let next = if i + 1 < irs.len() { vec![i + 1] } else { vec![] };
+    match irs[i].op {
+        IRType::JMP => return vec![labels[&irs[i].lhs]],
+        IRType::UNLESS => return next.into_iter().chain(Some(labels[&irs[i].rhs])).collect(),
+        IRType::JMP_TABLE => return irs[i].targets.iter().chain(Some(&irs[i].rhs)).map(|l| labels[l]).collect(),
+        IRType::RETURN | IRType::FRETURN => return vec![],
+        _ => return next,
+    }
+}
+
+// Backward dataflow over the instruction-level CFG, then each register's
+// interval is the span of instructions where it is live, used or defined.
+fn live_intervals(irs: &[IR]) -> Vec<Interval> {
+    let mut labels = HashMap::new();
+    for (i, ir) in irs.iter().enumerate() {
+        if ir.op == IRType::LABEL {
+            labels.insert(ir.lhs, i);
         }
-        if ir.op == IRType::KILL {
-            let r = reg_map[ir.lhs] as usize;
-            assert!(used[r]);
-            used[r] = false;
-            irs[i].op = IRType::NOP;
+    }
+    let ud: Vec<(Vec<usize>, Vec<usize>)> = irs.iter().map(uses_defs).collect();
+    let succs: Vec<Vec<usize>> = (0..irs.len()).map(|i| successors(irs, i, &labels)).collect();
+
+    let mut live_in: Vec<HashSet<usize>> = vec![HashSet::new(); irs.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..irs.len()).rev() {
+            let mut live: HashSet<usize> = HashSet::new();
+            for &s in &succs[i] {
+                live.extend(live_in[s].iter().cloned());
+            }
+            for r in &ud[i].1 {
+                live.remove(r);
+            }
+            live.extend(ud[i].0.iter().cloned());
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
         }
+    }
 
+    let mut ranges: HashMap<usize, (usize, usize)> = HashMap::new();
+    for i in 0..irs.len() {
+        for &r in live_in[i].iter().chain(ud[i].1.iter()) {
+            let range = ranges.entry(r).or_insert((i, i));
+            range.1 = i;
+        }
     }
+    let mut intervals: Vec<Interval> = ranges.into_iter()
+        .map(|(vreg, (start, end))| Interval{vreg, start, end})
+        .collect();
+    intervals.sort_by_key(|iv| (iv.start, iv.vreg));
+    return intervals
 }
 
-pub fn alloc_regs(fns: &mut Vec<Function>) {
-    for f in fns {
-        let mut reg_map = Vec::new();
-        let mut used = Vec::new();
-        for _i in 0..1000 {
-            reg_map.push(-1);
+struct Allocator {
+    reg_map: HashMap<usize, Loc>,
+    // Virtual registers that hold floating values.
+    xmm: HashSet<usize>,
+    free_slots: Vec<usize>,
+    stack_size: usize,
+}
+
+impl Allocator {
+    fn new_slot(&mut self) -> usize {
+        if let Some(offset) = self.free_slots.pop() {
+            return offset;
         }
-        for _i in 0..REGS.len() {
-            used.push(false);
+        self.stack_size += 8;
+        return self.stack_size
+    }
+
+    // Linear scan: walk intervals by start point, free registers whose
+    // intervals have ended, and when none is left spill whichever live
+    // interval ends last. `used` marks the registers that are taken for
+    // good, and an interval spanning one of `calls` goes straight to the
+    // stack, since the call clobbers every register of the pool.
+    fn scan(&mut self, intervals: Vec<Interval>, mut used: Vec<bool>, calls: &[usize]) {
+        let mut active: Vec<Interval> = Vec::new();
+        let mut spilled: Vec<Interval> = Vec::new();
+
+        for cur in intervals {
+            for iv in active.iter().filter(|iv| iv.end < cur.start) {
+                if let Loc::Reg(r) = self.reg_map[&iv.vreg] { used[r] = false; }
+            }
+            active.retain(|iv| iv.end >= cur.start);
+            for iv in spilled.iter().filter(|iv| iv.end < cur.start) {
+                if let Loc::Stack(offset) = self.reg_map[&iv.vreg] { self.free_slots.push(offset); }
+            }
+            spilled.retain(|iv| iv.end >= cur.start);
+
+            if calls.iter().any(|&c| cur.start < c && c < cur.end) {
+                let offset = self.new_slot();
+                self.reg_map.insert(cur.vreg, Loc::Stack(offset));
+                spilled.push(cur);
+                continue;
+            }
+
+            if let Some(r) = (0..used.len()).find(|&r| !used[r]) {
+                used[r] = true;
+                self.reg_map.insert(cur.vreg, Loc::Reg(r));
+                active.push(cur);
+                continue;
+            }
+
+            let (idx, victim) = active.iter().cloned().enumerate()
+                .max_by_key(|&(_, iv)| iv.end).unwrap();
+            let offset = self.new_slot();
+            if victim.end > cur.end {
+                let r = self.reg_map[&victim.vreg];
+                self.reg_map.insert(cur.vreg, r);
+                self.reg_map.insert(victim.vreg, Loc::Stack(offset));
+                active.remove(idx);
+                active.push(cur);
+                spilled.push(victim);
+            } else {
+                self.reg_map.insert(cur.vreg, Loc::Stack(offset));
+                spilled.push(cur);
+            }
+        }
+    }
+
+    // Maps `ir_reg` to a real register, reloading it into the `k`th scratch
+    // register before the instruction and writing it back after when spilled.
+    fn operand(&self, ir_reg: usize, k: usize, is_use: bool, is_def: bool,
+               before: &mut Vec<IR>, after: &mut Vec<IR>) -> Result<usize, CompileError> {
+        match self.reg_map.get(&ir_reg).cloned().unwrap_or(Loc::Unassigned) {
+            Loc::Reg(r) => return Ok(r),
+            Loc::Stack(offset) => {
+                let (r, load, store) = if self.xmm.contains(&ir_reg) {
+                    (XMM_SCRATCH[k], IRType::FLOAD_SPILL, IRType::FSTORE_SPILL)
+                } else {
+                    (SCRATCH[k], IRType::LOAD_SPILL, IRType::STORE_SPILL)
+                };
+                if is_use { before.push(IR{op: load, lhs: r, rhs: offset, ..Default::default()}); }
+                if is_def { after.push(IR{op: store, lhs: r, rhs: offset, ..Default::default()}); }
+                return Ok(r)
+            },
+            Loc::Unassigned => error!(Internal, Span::default(), "r{} is never live", ir_reg),
+        }
+    }
+
+    fn visit(&mut self, irs: Vec<IR>) -> Result<Vec<IR>, CompileError> {
+        let mut out = Vec::new();
+        for mut ir in irs {
+            let info = ir.get_irinfo();
+            let (is_use, is_def) = lhs_use_def(ir.op);
+            let mut after = Vec::new();
+            match info.ty {
+                IRInfoType::REG | IRInfoType::REG_IMN | IRInfoType::REG_LABEL | IRInfoType::LABEL_ADDR | IRInfoType::CALL => {
+                    ir.lhs = self.operand(ir.lhs, 0, is_use, is_def, &mut out, &mut after)?;
+                },
+                IRInfoType::REG_REG  => {
+                    ir.lhs = self.operand(ir.lhs, 0, is_use, is_def, &mut out, &mut after)?;
+                    ir.rhs = self.operand(ir.rhs, 1, true, false, &mut out, &mut after)?;
+                },
+                _ => {}
+            }
+            // Lifetimes come from liveness, so KILL is only a hint left by
+            // the IR generator.
+            if ir.op == IRType::KILL {
+                ir.op = IRType::NOP;
+            }
+            out.push(ir);
+            out.append(&mut after);
         }
+        return Ok(out)
+    }
+}
+
+pub fn alloc_regs(fns: &mut Vec<Function>) -> Result<(), CompileError> {
+    for f in fns {
+        let mut a = Allocator {
+            reg_map: HashMap::new(),
+            xmm: HashSet::new(),
+            free_slots: Vec::new(),
+            // Spill slots go below the local variables, 8-byte aligned.
+            stack_size: align_to(f.stack_size, 8),
+        };
         // r0 is a reserved register that is always mapped to rbp.
-        reg_map[0] = 0;
+        a.reg_map.insert(0, Loc::Reg(0));
+        // Integer and xmm registers are allocated from separate pools. Slots
+        // are shared, but not handed from one pool's scan to the other's.
+        // Calls only clobber xmm registers, as the integer ones that are not
+        // preserved are saved around them.
+        for ir in &f.irs {
+            let (lhs_xmm, rhs_xmm) = xmm_operands(ir.op);
+            if lhs_xmm { a.xmm.insert(ir.lhs); }
+            if rhs_xmm { a.xmm.insert(ir.rhs); }
+        }
+        let calls: Vec<usize> = (0..f.irs.len()).filter(|&i| f.irs[i].op == IRType::CALL).collect();
+        let (floats, ints) = live_intervals(&f.irs).into_iter().partition(|iv| a.xmm.contains(&iv.vreg));
+        let mut used = vec![false; SCRATCH[0]];
         used[0] = true;
-        visit(&mut f.irs, &mut reg_map, &mut used);
+        a.scan(ints, used, &[]);
+        a.free_slots.clear();
+        a.scan(floats, vec![false; XMM_SCRATCH[0]], &calls);
+        f.irs = a.visit(f.irs.clone())?;
+        if a.stack_size > f.stack_size {
+            f.stack_size = a.stack_size;
+        }
     }
+    return Ok(())
 }
 
 
@@ -96,7 +298,7 @@ mod tests {
                 stack_size: 0,
                 strings: Vec::new() }].to_vec();
 
-        alloc_regs(&mut input);
+        alloc_regs(&mut input).unwrap();
 
         let expect =  [
             Function {
@@ -125,4 +327,94 @@ mod tests {
             assert_eq!(input[i], expect[i]);
         }
     }
+
+    # [test]
+    fn can_keep_regs_live_across_loops() {
+        let mut input = [
+            Function {
+                name: "main".to_string(),
+                irs: [
+                    IR { op: IRType::IMM, lhs: 1, rhs: 7, ..Default::default() },
+                    IR { op: IRType::LABEL, lhs: 1, ..Default::default() },
+                    IR { op: IRType::MOV, lhs: 2, rhs: 1, ..Default::default() },
+                    IR { op: IRType::IMM, lhs: 3, rhs: 1, ..Default::default() },
+                    IR { op: IRType::UNLESS, lhs: 2, rhs: 2, ..Default::default() },
+                    IR { op: IRType::JMP, lhs: 1, ..Default::default() },
+                    IR { op: IRType::LABEL, lhs: 2, ..Default::default() },
+                    IR { op: IRType::RETURN, lhs: 3, ..Default::default() }].to_vec(),
+                stack_size: 0,
+                strings: Vec::new() }].to_vec();
+
+        alloc_regs(&mut input).unwrap();
+
+        // r1 is read again on the next iteration, so r3 must not reuse it.
+        assert_eq!(input[0].irs[0], IR { op: IRType::IMM, lhs: 1, rhs: 7, ..Default::default() });
+        assert_eq!(input[0].irs[2], IR { op: IRType::MOV, lhs: 2, rhs: 1, ..Default::default() });
+        assert_eq!(input[0].irs[3], IR { op: IRType::IMM, lhs: 3, rhs: 1, ..Default::default() });
+    }
+
+    # [test]
+    fn can_spill_many_live_regs() {
+        let mut irs = Vec::new();
+        for r in 1..10 {
+            irs.push(IR { op: IRType::IMM, lhs: r, rhs: r, ..Default::default() });
+        }
+        for r in 2..10 {
+            irs.push(IR { op: IRType::ADD, lhs: 1, rhs: r, ..Default::default() });
+        }
+        irs.push(IR { op: IRType::RETURN, lhs: 1, ..Default::default() });
+        let mut input = vec![Function { name: "main".to_string(), irs, stack_size: 0, strings: Vec::new() }];
+
+        alloc_regs(&mut input).unwrap();
+
+        // r1 lives longest, so it goes to the stack along with r7, r8 and r9.
+        assert_eq!(input[0].stack_size, 32);
+        assert_eq!(input[0].irs.iter().filter(|ir| ir.op == IRType::STORE_SPILL).count(), 12);
+        assert_eq!(input[0].irs.iter().filter(|ir| ir.op == IRType::LOAD_SPILL).count(), 12);
+    }
+
+    # [test]
+    fn can_alloc_xmm_regs_apart() {
+        let mut input = vec![Function {
+            name: "main".to_string(),
+            irs: [
+                IR { op: IRType::IMM, lhs: 1, rhs: 3, ..Default::default() },
+                IR { op: IRType::I2F, lhs: 2, rhs: 1, ..Default::default() },
+                IR { op: IRType::FIMM, lhs: 3, rhs: 0, ..Default::default() },
+                IR { op: IRType::CALL, lhs: 4, rhs: 0, name: "f".to_string(), ..Default::default() },
+                IR { op: IRType::FADD, lhs: 2, rhs: 3, ..Default::default() },
+                IR { op: IRType::F2I, lhs: 5, rhs: 2, ..Default::default() },
+                IR { op: IRType::ADD, lhs: 5, rhs: 1, ..Default::default() },
+                IR { op: IRType::RETURN, lhs: 5, ..Default::default() }].to_vec(),
+            stack_size: 0,
+            strings: Vec::new() }];
+
+        alloc_regs(&mut input).unwrap();
+
+        // r1 and the first xmm register are both numbered 1; the floats that
+        // live across the call are reloaded into the xmm scratch registers.
+        let irs = &input[0].irs;
+        assert_eq!(irs[0], IR { op: IRType::IMM, lhs: 1, rhs: 3, ..Default::default() });
+        assert_eq!(input[0].stack_size, 16);
+        assert_eq!(irs.iter().filter(|ir| ir.op == IRType::FSTORE_SPILL).count(), 3);
+        assert_eq!(irs.iter().filter(|ir| ir.op == IRType::STORE_SPILL).count(), 0);
+        let add = irs.iter().find(|ir| ir.op == IRType::FADD).unwrap();
+        assert_eq!((add.lhs, add.rhs), (XMM_SCRATCH[0], XMM_SCRATCH[1]));
+    }
+
+    # [test]
+    fn can_alloc_thousands_of_regs() {
+        let mut irs = vec![IR { op: IRType::IMM, lhs: 1, rhs: 0, ..Default::default() }];
+        for r in 2..3000 {
+            irs.push(IR { op: IRType::IMM, lhs: r, rhs: 1, ..Default::default() });
+            irs.push(IR { op: IRType::ADD, lhs: 1, rhs: r, ..Default::default() });
+        }
+        irs.push(IR { op: IRType::RETURN, lhs: 1, ..Default::default() });
+        let mut input = vec![Function { name: "main".to_string(), irs, stack_size: 0, strings: Vec::new() }];
+
+        alloc_regs(&mut input).unwrap();
+
+        assert_eq!(input[0].stack_size, 0);
+        assert!(input[0].irs.iter().all(|ir| ir.lhs < SCRATCH[0]));
+    }
 }
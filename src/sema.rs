@@ -1,174 +1,1342 @@
 extern crate rugcc;
-use self::rugcc::common::{ND,  Node, Type, TY};
-use std::collections::HashMap;
+use self::rugcc::common::{ND,  Node, Type, TY, Member, Span, CompileError, GlobalVar, align_to};
+use std::collections::{HashMap, HashSet};
+use std::cmp::{max, min};
+use std::mem;
 
 #[derive(PartialEq, Debug, Clone)]
 struct Var {
     ty: Type,
     offset: usize,
+    // The symbol of a variable with static storage; None for locals.
+    label: Option<String>,
 }
 
 pub struct SemaGenerator {
-    vars: HashMap<String, Var>,
+    // The variables of each enclosing block, innermost last. The first one
+    // is the file scope, which lives across functions.
+    scopes: Vec<HashMap<String, Var>>,
+    // Struct and union tags, scoped like variables.
+    tags: Vec<HashMap<String, Type>>,
+    // The definition of each complete struct, by id.
+    structs: HashMap<usize, Type>,
+    struct_id: usize,
+    // The return and parameter types of the functions declared so far.
+    funcs: HashMap<String, (Type, Vec<Type>)>,
+    ret: Type,
+    // Frame space taken by the variables in scope. Sibling blocks are
+    // disjoint, so each one starts where their parent left off.
+    offset: usize,
     stack_size: usize,
     strings: Vec<Node>,
     str_label: usize,
+    loops: usize,
+    // Case values seen so far, whether there was a default, and the type
+    // the values are converted to, for each enclosing switch, innermost last.
+    switches: Vec<(Vec<i64>, bool, Type)>,
+    // Labels are function-scoped, so gotos are checked once the body is done.
+    labels: HashSet<String>,
+    gotos: Vec<Node>,
+    // Global variables with an initializer, as opposed to tentative or
+    // extern declarations, and functions with a body.
+    initialized: HashSet<String>,
+    static_label: usize,
+    pub globals: Vec<GlobalVar>,
+}
+
+// Only variables, dereferences and members can be assigned to, and arrays never.
+fn check_lvalue(node: &Node) -> Result<(), CompileError> {
+    match node.op {
+        ND::LVAR | ND::GVAR | ND::DEREF | ND::DOT if node.ty.ty != TY::ARY => return Ok(()),
+        _ => error!(Sema, node.span, "expression is not assignable"),
+    }
+}
+
+// Structs cannot be used where a number or a pointer is expected.
+fn check_scalar(node: &Node) -> Result<(), CompileError> {
+    if node.ty.ty == TY::STRUCT {
+        error!(Sema, node.span, "'{}' is not a scalar type", type_name(&node.ty));
+    }
+    return Ok(())
+}
+
+// A struct can only be assigned a struct of the same type.
+fn check_assignable(ty: &Type, rhs: &Node) -> Result<(), CompileError> {
+    let ptr_float = (ty.ty == TY::PTR && rhs.ty.is_flonum()) || (ty.is_flonum() && rhs.ty.ty == TY::PTR);
+    if ptr_float || (ty.ty == TY::STRUCT || rhs.ty.ty == TY::STRUCT) && (ty.ty != rhs.ty.ty || ty.id != rhs.ty.id) {
+        error!(Sema, rhs.span, "assigning to '{}' from incompatible type '{}'", type_name(ty), type_name(&rhs.ty));
+    }
+    return Ok(())
+}
+
+// The spelling of a type in error messages.
+fn type_name(ty: &Type) -> String {
+    let name = match ty.ty {
+        TY::CHAR => "char".to_string(),
+        TY::SHORT => "short".to_string(),
+        TY::INT => "int".to_string(),
+        TY::BOOL => "_Bool".to_string(),
+        TY::LONG => "long".to_string(),
+        TY::LLONG => "long long".to_string(),
+        TY::FLOAT => "float".to_string(),
+        TY::DOUBLE => "double".to_string(),
+        TY::PTR => return format!("{} *", type_name(ty.ptr_of.as_ref().unwrap())),
+        TY::ARY => return format!("{}[{}]", type_name(ty.ary_of.as_ref().unwrap()), ty.len),
+        TY::STRUCT => {
+            let kind = if ty.is_union { "union" } else { "struct" };
+            if ty.tag.is_empty() { return format!("{} (anonymous)", kind) }
+            return format!("{} {}", kind, ty.tag)
+        },
+    };
+    if ty.is_unsigned { return format!("unsigned {}", name) }
+    return name
+}
+
+// A floating condition is tested against zero, which leaves an int.
+fn to_cond(node: Node) -> Node {
+    if !node.ty.is_flonum() {
+        return node
+    }
+    let span = node.span.clone();
+    let zero = Node{op: ND::NUM, ty: node.ty.clone(), span: span.clone(), ..Default::default()};
+    return Node{op: ND::NE, ty: Type::default(), lhs: Some(Box::new(node)), rhs: Some(Box::new(zero)), span, ..Default::default()}
+}
+
+fn is_integer(ty: &Type) -> bool {
+    match ty.ty {
+        TY::CHAR | TY::SHORT | TY::INT | TY::LONG | TY::LLONG | TY::BOOL => return true,
+        _ => return false,
+    }
+}
+
+// The integer promotions: everything narrower than int becomes int, which
+// holds all of their values.
+fn promote(ty: &Type) -> Type {
+    match ty.ty {
+        TY::CHAR | TY::SHORT | TY::BOOL => return Type::default(),
+        _ => return ty.clone(),
+    }
+}
+
+// The usual arithmetic conversions: double wins over float, which wins over
+// integers. Otherwise both sides are promoted, then the larger type wins, and
+// when the sizes tie the result is unsigned if either is.
+fn arith_conv(a: &Type, b: &Type) -> Type {
+    if a.ty == TY::PTR { return a.clone() }
+    if b.ty == TY::PTR { return b.clone() }
+    for ty in &[TY::DOUBLE, TY::FLOAT] {
+        if a.ty == *ty || b.ty == *ty { return Type{ty: ty.clone(), ..Default::default()} }
+    }
+    let (a, b) = (promote(a), promote(b));
+    let rank = |t: &Type| match t.ty { TY::LLONG => 3, TY::LONG => 2, _ => 1 };
+    let (hi, lo) = if rank(&a) >= rank(&b) { (a, b) } else { (b, a) };
+    let mut ty = hi.clone();
+    if lo.size_of() == ty.size_of() {
+        ty.is_unsigned |= lo.is_unsigned;
+    }
+    return ty
+}
+
+fn is_arith(ty: &Type) -> bool {
+    return is_integer(ty) || ty.is_flonum()
+}
+
+// Converts a number or pointer to the arithmetic type ty, when it is not of
+// that type already.
+fn cast(node: Node, ty: &Type) -> Node {
+    let same = node.ty.ty == ty.ty && node.ty.is_unsigned == ty.is_unsigned;
+    if same || !is_arith(ty) || !(is_arith(&node.ty) || node.ty.ty == TY::PTR) {
+        return node
+    }
+    let span = node.span.clone();
+    return Node{op: ND::CAST, ty: ty.clone(), expr: Some(Box::new(node)), span, ..Default::default()}
+}
+
+// The value val takes when converted to the integer type ty.
+fn convert(val: i64, ty: &Type) -> i64 {
+    match (ty.ty.clone(), ty.size_of(), ty.is_unsigned) {
+        (TY::BOOL, _, _) => return (val != 0) as i64,
+        (_, 1, false) => return val as i8 as i64,
+        (_, 1, true) => return val as u8 as i64,
+        (_, 2, false) => return val as i16 as i64,
+        (_, 2, true) => return val as u16 as i64,
+        (_, 4, false) => return val as i32 as i64,
+        (_, 4, true) => return val as u32 as i64,
+        _ => return val,
+    }
+}
+
+// Folds an integer constant expression, such as a case label. None if the
+// expression is not constant, or divides by zero. The result wraps to the
+// type of the expression as it would at run time.
+fn eval(node: &Node) -> Option<i64> {
+    let val = fold(node)?;
+    if is_integer(&node.ty) {
+        return Some(convert(val, &node.ty))
+    }
+    return Some(val)
+}
+
+fn fold(node: &Node) -> Option<i64> {
+    let sub = |n: &Option<Box<Node>>| eval(n.as_ref().unwrap());
+    if node.ty.is_flonum() {
+        return None
+    }
+    // Floating operands of conversions and comparisons are folded as such.
+    let float = |n: &Option<Box<Node>>| n.as_ref().is_some_and(|n| n.ty.is_flonum());
+    if float(&node.expr) && node.op == ND::CAST {
+        return Some(eval_float(node.expr.as_ref().unwrap())? as i64)
+    }
+    if float(&node.lhs) {
+        let (a, b) = (eval_float(node.lhs.as_ref().unwrap())?, eval_float(node.rhs.as_ref().unwrap())?);
+        match node.op {
+            ND::OPE('<') => return Some((a < b) as i64),
+            ND::LE => return Some((a <= b) as i64),
+            ND::EQ => return Some((a == b) as i64),
+            ND::NE => return Some((a != b) as i64),
+            _ => return None,
+        }
+    }
+    match node.op {
+        ND::NUM => return Some(node.num),
+        ND::NEG => return sub(&node.expr).map(|x| x.wrapping_neg()),
+        ND::BITNOT => return sub(&node.expr).map(|x| !x),
+        ND::NOT => return sub(&node.expr).map(|x| (x == 0) as i64),
+        ND::TERNARY => return if sub(&node.cond)? != 0 { sub(&node.then) } else { sub(&node.els) },
+        ND::CAST => return sub(&node.expr),
+        ND::LOGAND => return Some((sub(&node.lhs)? != 0 && sub(&node.rhs)? != 0) as i64),
+        ND::LOGOR => return Some((sub(&node.lhs)? != 0 || sub(&node.rhs)? != 0) as i64),
+        _ => {},
+    }
+    if node.lhs.is_none() || node.rhs.is_none() {
+        return None
+    }
+    let (a, b) = (sub(&node.lhs)?, sub(&node.rhs)?);
+    // Operands were converted to a common type, so the lhs tells whether
+    // division, comparison and right shift are unsigned.
+    let lty = &node.lhs.as_ref().unwrap().ty;
+    if lty.is_unsigned || lty.ty == TY::PTR {
+        let (x, y) = (a as u64, b as u64);
+        match node.op {
+            ND::OPE('/') => return x.checked_div(y).map(|n| n as i64),
+            ND::OPE('%') => return x.checked_rem(y).map(|n| n as i64),
+            ND::OPE('<') => return Some((x < y) as i64),
+            ND::LE => return Some((x <= y) as i64),
+            ND::SHR => return Some(x.wrapping_shr(b as u32) as i64),
+            _ => {},
+        }
+    }
+    match node.op {
+        ND::OPE('+') => return Some(a.wrapping_add(b)),
+        ND::OPE('-') => return Some(a.wrapping_sub(b)),
+        ND::OPE('*') => return Some(a.wrapping_mul(b)),
+        ND::OPE('/') => return a.checked_div(b),
+        ND::OPE('%') => return a.checked_rem(b),
+        ND::OPE('&') => return Some(a & b),
+        ND::OPE('|') => return Some(a | b),
+        ND::OPE('^') => return Some(a ^ b),
+        ND::OPE('<') => return Some((a < b) as i64),
+        ND::LE => return Some((a <= b) as i64),
+        ND::EQ => return Some((a == b) as i64),
+        ND::NE => return Some((a != b) as i64),
+        ND::SHL => return Some(a.wrapping_shl(b as u32)),
+        ND::SHR => return Some(a.wrapping_shr(b as u32)),
+        _ => return None,
+    }
+}
+
+// Folds a floating constant expression. Integer subexpressions are folded by
+// eval, and float results are rounded to single precision.
+fn eval_float(node: &Node) -> Option<f64> {
+    if !node.ty.is_flonum() {
+        let val = eval(node)?;
+        if node.ty.is_unsigned || node.ty.ty == TY::PTR { return Some(val as u64 as f64) }
+        return Some(val as f64)
+    }
+    let sub = |n: &Option<Box<Node>>| eval_float(n.as_ref().unwrap());
+    let val = match node.op {
+        ND::NUM => node.fnum,
+        ND::NEG => -sub(&node.expr)?,
+        ND::CAST => sub(&node.expr)?,
+        ND::TERNARY => if eval(node.cond.as_ref().unwrap())? != 0 { sub(&node.then)? } else { sub(&node.els)? },
+        ND::OPE('+') => sub(&node.lhs)? + sub(&node.rhs)?,
+        ND::OPE('-') => sub(&node.lhs)? - sub(&node.rhs)?,
+        ND::OPE('*') => sub(&node.lhs)? * sub(&node.rhs)?,
+        ND::OPE('/') => sub(&node.lhs)? / sub(&node.rhs)?,
+        _ => return None,
+    };
+    if node.ty.ty == TY::FLOAT { return Some(val as f32 as f64) }
+    return Some(val)
+}
+
+// Folds an address constant: a symbol plus a byte offset, with no symbol for
+// plain integers.
+fn eval_addr(node: &Node) -> Option<(Option<String>, i64)> {
+    match node.op {
+        ND::ADDR => {
+            let expr = node.expr.as_ref().unwrap();
+            match expr.op {
+                ND::GVAR => return Some((Some(expr.val.clone()), 0)),
+                ND::DEREF => return eval_addr(expr.expr.as_ref().unwrap()),
+                _ => return None,
+            }
+        },
+        ND::OPE('+') | ND::OPE('-') if node.ty.ty == TY::PTR => {
+            let (label, addend) = eval_addr(node.lhs.as_ref().unwrap())?;
+            let n = eval(node.rhs.as_ref().unwrap())? * node.ty.ptr_of.as_ref().unwrap().size_of() as i64;
+            if node.op == ND::OPE('+') { return Some((label, addend + n)) }
+            return Some((label, addend - n))
+        },
+        _ => return eval(node).map(|n| (None, n)),
+    }
+}
+
+// Fills in the length of `int a[] = {...}` from its initializer.
+fn complete_type(ty: &Type, init: &Option<Box<Node>>) -> Type {
+    let mut ty = ty.clone();
+    if ty.ty == TY::ARY && ty.len == 0 {
+        if let Some(ref init) = *init {
+            match init.op {
+                ND::STR => ty.len = init.str.len() + 1,
+                ND::INIT_LIST => ty.len = init.args.len(),
+                _ => {},
+            }
+        }
+    }
+    return ty
 }
 
 impl SemaGenerator {
     pub fn new() -> SemaGenerator {
-        SemaGenerator{vars: HashMap::new(), stack_size: 0, strings: Vec::new(), str_label: 0}
+        SemaGenerator{scopes: vec![HashMap::new()], tags: vec![HashMap::new()], structs: HashMap::new(), struct_id: 0,
+                      funcs: HashMap::new(), ret: Type::default(), offset: 0, stack_size: 0, strings: Vec::new(), str_label: 0,
+                      loops: 0, switches: Vec::new(), labels: HashSet::new(), gotos: Vec::new(),
+                      initialized: HashSet::new(), static_label: 0, globals: Vec::new()}
+    }
+
+    // Lays the constant initializer of a ty at offset out into data, and the
+    // addresses it takes into relocs.
+    fn static_init(&mut self, ty: &Type, init: Node, offset: usize, data: &mut Vec<u8>, relocs: &mut Vec<(usize, String, i64)>) -> Result<(), CompileError> {
+        // Members are initialized in order; a union only through its first.
+        if ty.ty == TY::STRUCT {
+            if init.op != ND::INIT_LIST {
+                error!(Sema, init.span, "initializer element is not a compile-time constant");
+            }
+            let members = ty.members.clone().unwrap();
+            let n = if ty.is_union { min(1, members.len()) } else { members.len() };
+            if init.args.len() > n {
+                error!(Sema, init.args[n].span, "excess elements in {} initializer", if ty.is_union { "union" } else { "struct" });
+            }
+            for (m, arg) in members.into_iter().zip(init.args) {
+                self.static_init(&m.ty, arg, offset + m.offset, data, relocs)?;
+            }
+            return Ok(())
+        }
+        if ty.ty == TY::ARY {
+            let elem = *ty.ary_of.clone().unwrap();
+            match init.op {
+                ND::STR if elem.ty == TY::CHAR => {
+                    // The terminating NUL is dropped when it does not fit.
+                    let n = min(init.str.len(), ty.len);
+                    data[offset..offset + n].copy_from_slice(&init.str[..n]);
+                },
+                ND::INIT_LIST => {
+                    if init.args.len() > ty.len {
+                        error!(Sema, init.args[ty.len].span, "excess elements in array initializer");
+                    }
+                    let size = elem.size_of();
+                    for (i, arg) in init.args.into_iter().enumerate() {
+                        self.static_init(&elem, arg, offset + i * size, data, relocs)?;
+                    }
+                },
+                _ => error!(Sema, init.span, "array initializer must be an initializer list or string literal"),
+            }
+            return Ok(())
+        }
+
+        let init = match init.op {
+            ND::INIT_LIST if init.args.len() == 1 => init.args[0].clone(),
+            ND::INIT_LIST => error!(Sema, init.span, "scalar initializer must have exactly one element"),
+            _ => init,
+        };
+        let span = init.span.clone();
+        let size = ty.size_of();
+        let init = cast(self.walk(init, true)?, ty);
+        if ty.is_flonum() {
+            match eval_float(&init) {
+                Some(val) if size == 4 => data[offset..offset + 4].copy_from_slice(&(val as f32).to_le_bytes()),
+                Some(val) => data[offset..offset + 8].copy_from_slice(&val.to_le_bytes()),
+                None => error!(Sema, span, "initializer element is not a compile-time constant"),
+            }
+            return Ok(())
+        }
+        match eval_addr(&init) {
+            Some((None, val)) => data[offset..offset + size].copy_from_slice(&val.to_le_bytes()[..size]),
+            Some((Some(label), addend)) if size == 8 => relocs.push((offset, label, addend)),
+            _ => error!(Sema, span, "initializer element is not a compile-time constant"),
+        }
+        return Ok(())
+    }
+
+    // Allocates a variable with static storage under the symbol name, or
+    // gives it its initial value if it was only declared so far.
+    fn define_static(&mut self, name: &str, node: Node, is_static: bool) -> Result<(), CompileError> {
+        let ty = node.ty.clone();
+        let data = match node.init {
+            Some(init) => {
+                let mut data = vec![0; ty.size_of()];
+                let mut relocs = Vec::new();
+                self.static_init(&ty, *init, 0, &mut data, &mut relocs)?;
+                Some((data, relocs))
+            },
+            None => None,
+        };
+        let pos = self.globals.iter().position(|g| g.name == name);
+        let index = match pos {
+            Some(i) => i,
+            None => {
+                self.globals.push(GlobalVar{name: name.to_string(), size: ty.size_of(), align: ty.align_of(), is_static,
+                                            data: None, relocs: Vec::new()});
+                self.globals.len() - 1
+            },
+        };
+        if let Some((data, relocs)) = data {
+            self.globals[index].data = Some(data);
+            self.globals[index].relocs = relocs;
+        }
+        return Ok(())
+    }
+
+    // A file-scope declaration. Without an initializer it is only tentative,
+    // so it may be repeated, and extern ones take no storage at all.
+    fn global_var(&mut self, mut node: Node) -> Result<(), CompileError> {
+        node.ty = self.resolve_type(&node.ty, &node.span)?;
+        if node.val.is_empty() {
+            return Ok(())
+        }
+        node.ty = complete_type(&node.ty, &node.init);
+        if !node.is_extern && node.ty.ty == TY::STRUCT && node.ty.members.is_none() {
+            error!(Sema, node.span, "variable has incomplete type '{}'", type_name(&node.ty));
+        }
+        if node.is_extern && node.init.is_some() {
+            error!(Sema, node.span, "'extern' variable cannot have an initializer");
+        }
+        if let Some(var) = self.scopes[0].get(&node.val) {
+            if var.ty != node.ty {
+                error!(Sema, node.span, "conflicting types for '{}'", node.val);
+            }
+        }
+        if self.funcs.contains_key(&node.val) {
+            error!(Sema, node.span, "conflicting types for '{}'", node.val);
+        }
+        if node.init.is_some() && self.initialized.contains(&node.val) {
+            error!(Sema, node.span, "redefinition of '{}'", node.val);
+        }
+        self.scopes[0].insert(node.val.clone(), Var{ty: node.ty.clone(), offset: 0, label: Some(node.val.clone())});
+        if node.is_extern {
+            return Ok(())
+        }
+        if node.init.is_some() {
+            self.initialized.insert(node.val.clone());
+        }
+        // String literals in the initializer go to .data along with it.
+        let strings = mem::take(&mut self.strings);
+        let name = node.val.clone();
+        let is_static = node.is_static;
+        self.define_static(&name, node, is_static)?;
+        for s in mem::replace(&mut self.strings, strings) {
+            let mut data = s.str.clone();
+            data.push(0);
+            self.globals.push(GlobalVar{name: s.val, size: data.len(), align: 1, is_static: true, data: Some(data), relocs: Vec::new()});
+        }
+        return Ok(())
+    }
+
+    // Resolves the struct tags in a type from the parser, and lays out the
+    // structs that come with a member list.
+    fn resolve_type(&mut self, ty: &Type, span: &Span) -> Result<Type, CompileError> {
+        match ty.ty {
+            TY::PTR => return Ok(self.resolve_type(ty.ptr_of.as_ref().unwrap(), span)?.ptr_of()),
+            TY::ARY => return Ok(self.resolve_type(ty.ary_of.as_ref().unwrap(), span)?.ary_of(ty.len)),
+            TY::STRUCT => {},
+            _ => return Ok(ty.clone()),
+        }
+        let members = match ty.members {
+            Some(ref members) => members.clone(),
+            None => {
+                let found = self.tags.iter().rev().filter_map(|t| t.get(&ty.tag)).next().cloned();
+                if let Some(found) = found {
+                    return Ok(self.complete(&found))
+                }
+                // A tag seen for the first time declares an incomplete type.
+                self.struct_id += 1;
+                let decl = Type{id: self.struct_id, ..ty.clone()};
+                self.tags.last_mut().unwrap().insert(ty.tag.clone(), decl.clone());
+                return Ok(decl)
+            },
+        };
+        // A definition completes a declaration of the tag in the same scope.
+        let prev = self.tags.last().unwrap().get(&ty.tag).cloned();
+        let id = match prev {
+            Some(ref prev) if !ty.tag.is_empty() => {
+                if self.structs.contains_key(&prev.id) {
+                    error!(Sema, span, "redefinition of '{}'", type_name(prev));
+                }
+                prev.id
+            },
+            _ => {
+                self.struct_id += 1;
+                self.struct_id
+            },
+        };
+        // Members may point to the struct being defined.
+        let mut def = Type{id, members: None, ..ty.clone()};
+        if !ty.tag.is_empty() {
+            self.tags.last_mut().unwrap().insert(ty.tag.clone(), def.clone());
+        }
+        let mut laid_out: Vec<Member> = Vec::new();
+        let mut end = 0;
+        for m in members {
+            let mty = self.resolve_type(&m.ty, span)?;
+            if (mty.ty == TY::STRUCT && mty.members.is_none()) || (mty.ty == TY::ARY && mty.len == 0) {
+                error!(Sema, span, "field '{}' has incomplete type '{}'", m.name, type_name(&mty));
+            }
+            if laid_out.iter().any(|l| l.name == m.name) {
+                error!(Sema, span, "duplicate member '{}'", m.name);
+            }
+            let align = mty.align_of();
+            let offset = if ty.is_union { 0 } else { align_to(end, align) };
+            end = offset + mty.size_of();
+            laid_out.push(Member{name: m.name, ty: mty, offset});
+        }
+        def.members = Some(laid_out);
+        self.structs.insert(id, def.clone());
+        if !ty.tag.is_empty() {
+            self.tags.last_mut().unwrap().insert(ty.tag.clone(), def.clone());
+        }
+        return Ok(def)
+    }
+
+    // A pointer may refer to a struct that was completed after it was declared.
+    fn complete(&self, ty: &Type) -> Type {
+        if ty.ty == TY::STRUCT && ty.members.is_none() {
+            if let Some(def) = self.structs.get(&ty.id) {
+                return def.clone()
+            }
+        }
+        return ty.clone()
+    }
+
+    // Reserves frame space and returns its offset from rbp. Struct slots are
+    // rounded up to whole eightbytes, as they may be filled a register at a time.
+    fn alloc(&mut self, ty: &Type) -> usize {
+        let mut size = ty.size_of();
+        if ty.ty == TY::STRUCT {
+            size = align_to(size, 8);
+        }
+        let align = ty.align_of();
+        self.offset = align_to(self.offset + size, align);
+        self.stack_size = max(self.stack_size, self.offset);
+        return self.offset
+    }
+
+    fn find_var(&self, name: &str) -> Option<Var> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(var) = scope.get(name) {
+                return Some(var.clone())
+            }
+        }
+        return None
+    }
+
+    // Opens a block scope and returns what close_scope needs to end it.
+    fn open_scope(&mut self) -> usize {
+        self.scopes.push(HashMap::new());
+        self.tags.push(HashMap::new());
+        return self.offset
+    }
+
+    fn close_scope(&mut self, offset: usize) {
+        self.scopes.pop();
+        self.tags.pop();
+        self.offset = offset;
+    }
+
+    fn walk_stmts(&mut self, node: &mut Node) -> Result<(), CompileError> {
+        for i in 0..node.stmts.len() {
+            node.stmts[i] = self.walk(node.stmts[i].clone(), true)?;
+        }
+        return Ok(())
     }
 
     fn swap(&self, p: Option<Box<Node>>, q: Option<Box<Node>>) -> (Option<Box<Node>>, Option<Box<Node>>){
         return (q, p)
     }
 
-    fn walk(&mut self, mut node: Node, decay: bool) -> Node {
+    fn walk(&mut self, mut node: Node, decay: bool) -> Result<Node, CompileError> {
         match node.op {
-            ND::NUM => return node,
+            ND::NUM => return Ok(node),
             ND::STR => {
                 let name = format!(".L.str{}", self.str_label);
                 self.str_label += 1;
                 node.val = name;
                 self.strings.push(node.clone());
-                return self.walk(Node{op: ND::GVAR, ty: node.ty, val: node.val, ..Default::default()}, decay)
+                return self.walk(Node{op: ND::GVAR, ty: node.ty, val: node.val, span: node.span, ..Default::default()}, decay)
             },
             ND::IDENT => {
-                if self.vars.get(&node.val.to_string()).is_none() {
-                    unreachable!("undefined variable: {}", node.val);
+                let var = match self.find_var(&node.val) {
+                    Some(var) => var,
+                    None => error!(Sema, node.span, "undefined variable: {}", node.val),
+                };
+                if let Some(label) = var.label {
+                    return self.walk(Node{op: ND::GVAR, ty: var.ty, val: label, span: node.span, ..Default::default()}, decay)
                 }
                 node.op = ND::LVAR;
-                let var: Var = self.vars.get(&node.val).unwrap().clone();
                 node.offset = var.offset;
                 if decay && var.ty.ty == TY::ARY {
                     node = node.addr_of(*var.ty.ary_of.unwrap());
                 } else {
                     node.ty = var.ty;
                 }
-                return node
+                return Ok(node)
             },
             ND::GVAR => {
                 if decay && node.ty.ty == TY::ARY {
-                    return node.clone().addr_of(*node.ty.ary_of.unwrap())
+                    return Ok(node.clone().addr_of(*node.ty.ary_of.unwrap()))
                 }
-                return node
+                return Ok(node)
             },
             ND::VARDEF => {
-                self.stack_size += node.ty.size_of();
-                self.vars.insert(node.val.clone(), Var{ty: node.ty.clone(), offset: self.stack_size});
-                node.offset = self.stack_size;
+                node.ty = self.resolve_type(&node.ty, &node.span)?;
+                if node.val.is_empty() {
+                    return Ok(Node{op: ND::COMP_STMT, span: node.span, ..Default::default()})
+                }
+                if self.scopes.last().unwrap().contains_key(&node.val) {
+                    error!(Sema, node.span, "redefinition of '{}'", node.val);
+                }
+                node.ty = complete_type(&node.ty, &node.init);
+                if !node.is_extern && node.ty.ty == TY::STRUCT && node.ty.members.is_none() {
+                    error!(Sema, node.span, "variable has incomplete type '{}'", type_name(&node.ty));
+                }
+                // An extern declaration in a block refers to the global.
+                if node.is_extern {
+                    if node.init.is_some() {
+                        error!(Sema, node.span, "'extern' variable cannot have an initializer");
+                    }
+                    let var = Var{ty: node.ty.clone(), offset: 0, label: Some(node.val.clone())};
+                    self.scopes.last_mut().unwrap().insert(node.val.clone(), var);
+                    return Ok(Node{op: ND::COMP_STMT, span: node.span, ..Default::default()})
+                }
+                // A static local is a global under a private name, initialized
+                // once, so the definition itself does nothing at run time.
+                if node.is_static {
+                    let label = format!(".L.{}.{}", node.val, self.static_label);
+                    self.static_label += 1;
+                    let var = Var{ty: node.ty.clone(), offset: 0, label: Some(label.clone())};
+                    self.scopes.last_mut().unwrap().insert(node.val.clone(), var);
+                    let span = node.span.clone();
+                    self.define_static(&label, node, true)?;
+                    return Ok(Node{op: ND::COMP_STMT, span, ..Default::default()})
+                }
+                if node.ty.ty == TY::ARY && node.init.is_some() {
+                    error!(Sema, node.span, "initializing an array with automatic storage is not supported");
+                }
+                if let Some(init) = node.init.clone() {
+                    if init.op == ND::INIT_LIST && node.ty.ty == TY::STRUCT {
+                        error!(Sema, node.span, "initializing a struct with automatic storage from a list is not supported");
+                    }
+                    if init.op == ND::INIT_LIST {
+                        if init.args.len() != 1 {
+                            error!(Sema, init.span, "scalar initializer must have exactly one element");
+                        }
+                        node.init = Some(Box::new(init.args[0].clone()));
+                    }
+                }
+                node.offset = self.alloc(&node.ty);
+                self.scopes.last_mut().unwrap().insert(node.val.clone(), Var{ty: node.ty.clone(), offset: node.offset, label: None});
                 if node.init.is_some() {
-                    node.init = Some(Box::new(self.walk(*node.init.unwrap(), true)));
+                    let init = self.walk(*node.init.unwrap(), true)?;
+                    check_assignable(&node.ty, &init)?;
+                    node.init = Some(Box::new(cast(init, &node.ty)));
                 }
-                return node
+                return Ok(node)
             },
             ND::IF => {
-                node.cond = Some(Box::new(self.walk(*node.cond.unwrap(), true)));
-                node.then = Some(Box::new(self.walk(*node.then.unwrap(), true)));
-                if node.els.is_some() { node.els =  Some(Box::new(self.walk(*node.els.unwrap(), true))); }
-                return node
+                node.cond = Some(Box::new(to_cond(self.walk(*node.cond.unwrap(), true)?)));
+                check_scalar(node.cond.as_ref().unwrap())?;
+                node.then = Some(Box::new(self.walk(*node.then.unwrap(), true)?));
+                if node.els.is_some() { node.els =  Some(Box::new(self.walk(*node.els.unwrap(), true)?)); }
+                return Ok(node)
             },
             ND::FOR => {
-                node.init = Some(Box::new(self.walk(*node.init.unwrap(), true)));
-                node.cond = Some(Box::new(self.walk(*node.cond.unwrap(), true)));
-                node.inc = Some(Box::new(self.walk(*node.inc.unwrap(), true)));
-                node.body = Some(Box::new(self.walk(*node.body.unwrap(), true)));
-                return node
+                let scope = self.open_scope();
+                if node.init.is_some() { node.init = Some(Box::new(self.walk(*node.init.unwrap(), true)?)); }
+                if node.cond.is_some() {
+                    node.cond = Some(Box::new(to_cond(self.walk(*node.cond.unwrap(), true)?)));
+                    check_scalar(node.cond.as_ref().unwrap())?;
+                }
+                if node.inc.is_some() { node.inc = Some(Box::new(self.walk(*node.inc.unwrap(), true)?)); }
+                self.loops += 1;
+                node.body = Some(Box::new(self.walk(*node.body.unwrap(), true)?));
+                self.loops -= 1;
+                self.close_scope(scope);
+                return Ok(node)
+            },
+            ND::DO_WHILE => {
+                self.loops += 1;
+                node.body = Some(Box::new(self.walk(*node.body.unwrap(), true)?));
+                self.loops -= 1;
+                node.cond = Some(Box::new(to_cond(self.walk(*node.cond.unwrap(), true)?)));
+                check_scalar(node.cond.as_ref().unwrap())?;
+                return Ok(node)
+            },
+            ND::BREAK => {
+                if self.loops == 0 && self.switches.is_empty() {
+                    error!(Sema, node.span, "'break' statement not in loop or switch statement");
+                }
+                return Ok(node)
+            },
+            ND::CONTINUE => {
+                if self.loops == 0 {
+                    error!(Sema, node.span, "'continue' statement not in loop statement");
+                }
+                return Ok(node)
+            },
+            ND::SWITCH => {
+                let cond = self.walk(*node.cond.unwrap(), true)?;
+                if !is_integer(&cond.ty) {
+                    error!(Sema, node.span, "statement requires expression of integer type");
+                }
+                let ty = promote(&cond.ty);
+                node.cond = Some(Box::new(cast(cond, &ty)));
+                // A loop around the switch is out of reach of its breaks but
+                // not of its continues, so the loop count stays as it is.
+                self.switches.push((Vec::new(), false, ty));
+                node.body = Some(Box::new(self.walk(*node.body.unwrap(), true)?));
+                node.cases = self.switches.pop().unwrap().0;
+                return Ok(node)
+            },
+            ND::CASE => {
+                let expr = self.walk(*node.expr.unwrap(), true)?;
+                let val = match eval(&expr) {
+                    Some(val) => val,
+                    None => error!(Sema, expr.span, "expression is not an integer constant expression"),
+                };
+                match self.switches.last_mut() {
+                    None => error!(Sema, node.span, "'case' statement not in switch statement"),
+                    Some(&mut (ref mut cases, _, ref ty)) => {
+                        let val = convert(val, ty);
+                        node.num = val;
+                        if cases.contains(&val) {
+                            error!(Sema, expr.span, "duplicate case value '{}'", val);
+                        }
+                        cases.push(val);
+                    },
+                }
+                node.expr = Some(Box::new(expr));
+                node.body = Some(Box::new(self.walk(*node.body.unwrap(), true)?));
+                return Ok(node)
+            },
+            ND::DEFAULT => {
+                match self.switches.last_mut() {
+                    None => error!(Sema, node.span, "'default' statement not in switch statement"),
+                    Some(&mut (_, true, _)) => error!(Sema, node.span, "multiple default labels in one switch"),
+                    Some(&mut (_, ref mut has_default, _)) => *has_default = true,
+                }
+                node.body = Some(Box::new(self.walk(*node.body.unwrap(), true)?));
+                return Ok(node)
+            },
+            ND::GOTO => {
+                self.gotos.push(node.clone());
+                return Ok(node)
+            },
+            ND::LABEL => {
+                if self.labels.contains(&node.val) {
+                    error!(Sema, node.span, "redefinition of label '{}'", node.val);
+                }
+                self.labels.insert(node.val.clone());
+                node.body = Some(Box::new(self.walk(*node.body.unwrap(), true)?));
+                return Ok(node)
             },
             ND::OPE('+') | ND::OPE('-') => {
-                node.lhs = Some(Box::new(self.walk(*node.lhs.unwrap(), true)));
-                node.rhs = Some(Box::new(self.walk(*node.rhs.unwrap(), true)));
+                node.lhs = Some(Box::new(self.walk(*node.lhs.unwrap(), true)?));
+                node.rhs = Some(Box::new(self.walk(*node.rhs.unwrap(), true)?));
+                check_scalar(node.lhs.as_ref().unwrap())?;
+                check_scalar(node.rhs.as_ref().unwrap())?;
                 if node.rhs.clone().unwrap().ty.ty == TY::PTR {
                     let (lhs, rhs) = self.swap(node.lhs, node.rhs);
                     node.lhs = lhs;
                     node.rhs = rhs;
                 }
                 if node.rhs.clone().unwrap().ty.ty == TY::PTR {
-                    unreachable!("'pointer {:?} pointer' is not defined", node.op);
+                    error!(Sema, node.span, "'pointer {} pointer' is not defined", node.get_ope());
                 }
-                node.ty = node.lhs.clone().unwrap().ty;
-                return node
+                if node.lhs.as_ref().unwrap().ty.ty == TY::PTR && node.rhs.as_ref().unwrap().ty.is_flonum() {
+                    error!(Sema, node.span, "invalid operands to binary {}", node.get_ope());
+                }
+                if node.lhs.as_ref().unwrap().ty.ty == TY::PTR {
+                    node.ty = node.lhs.clone().unwrap().ty;
+                    return Ok(node)
+                }
+                node.ty = arith_conv(&node.lhs.as_ref().unwrap().ty, &node.rhs.as_ref().unwrap().ty);
+                node.lhs = Some(Box::new(cast(*node.lhs.unwrap(), &node.ty)));
+                node.rhs = Some(Box::new(cast(*node.rhs.unwrap(), &node.ty)));
+                return Ok(node)
             },
             ND::OPE('=') => {
-                node.lhs = Some(Box::new(self.walk(*node.lhs.unwrap(), false)));
-                node.rhs = Some(Box::new(self.walk(*node.rhs.unwrap(), true)));
+                node.lhs = Some(Box::new(self.walk(*node.lhs.unwrap(), false)?));
+                node.rhs = Some(Box::new(self.walk(*node.rhs.unwrap(), true)?));
+                check_lvalue(node.lhs.as_ref().unwrap())?;
+                check_assignable(&node.lhs.as_ref().unwrap().ty, node.rhs.as_ref().unwrap())?;
                 node.ty = node.lhs.clone().unwrap().ty;
-                return node
+                node.rhs = Some(Box::new(cast(*node.rhs.unwrap(), &node.ty)));
+                return Ok(node)
             },
-            ND::OPE(_) | ND::LOGAND | ND::LOGOR => {
-                node.lhs = Some(Box::new(self.walk(*node.lhs.unwrap(), true)));
-                node.rhs = Some(Box::new(self.walk(*node.rhs.unwrap(), true)));
+            ND::ASSIGN_OP(ref op) => {
+                let op = *op.clone();
+                node.lhs = Some(Box::new(self.walk(*node.lhs.unwrap(), false)?));
+                node.rhs = Some(Box::new(self.walk(*node.rhs.unwrap(), true)?));
+                check_lvalue(node.lhs.as_ref().unwrap())?;
+                check_scalar(node.lhs.as_ref().unwrap())?;
+                check_scalar(node.rhs.as_ref().unwrap())?;
+                let lty = node.lhs.clone().unwrap().ty.ty;
+                let rty = node.rhs.clone().unwrap().ty.ty;
+                let pointer_arith = op == ND::OPE('+') || op == ND::OPE('-');
+                let float_arith = pointer_arith || op == ND::OPE('*') || op == ND::OPE('/');
+                let float = lty == TY::FLOAT || lty == TY::DOUBLE || rty == TY::FLOAT || rty == TY::DOUBLE;
+                if rty == TY::PTR || (lty == TY::PTR && (!pointer_arith || float)) || (float && !float_arith) {
+                    error!(Sema, node.span, "invalid operands to compound assignment");
+                }
                 node.ty = node.lhs.clone().unwrap().ty;
-                return node
+                // The operation is done in the type of the rhs, and the result
+                // converted back when it is stored.
+                if lty != TY::PTR {
+                    let ty = if op == ND::SHL || op == ND::SHR {
+                        promote(&node.ty)
+                    } else {
+                        arith_conv(&node.ty, &node.rhs.as_ref().unwrap().ty)
+                    };
+                    node.rhs = Some(Box::new(cast(*node.rhs.unwrap(), &ty)));
+                }
+                return Ok(node)
+            },
+            ND::POST_INC | ND::POST_DEC => {
+                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), false)?));
+                check_lvalue(node.expr.as_ref().unwrap())?;
+                check_scalar(node.expr.as_ref().unwrap())?;
+                node.ty = node.expr.clone().unwrap().ty;
+                return Ok(node)
+            },
+            ND::OPE('<') | ND::LE | ND::EQ | ND::NE | ND::LOGAND | ND::LOGOR => {
+                node.lhs = Some(Box::new(self.walk(*node.lhs.unwrap(), true)?));
+                node.rhs = Some(Box::new(self.walk(*node.rhs.unwrap(), true)?));
+                check_scalar(node.lhs.as_ref().unwrap())?;
+                check_scalar(node.rhs.as_ref().unwrap())?;
+                // Comparisons are done in the common type of both sides.
+                if node.op != ND::LOGAND && node.op != ND::LOGOR {
+                    let (lty, rty) = (&node.lhs.as_ref().unwrap().ty, &node.rhs.as_ref().unwrap().ty);
+                    if (lty.ty == TY::PTR && rty.is_flonum()) || (lty.is_flonum() && rty.ty == TY::PTR) {
+                        error!(Sema, node.span, "invalid operands to binary expression ('{}' and '{}')", type_name(lty), type_name(rty));
+                    }
+                    let ty = arith_conv(&node.lhs.as_ref().unwrap().ty, &node.rhs.as_ref().unwrap().ty);
+                    node.lhs = Some(Box::new(cast(*node.lhs.unwrap(), &ty)));
+                    node.rhs = Some(Box::new(cast(*node.rhs.unwrap(), &ty)));
+                } else {
+                    node.lhs = Some(Box::new(to_cond(*node.lhs.unwrap())));
+                    node.rhs = Some(Box::new(to_cond(*node.rhs.unwrap())));
+                }
+                node.ty = Type{ty: TY::INT, ..Default::default()};
+                return Ok(node)
+            },
+            ND::OPE(_) | ND::SHL | ND::SHR => {
+                node.lhs = Some(Box::new(self.walk(*node.lhs.unwrap(), true)?));
+                node.rhs = Some(Box::new(self.walk(*node.rhs.unwrap(), true)?));
+                check_scalar(node.lhs.as_ref().unwrap())?;
+                check_scalar(node.rhs.as_ref().unwrap())?;
+                let float = node.op == ND::OPE('*') || node.op == ND::OPE('/');
+                for side in &[&node.lhs, &node.rhs] {
+                    let ty = &side.as_ref().unwrap().ty;
+                    if ty.ty == TY::PTR || (ty.is_flonum() && !float) {
+                        error!(Sema, node.span, "invalid operands to binary {}", node.get_ope());
+                    }
+                }
+                // Shifts take the type of their promoted lhs alone.
+                let (lty, rty) = if node.op == ND::SHL || node.op == ND::SHR {
+                    (promote(&node.lhs.as_ref().unwrap().ty), promote(&node.rhs.as_ref().unwrap().ty))
+                } else {
+                    let ty = arith_conv(&node.lhs.as_ref().unwrap().ty, &node.rhs.as_ref().unwrap().ty);
+                    (ty.clone(), ty)
+                };
+                node.lhs = Some(Box::new(cast(*node.lhs.unwrap(), &lty)));
+                node.rhs = Some(Box::new(cast(*node.rhs.unwrap(), &rty)));
+                node.ty = lty;
+                return Ok(node)
+            },
+            ND::NEG | ND::BITNOT => {
+                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), true)?));
+                check_scalar(node.expr.as_ref().unwrap())?;
+                let ty = &node.expr.as_ref().unwrap().ty;
+                if ty.ty == TY::PTR || (ty.is_flonum() && node.op == ND::BITNOT) {
+                    error!(Sema, node.span, "invalid argument type to unary expression");
+                }
+                node.ty = promote(&node.expr.as_ref().unwrap().ty);
+                node.expr = Some(Box::new(cast(*node.expr.unwrap(), &node.ty)));
+                return Ok(node)
+            },
+            ND::CAST => {
+                node.ty = self.resolve_type(&node.ty, &node.span)?;
+                if node.ty.ty == TY::STRUCT {
+                    error!(Sema, node.span, "used type '{}' where arithmetic or pointer type is required", type_name(&node.ty));
+                }
+                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), true)?));
+                check_scalar(node.expr.as_ref().unwrap())?;
+                let from = &node.expr.as_ref().unwrap().ty;
+                if (node.ty.ty == TY::PTR && from.is_flonum()) || (node.ty.is_flonum() && from.ty == TY::PTR) {
+                    error!(Sema, node.span, "cannot cast '{}' to '{}'", type_name(from), type_name(&node.ty));
+                }
+                return Ok(node)
+            },
+            ND::NOT => {
+                node.expr = Some(Box::new(to_cond(self.walk(*node.expr.unwrap(), true)?)));
+                check_scalar(node.expr.as_ref().unwrap())?;
+                node.ty = Type{ty: TY::INT, ..Default::default()};
+                return Ok(node)
+            },
+            ND::TERNARY => {
+                node.cond = Some(Box::new(to_cond(self.walk(*node.cond.unwrap(), true)?)));
+                check_scalar(node.cond.as_ref().unwrap())?;
+                node.then = Some(Box::new(self.walk(*node.then.unwrap(), true)?));
+                node.els = Some(Box::new(self.walk(*node.els.unwrap(), true)?));
+                node.ty = arith_conv(&node.then.as_ref().unwrap().ty, &node.els.as_ref().unwrap().ty);
+                node.then = Some(Box::new(cast(*node.then.unwrap(), &node.ty)));
+                node.els = Some(Box::new(cast(*node.els.unwrap(), &node.ty)));
+                return Ok(node)
+            },
+            ND::COMMA => {
+                node.lhs = Some(Box::new(self.walk(*node.lhs.unwrap(), true)?));
+                node.rhs = Some(Box::new(self.walk(*node.rhs.unwrap(), true)?));
+                node.ty = node.rhs.clone().unwrap().ty;
+                return Ok(node)
             },
             ND::ADDR => {
-                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), true)));
+                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), true)?));
                 node.ty = node.expr.clone().unwrap().ty.ptr_of();
-                return node
+                return Ok(node)
             },
             ND::DEREF => {
-                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), true)));
+                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), true)?));
                 if node.expr.clone().unwrap().ty.ty != TY::PTR {
-                    unreachable!("operand must be a pointer");
+                    error!(Sema, node.span, "operand must be a pointer");
                 }
-                node.ty = *node.expr.clone().unwrap().ty.ptr_of.unwrap();
-                return node
+                node.ty = self.complete(node.expr.as_ref().unwrap().ty.ptr_of.as_ref().unwrap());
+                // a[1] of an `int a[2][3]` is itself an array.
+                if decay && node.ty.ty == TY::ARY {
+                    let span = node.span.clone();
+                    let mut addr = node.clone().addr_of(*node.ty.ary_of.unwrap());
+                    addr.span = span;
+                    return Ok(addr)
+                }
+                return Ok(node)
+            },
+            ND::DOT => {
+                let name = node.val.clone();
+                let expr = self.walk(*node.expr.unwrap(), false)?;
+                let ty = self.complete(&expr.ty);
+                if ty.ty != TY::STRUCT {
+                    error!(Sema, node.span, "member reference base type '{}' is not a structure or union", type_name(&ty));
+                }
+                let member = match ty.members {
+                    None => error!(Sema, node.span, "incomplete definition of type '{}'", type_name(&ty)),
+                    Some(ref members) => members.iter().find(|m| m.name == name).cloned(),
+                };
+                let member = match member {
+                    Some(m) => m,
+                    None => error!(Sema, node.span, "no member named '{}' in '{}'", node.val, type_name(&ty)),
+                };
+                node.expr = Some(Box::new(expr));
+                node.offset = member.offset;
+                if decay && member.ty.ty == TY::ARY {
+                    let span = node.span.clone();
+                    let mut addr = node.addr_of(*member.ty.ary_of.unwrap());
+                    addr.span = span;
+                    return Ok(addr)
+                }
+                node.ty = member.ty;
+                return Ok(node)
             },
             ND::RETURN => {
-                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), true)));
-                return node
+                let expr = self.walk(*node.expr.unwrap(), true)?;
+                let ptr_float = (self.ret.ty == TY::PTR && expr.ty.is_flonum()) || (self.ret.is_flonum() && expr.ty.ty == TY::PTR);
+                if ptr_float || (self.ret.ty == TY::STRUCT || expr.ty.ty == TY::STRUCT) &&
+                   (self.ret.ty != expr.ty.ty || self.ret.id != expr.ty.id) {
+                    error!(Sema, expr.span, "returning '{}' from a function with incompatible result type '{}'",
+                           type_name(&expr.ty), type_name(&self.ret));
+                }
+                node.expr = Some(Box::new(cast(expr, &self.ret)));
+                return Ok(node)
             },
             ND::SIZEOF => {
-                let expr = self.walk(*node.expr.unwrap(), false);
-                return Node{op: ND::NUM, ty:Type{..Default::default()}, val: expr.ty.size_of().to_string(), ..Default::default()}
+                let of = match node.expr {
+                    Some(expr) => self.walk(*expr, false)?.ty,
+                    None => self.resolve_type(&node.ty, &node.span)?,
+                };
+                if of.ty == TY::STRUCT && of.members.is_none() {
+                    error!(Sema, node.span, "invalid application of 'sizeof' to an incomplete type '{}'", type_name(&of));
+                }
+                // sizeof gives a size_t, which is unsigned long.
+                let ty = Type{ty: TY::LONG, is_unsigned: true, ..Default::default()};
+                return Ok(Node{op: ND::NUM, ty, num: of.size_of() as i64, span: node.span, ..Default::default()})
             },
             ND::CALL => {
+                // The result of an undeclared function is taken whole, since
+                // it may well be a pointer.
+                let long = Type{ty: TY::LONG, ..Default::default()};
+                let (ret, params) = self.funcs.get(&node.val).cloned().unwrap_or((long, Vec::new()));
+                // Arguments are converted to their parameter types, and any
+                // others, as those of `...`, have a float promoted to double.
                 for i in 0..node.args.len() {
-                    node.args[i] = self.walk(node.args[i].clone(), true);
+                    let arg = self.walk(node.args[i].clone(), true)?;
+                    node.args[i] = match params.get(i) {
+                        Some(ty) => {
+                            check_assignable(ty, &arg)?;
+                            cast(arg, ty)
+                        },
+                        None if arg.ty.ty == TY::FLOAT => cast(arg, &Type{ty: TY::DOUBLE, ..Default::default()}),
+                        None => arg,
+                    };
+                }
+                node.ty = ret;
+                // A returned struct is kept in a temporary of the caller.
+                if node.ty.ty == TY::STRUCT {
+                    node.offset = self.alloc(&node.ty);
                 }
-                node.ty = Type{..Default::default()};
-                return node
+                return Ok(node)
             },
+            // Parameters share a scope with the outermost block of the body.
             ND::FUNC => {
+                node.ty = self.resolve_type(&node.ty, &node.span)?;
+                if node.ty.ty == TY::STRUCT && node.ty.members.is_none() {
+                    error!(Sema, node.span, "incomplete result type '{}' in function definition", type_name(&node.ty));
+                }
+                let mut params = Vec::new();
+                for arg in &node.args {
+                    params.push(self.resolve_type(&arg.ty, &arg.span)?);
+                }
+                // An empty parameter list says nothing about the parameters.
+                let conflict = match self.funcs.get(&node.val) {
+                    Some((ret, ps)) => *ret != node.ty || (!ps.is_empty() && !params.is_empty() && *ps != params),
+                    None => self.scopes[0].contains_key(&node.val),
+                };
+                if conflict {
+                    error!(Sema, node.span, "conflicting types for '{}'", node.val);
+                }
+                if node.body.is_some() && !self.initialized.insert(node.val.clone()) {
+                    error!(Sema, node.span, "redefinition of '{}'", node.val);
+                }
+                if params.is_empty() {
+                    if let Some((_, ps)) = self.funcs.get(&node.val) {
+                        params = ps.clone();
+                    }
+                }
+                self.funcs.insert(node.val.clone(), (node.ty.clone(), params));
+                if node.body.is_none() {
+                    return Ok(node)
+                }
+                self.ret = node.ty.clone();
+                let scope = self.open_scope();
+                // Where to put a struct that is returned in memory, as the
+                // caller passes its address along with the arguments.
+                if node.ty.ty == TY::STRUCT {
+                    node.offset = self.alloc(&node.ty.ptr_of());
+                }
                 for i in 0..node.args.len() {
-                    node.args[i] = self.walk(node.args[i].clone(), true);
+                    node.args[i] = self.walk(node.args[i].clone(), true)?;
                 }
-                node.body = Some(Box::new(self.walk(*node.body.unwrap(), true)));
-                return node
+                let mut body = *node.body.unwrap();
+                self.walk_stmts(&mut body)?;
+                node.body = Some(Box::new(body));
+                self.close_scope(scope);
+                return Ok(node)
             },
             ND::COMP_STMT => {
-                for i in 0..node.stmts.len() {
-                    node.stmts[i] = self.walk(node.stmts[i].clone(), true);
-                }
-                return node
+                let scope = self.open_scope();
+                self.walk_stmts(&mut node)?;
+                self.close_scope(scope);
+                return Ok(node)
             },
             ND::EXPR_STMT => {
-                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), true)));
-                return node
+                node.expr = Some(Box::new(self.walk(*node.expr.unwrap(), true)?));
+                return Ok(node)
             },
-            ND::LVAR => {unreachable!("unexpected type: LVAR");}
+            ND::LVAR => error!(Internal, node.span, "unexpected type: LVAR"),
+            ND::INIT_LIST => error!(Sema, node.span, "initializer list is not an expression"),
         }
     }
-    pub fn sema(&mut self, nodes: Vec<Node>)  -> Vec<Node>{
+    pub fn sema(&mut self, nodes: Vec<Node>)  -> Result<Vec<Node>, CompileError> {
         let mut res = Vec::new();
         for mut node in nodes.clone() {
-            assert!(node.op == ND::FUNC);
-            self.vars = HashMap::new();
+            if node.op == ND::VARDEF {
+                self.global_var(node)?;
+                continue
+            }
+            if node.op != ND::FUNC { error!(Internal, node.span, "function expected, but got {:?}", node.op) }
+            self.scopes.truncate(1);
+            self.tags.truncate(1);
+            self.offset = 0;
             self.stack_size = 0;
             self.strings = Vec::new();
-            node = self.walk(node, true);
+            self.labels = HashSet::new();
+            self.gotos = Vec::new();
+            node = self.walk(node, true)?;
+            if node.body.is_none() {
+                continue
+            }
+            for goto in &self.gotos {
+                if !self.labels.contains(&goto.val) {
+                    error!(Sema, goto.span, "use of undeclared label '{}'", goto.val);
+                }
+            }
             node.stack_size = self.stack_size;
             node.strings = self.strings.clone();
             res.push(node);
         }
-        return res
+        return Ok(res)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use self::rugcc::common::SourceFile;
+    use token::tokenize;
+    use node::parse;
+    use std::rc::Rc;
+
+    fn check(src: &str) -> Result<Vec<Node>, CompileError> {
+        let mut tokens = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+        return SemaGenerator::new().sema(parse(&mut tokens).unwrap())
+    }
+
+    # [test]
+    fn cannot_walk_misplaced_jumps() {
+        assert!(check("int main() { for (;;) { if (1) break; continue; } while (1) break; do continue; while (0); return 0; }").is_ok());
+        assert!(check("int main() { goto end; end: return 0; }").is_ok());
+        assert_eq!(check("int main() { break; }").unwrap_err().msg, "'break' statement not in loop or switch statement");
+        assert_eq!(check("int main() { if (1) continue; }").unwrap_err().msg, "'continue' statement not in loop statement");
+        assert_eq!(check("int main() { goto out; }").unwrap_err().msg, "use of undeclared label 'out'");
+        assert_eq!(check("int main() { a: a: return 0; }").unwrap_err().msg, "redefinition of label 'a'");
+        // Labels belong to one function.
+        assert!(check("int f() { x: return 0; } int main() { goto x; }").is_err());
+    }
+
+    # [test]
+    fn can_scope_blocks() {
+        // x at 4, y at 8; the sibling blocks both start at 8, so z and w share a slot.
+        let nodes = check("int main() { int x; { int y; { int z; } { int w; } } { int x; return x; } }").unwrap();
+        assert_eq!(nodes[0].stack_size, 12);
+        let stmts = &nodes[0].body.as_ref().unwrap().stmts;
+        let inner = &stmts[1].stmts;
+        assert_eq!(inner[1].stmts[0].offset, 12);
+        assert_eq!(inner[2].stmts[0].offset, 12);
+        // The shadowing x gets its own slot, and the return refers to it.
+        assert_eq!(stmts[2].stmts[0].offset, 8);
+        assert_eq!(stmts[2].stmts[1].expr.as_ref().unwrap().offset, 8);
+
+        assert_eq!(check("int main() { int x; int x; }").unwrap_err().msg, "redefinition of 'x'");
+        assert_eq!(check("int f(int a) { int a; return a; }").unwrap_err().msg, "redefinition of 'a'");
+        assert_eq!(check("int main() { { int y; } return y; }").unwrap_err().msg, "undefined variable: y");
+        assert_eq!(check("int main() { for (int i=0; i<2; i++) ; return i; }").unwrap_err().msg, "undefined variable: i");
+        assert!(check("int main() { for (int i=0; i<2; i++) { int i; } int i; return 0; }").is_ok());
+    }
+
+    # [test]
+    fn can_check_switch_cases() {
+        let nodes = check("int main() { int x; switch (x) { case 1+2: case 'a': case -1: case 1<<4 ? 8 : 9: default: break; } return 0; }").unwrap();
+        let switch = &nodes[0].body.as_ref().unwrap().stmts[1];
+        assert_eq!(switch.cases, [3, 97, -1, 8]);
+        assert_eq!(check("int main() { switch (1) { case 1: case 2: case 1: ; } }").unwrap_err().msg, "duplicate case value '1'");
+        assert_eq!(check("int main() { int x; switch (1) { case x: ; } }").unwrap_err().msg, "expression is not an integer constant expression");
+        assert_eq!(check("int main() { switch (1) { case 1/0: ; } }").unwrap_err().msg, "expression is not an integer constant expression");
+        assert_eq!(check("int main() { switch (1) { default: default: ; } }").unwrap_err().msg, "multiple default labels in one switch");
+        assert_eq!(check("int main() { case 1: return 0; }").unwrap_err().msg, "'case' statement not in switch statement");
+        assert_eq!(check("int main() { default: return 0; }").unwrap_err().msg, "'default' statement not in switch statement");
+        assert_eq!(check("int main() { switch (1) { case 1: continue; } }").unwrap_err().msg, "'continue' statement not in loop statement");
+        // Case values belong to the innermost switch.
+        assert!(check("int main() { switch (1) { case 1: switch (2) { case 1: ; } } return 0; }").is_ok());
+    }
+
+    # [test]
+    fn can_fold_by_signedness() {
+        let nodes = check("int main() { long x; switch (x) { case -7/2: ; case -7%2: ; case (unsigned)-8/2: ; case -1 < 0u: ; \
+                           case -8 >> 1: ; case -16u >> 2: ; case 2147483647 + 1: ; case -1 < 0: ; } return 0; }").unwrap();
+        let switch = &nodes[0].body.as_ref().unwrap().stmts[1];
+        assert_eq!(switch.cases, [-3, -1, 2147483644, 0, -4, 1073741820, -2147483648, 1]);
+    }
+
+    # [test]
+    fn can_lay_out_static_data() {
+        let mut sema = SemaGenerator::new();
+        let src = "int a[] = {1, 2}; int *p = a + 1; char s[4] = \"ab\"; char *t = \"x\"; int b; int b; extern int c; \
+                   int f() { static int n = 3; return n + b; }";
+        let mut tokens = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+        sema.sema(parse(&mut tokens).unwrap()).unwrap();
+        let globals: Vec<(&str, Option<Vec<u8>>)> = sema.globals.iter().map(|g| (&g.name[..], g.data.clone())).collect();
+        assert_eq!(globals, [
+            ("a", Some(vec![1, 0, 0, 0, 2, 0, 0, 0])),
+            ("p", Some(vec![0; 8])),
+            ("s", Some(vec![97, 98, 0, 0])),
+            ("t", Some(vec![0; 8])),
+            (".L.str0", Some(vec![120, 0])),
+            ("b", None),
+            (".L.n.0", Some(vec![3, 0, 0, 0])),
+        ]);
+        assert_eq!(sema.globals[1].relocs, [(0, "a".to_string(), 4)]);
+        assert_eq!(sema.globals[3].relocs, [(0, ".L.str0".to_string(), 0)]);
+        assert!(sema.globals[6].is_static && !sema.globals[0].is_static);
+
+        assert_eq!(check("int g = 1; int g = 2;").unwrap_err().msg, "redefinition of 'g'");
+        assert_eq!(check("int g; char g;").unwrap_err().msg, "conflicting types for 'g'");
+        assert_eq!(check("int a[1] = {1, 2};").unwrap_err().msg, "excess elements in array initializer");
+        assert_eq!(check("int x; int y = x;").unwrap_err().msg, "initializer element is not a compile-time constant");
+        assert_eq!(check("int main() { int x; static int *p = &x; }").unwrap_err().msg, "initializer element is not a compile-time constant");
+        assert_eq!(check("extern int e = 1;").unwrap_err().msg, "'extern' variable cannot have an initializer");
+    }
+
+    # [test]
+    fn cannot_redefine_functions() {
+        assert!(check("int f(int a); int f(); int f(int b) { return b; } int main() { return f(1); }").is_ok());
+        let err = check("int f() { return 1; }\nint f() { return 2; }").unwrap_err();
+        assert_eq!((err.msg.as_str(), err.span.line), ("redefinition of 'f'", 2));
+        assert_eq!(check("int f(int x); long f(int a) { return a; }").unwrap_err().msg, "conflicting types for 'f'");
+        assert_eq!(check("int f(int x); int f(char a) { return a; }").unwrap_err().msg, "conflicting types for 'f'");
+        assert_eq!(check("int f; int f() { return 0; }").unwrap_err().msg, "conflicting types for 'f'");
+        assert_eq!(check("int f(); int f;").unwrap_err().msg, "conflicting types for 'f'");
+    }
+
+    # [test]
+    fn can_lay_out_structs() {
+        let mut sema = SemaGenerator::new();
+        let src = "struct S { char c; int i; char d; struct S *next; int a[3]; }; union U { char c; struct S s; int i; }; \
+                   struct S s; union U u; int f() { struct S { char c; } t; return sizeof(t) + sizeof(s.next->next); }";
+        let mut tokens = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+        let nodes = sema.sema(parse(&mut tokens).unwrap()).unwrap();
+        let s = sema.structs.values().find(|t| t.tag == "S" && t.members.as_ref().unwrap().len() == 5).unwrap().clone();
+        let offsets: Vec<usize> = s.members.as_ref().unwrap().iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, [0, 4, 8, 16, 24]);
+        assert_eq!((s.size_of(), s.align_of()), (40, 8));
+        // The pointer member refers to the struct being defined.
+        assert_eq!(s.members.as_ref().unwrap()[3].ty.ptr_of.as_ref().unwrap().id, s.id);
+        let u = sema.structs.values().find(|t| t.tag == "U").unwrap();
+        assert!(u.members.as_ref().unwrap().iter().all(|m| m.offset == 0));
+        assert_eq!(u.size_of(), 40);
+        // The inner struct S shadows the outer one.
+        let ret = nodes[0].body.as_ref().unwrap().stmts[1].expr.as_ref().unwrap();
+        assert_eq!(eval(ret), Some(9));
+
+        assert_eq!(check("struct S { int a; }; int main() { struct S s; return s.b; }").unwrap_err().msg, "no member named 'b' in 'struct S'");
+        assert_eq!(check("int main() { int x; return x.a; }").unwrap_err().msg, "member reference base type 'int' is not a structure or union");
+        assert_eq!(check("struct L; int main() { struct L l; }").unwrap_err().msg, "variable has incomplete type 'struct L'");
+        assert_eq!(check("struct L *p; int main() { return p->a; }").unwrap_err().msg, "incomplete definition of type 'struct L'");
+        assert_eq!(check("struct S { int a; struct S s; };").unwrap_err().msg, "field 's' has incomplete type 'struct S'");
+        assert_eq!(check("struct S { int a; int a; };").unwrap_err().msg, "duplicate member 'a'");
+        assert_eq!(check("struct S { int a; }; struct S { int b; };").unwrap_err().msg, "redefinition of 'struct S'");
+        assert_eq!(check("struct P { int x; }; struct Q { int x; }; int main() { struct P p; struct Q q; p = q; }").unwrap_err().msg,
+                   "assigning to 'struct P' from incompatible type 'struct Q'");
+        assert_eq!(check("struct P { int x; }; int main() { struct P p; return p; }").unwrap_err().msg,
+                   "returning 'struct P' from a function with incompatible result type 'int'");
+        assert_eq!(check("struct P { int x; }; int main() { struct P p; return !p; }").unwrap_err().msg, "'struct P' is not a scalar type");
+    }
+
+    # [test]
+    fn can_convert_arithmetic_types() {
+        let ty = |ty: TY, is_unsigned: bool| Type{ty, is_unsigned, ..Default::default()};
+        assert_eq!(arith_conv(&ty(TY::CHAR, false), &ty(TY::CHAR, false)), ty(TY::INT, false));
+        assert_eq!(arith_conv(&ty(TY::INT, true), &ty(TY::CHAR, false)), ty(TY::INT, true));
+        assert_eq!(arith_conv(&ty(TY::INT, true), &ty(TY::LONG, false)), ty(TY::LONG, false));
+        assert_eq!(arith_conv(&ty(TY::LONG, true), &ty(TY::LLONG, false)), ty(TY::LLONG, true));
+        // unsigned short and _Bool fit in an int, so they promote to a signed one.
+        assert_eq!(arith_conv(&ty(TY::SHORT, true), &ty(TY::BOOL, false)), ty(TY::INT, false));
+        assert_eq!(arith_conv(&ty(TY::SHORT, false), &ty(TY::INT, true)), ty(TY::INT, true));
+        let ptr = ty(TY::INT, false).ptr_of();
+        assert_eq!(arith_conv(&ty(TY::INT, false), &ptr), ptr);
+
+        assert_eq!(convert(300, &ty(TY::CHAR, false)), 44);
+        assert_eq!(convert(-1, &ty(TY::CHAR, true)), 255);
+        assert_eq!(convert(65535, &ty(TY::SHORT, false)), -1);
+        assert_eq!(convert(-1, &ty(TY::INT, true)), 4294967295);
+        assert_eq!(convert(-1, &ty(TY::LONG, true)), -1);
+        assert_eq!(convert(256, &ty(TY::BOOL, false)), 1);
+    }
+
+    # [test]
+    fn can_check_floating_types() {
+        let ty = |ty: TY, is_unsigned: bool| Type{ty, is_unsigned, ..Default::default()};
+        assert_eq!(arith_conv(&ty(TY::LONG, true), &ty(TY::FLOAT, false)), ty(TY::FLOAT, false));
+        assert_eq!(arith_conv(&ty(TY::FLOAT, false), &ty(TY::DOUBLE, false)), ty(TY::DOUBLE, false));
+
+        let nodes = check("int main() { int x; switch (x) { case (int)2.9: ; case (int)-2.9: ; case 1.5 < 2: ; case (int)(0.1f == 0.1): ; \
+                           case (long)(1e10 / 4): ; case (char)65.9f: ; } return 0; }").unwrap();
+        assert_eq!(nodes[0].body.as_ref().unwrap().stmts[1].cases, [2, -2, 1, 0, -1794967296, 65]);
+
+        let mut sema = SemaGenerator::new();
+        let src = "double d = 1.0 / 4 + 1; float f = 3; double u = -1u;";
+        let mut tokens = tokenize(Rc::new(SourceFile{name: "test.c".to_string(), text: src.to_string()})).unwrap();
+        sema.sema(parse(&mut tokens).unwrap()).unwrap();
+        let data: Vec<Vec<u8>> = sema.globals.iter().map(|g| g.data.clone().unwrap()).collect();
+        assert_eq!(data, [1.25f64.to_le_bytes().to_vec(), 3f32.to_le_bytes().to_vec(), 4294967295f64.to_le_bytes().to_vec()]);
+
+        // Conditions are compared with zero, and arguments converted to their
+        // parameters, or to double past them.
+        let nodes = check("int f(float x, ...); int main() { float g; if (g) return f(1, g); return 0; }").unwrap();
+        let stmt = &nodes[0].body.as_ref().unwrap().stmts[1];
+        assert_eq!((stmt.cond.as_ref().unwrap().op.clone(), stmt.cond.as_ref().unwrap().ty.ty.clone()), (ND::NE, TY::INT));
+        let call = stmt.then.as_ref().unwrap().expr.as_ref().unwrap();
+        let tys: Vec<TY> = call.args.iter().map(|a| a.ty.ty.clone()).collect();
+        assert_eq!(tys, [TY::FLOAT, TY::DOUBLE]);
+
+        assert_eq!(check("int main() { double d; return d % 2; }").unwrap_err().msg, "invalid operands to binary %");
+        assert_eq!(check("int main() { double d; return d << 1; }").unwrap_err().msg, "invalid operands to binary <<");
+        assert_eq!(check("int main() { double d; return ~d; }").unwrap_err().msg, "invalid argument type to unary expression");
+        assert_eq!(check("int main() { int *p; p += 1.0; }").unwrap_err().msg, "invalid operands to compound assignment");
+        assert_eq!(check("int main() { double d; d &= 1; }").unwrap_err().msg, "invalid operands to compound assignment");
+        assert_eq!(check("int main() { int *p; return p - 1.0; }").unwrap_err().msg, "invalid operands to binary -");
+        assert_eq!(check("int main() { int *p; return p < 1.0; }").unwrap_err().msg, "invalid operands to binary expression ('int *' and 'double')");
+        assert_eq!(check("int main() { int *p; double d = p; }").unwrap_err().msg, "assigning to 'double' from incompatible type 'int *'");
+        assert_eq!(check("int main() { float f; return (char *)f; }").unwrap_err().msg, "cannot cast 'float' to 'char *'");
+        assert_eq!(check("int *f() { return 1.0; }").unwrap_err().msg, "returning 'double' from a function with incompatible result type 'int *'");
+        assert_eq!(check("double g = 1 % 0;").unwrap_err().msg, "initializer element is not a compile-time constant");
+    }
+
+    # [test]
+    fn can_insert_implicit_casts() {
+        let nodes = check("int main() { short s; unsigned u; long l; _Bool b = s; return s + u < l; }").unwrap();
+        let stmts = &nodes[0].body.as_ref().unwrap().stmts;
+        let init = stmts[3].init.as_ref().unwrap();
+        assert_eq!((init.op.clone(), init.ty.ty.clone()), (ND::CAST, TY::BOOL));
+        // s + u is done in unsigned int, then compared as a long.
+        let lt = stmts[4].expr.as_ref().unwrap();
+        let sum = lt.lhs.as_ref().unwrap();
+        assert_eq!((sum.op.clone(), sum.ty.ty.clone()), (ND::CAST, TY::LONG));
+        let add = sum.expr.as_ref().unwrap();
+        assert_eq!(add.ty, Type{ty: TY::INT, is_unsigned: true, ..Default::default()});
+        assert_eq!(add.lhs.as_ref().unwrap().op, ND::CAST);
+        assert_eq!(add.rhs.as_ref().unwrap().op, ND::LVAR);
+        assert_eq!(lt.rhs.as_ref().unwrap().op, ND::LVAR);
+        // The result is converted to the return type.
+        assert_eq!(stmts[4].expr.as_ref().unwrap().ty.ty, TY::INT);
+        let ret = check("char f() { int x; return x; }").unwrap();
+        assert_eq!(ret[0].body.as_ref().unwrap().stmts[1].expr.as_ref().unwrap().ty.ty, TY::CHAR);
+
+        // Case values are converted to the type of the condition.
+        let nodes = check("int main() { unsigned u; switch (u) { case -1: case 4294967295: ; } }");
+        assert_eq!(nodes.unwrap_err().msg, "duplicate case value '4294967295'");
+        let nodes = check("int main() { unsigned u; switch (u) { case -1: ; } return 0; }").unwrap();
+        assert_eq!(nodes[0].body.as_ref().unwrap().stmts[1].cases, [4294967295]);
+        assert_eq!(check("struct S { int a; }; int main() { return (struct S)1; }").unwrap_err().msg,
+                   "used type 'struct S' where arithmetic or pointer type is required");
+        assert_eq!(check("int main() { int *p; switch (p) { } }").unwrap_err().msg, "statement requires expression of integer type");
+    }
+
     # [test]
     fn can_gen_walk_arithmetic_expr() {
         let input = [
@@ -188,24 +1356,24 @@ mod tests {
                                         op: ND::OPE('+'),
                                         lhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "2".to_string(), ..Default::default() })),
+                                            num: 2, ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::OPE('*'),
                                             lhs: Some(Box::new(Node {
                                                 op: ND::NUM,
-                                                val: "2".to_string(), ..Default::default()})),
+                                                num: 2, ..Default::default()})),
                                             rhs: Some(Box::new(Node {
                                                 op: ND::NUM,
-                                                val: "3".to_string(), ..Default::default()})),
+                                                num: 3, ..Default::default()})),
                                             ..Default::default()})),
                                         ..Default::default()})),
                                     rhs: Some(Box::new(Node {
                                         op: ND::NUM,
-                                        val: "2".to_string(), ..Default::default()
+                                        num: 2, ..Default::default()
                                     })),  ..Default::default()})),
                                 rhs: Some(Box::new(Node {
                                     op: ND::NUM,
-                                    val: "1".to_string(), ..Default::default() })),
+                                    num: 1, ..Default::default() })),
                                 ..Default::default()
                             })),
                             ..Default::default()
@@ -213,7 +1381,7 @@ mod tests {
                     ..Default::default() })),
                 ..Default::default()}];
 
-        let result = SemaGenerator::new().sema(input.to_vec());
+        let result = SemaGenerator::new().sema(input.to_vec()).unwrap();
 
         let expect = [
             Node {
@@ -232,24 +1400,24 @@ mod tests {
                                         op: ND::OPE('+'),
                                         lhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "2".to_string(), ..Default::default() })),
+                                            num: 2, ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::OPE('*'),
                                             lhs: Some(Box::new(Node {
                                                 op: ND::NUM,
-                                                val: "2".to_string(), ..Default::default()})),
+                                                num: 2, ..Default::default()})),
                                             rhs: Some(Box::new(Node {
                                                 op: ND::NUM,
-                                                val: "3".to_string(), ..Default::default()})),
+                                                num: 3, ..Default::default()})),
                                             ..Default::default()})),
                                         ..Default::default()})),
                                     rhs: Some(Box::new(Node {
                                         op: ND::NUM,
-                                        val: "2".to_string(), ..Default::default()
+                                        num: 2, ..Default::default()
                                     })),  ..Default::default()})),
                                 rhs: Some(Box::new(Node {
                                     op: ND::NUM,
-                                    val: "1".to_string(), ..Default::default() })),
+                                    num: 1, ..Default::default() })),
                                 ..Default::default()
                             })),
                             ..Default::default()
@@ -302,8 +1470,8 @@ mod tests {
                                 op: ND::CALL,
                                 val: "add".to_string(),
                                 args: [
-                                    Node { op: ND::NUM, val: "1".to_string(), ..Default::default() },
-                                    Node { op: ND::NUM, val: "2".to_string(), ..Default::default() }
+                                    Node { op: ND::NUM, num: 1, ..Default::default() },
+                                    Node { op: ND::NUM, num: 2, ..Default::default() }
                                 ].to_vec(),
                                 ..Default::default()
                             })),
@@ -315,7 +1483,7 @@ mod tests {
                 ..Default::default()
             }];
 
-        let result = SemaGenerator::new().sema(input.to_vec());
+        let result = SemaGenerator::new().sema(input.to_vec()).unwrap();
 
         let expect = [
             Node {
@@ -355,8 +1523,8 @@ mod tests {
                                 op: ND::CALL,
                                 val: "add".to_string(),
                                 args: [
-                                    Node { op: ND::NUM, val: "1".to_string(), ..Default::default() },
-                                    Node { op: ND::NUM, val: "2".to_string(), ..Default::default() }
+                                    Node { op: ND::NUM, num: 1, ..Default::default() },
+                                    Node { op: ND::NUM, num: 2, ..Default::default() }
                                 ].to_vec(),
                                 ..Default::default()
                             })),
@@ -401,7 +1569,7 @@ mod tests {
                                     ..Default::default() })),
                                 rhs: Some(Box::new(Node {
                                     op: ND::NUM,
-                                    val: "3".to_string(), ..Default::default() })), ..Default::default()})),
+                                    num: 3, ..Default::default() })), ..Default::default()})),
                             ..Default::default()},
                         Node {
                             op: ND::EXPR_STMT,
@@ -416,11 +1584,11 @@ mod tests {
                                             val: "ary".to_string(), ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "1".to_string(),
+                                            num: 1,
                                             ..Default::default()})),
                                         ..Default::default()})),
                                     ..Default::default()})),
-                                rhs: Some(Box::new(Node { op: ND::NUM, val: "7".to_string(), ..Default::default() })),
+                                rhs: Some(Box::new(Node { op: ND::NUM, num: 7, ..Default::default() })),
                                 ..Default::default()})),
                             ..Default::default()},
                         Node {
@@ -442,7 +1610,7 @@ mod tests {
                                             val: "ary".to_string(), ..Default::default()})),
                                         rhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "1".to_string(), ..Default::default() })),
+                                            num: 1, ..Default::default() })),
                                         ..Default::default() })),
                                     ..Default::default() })),
                                 ..Default::default() })),
@@ -451,7 +1619,7 @@ mod tests {
                 ..Default::default() }
         ];
 
-        let result = SemaGenerator::new().sema(input.to_vec());
+        let result = SemaGenerator::new().sema(input.to_vec()).unwrap();
 
         let expect = [
             Node {
@@ -483,7 +1651,7 @@ mod tests {
                                     ..Default::default() })),
                                 rhs: Some(Box::new(Node {
                                     op: ND::NUM,
-                                    val: "3".to_string(), ..Default::default() })), ..Default::default()})),
+                                    num: 3, ..Default::default() })), ..Default::default()})),
                             ..Default::default()},
                         Node {
                             op: ND::EXPR_STMT,
@@ -504,11 +1672,11 @@ mod tests {
                                              ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "1".to_string(),
+                                            num: 1,
                                             ..Default::default()})),
                                         ..Default::default()})),
                                     ..Default::default()})),
-                                rhs: Some(Box::new(Node { op: ND::NUM, val: "7".to_string(), ..Default::default() })),
+                                rhs: Some(Box::new(Node { op: ND::NUM, num: 7, ..Default::default() })),
                                 ..Default::default()})),
                             ..Default::default()},
                         Node {
@@ -541,7 +1709,7 @@ mod tests {
                                             ..Default::default() })),
                                         rhs: Some(Box::new(Node {
                                             op: ND::NUM,
-                                            val: "1".to_string(), ..Default::default() })),
+                                            num: 1, ..Default::default() })),
                                         ..Default::default() })),
                                     ..Default::default() })),
                                 ..Default::default() })),
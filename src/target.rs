@@ -0,0 +1,92 @@
+// Platform conventions the generated assembly has to follow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Linux,   // x86_64-linux-gnu: ELF, System V
+    Darwin,  // x86_64-apple-darwin: Mach-O
+}
+
+pub static TRIPLES: [&str; 2] = ["x86_64-linux-gnu", "x86_64-apple-darwin"];
+
+impl Target {
+    pub fn from_triple(triple: &str) -> Option<Target> {
+        match triple {
+            "x86_64-linux-gnu" => return Some(Target::Linux),
+            "x86_64-apple-darwin" => return Some(Target::Darwin),
+            _ => return None,
+        }
+    }
+
+    pub fn host() -> Target {
+        if cfg!(target_os = "macos") {
+            return Target::Darwin
+        }
+        return Target::Linux
+    }
+
+    // Mach-O prefixes C symbols with an underscore, ELF does not.
+    pub fn symbol(&self, name: &str) -> String {
+        match *self {
+            Target::Linux => return name.to_string(),
+            Target::Darwin => return format!("_{}", name),
+        }
+    }
+
+    // String literals without a NUL inside.
+    pub fn rodata_section(&self) -> &'static str {
+        match *self {
+            Target::Linux => return ".section .rodata",
+            Target::Darwin => return ".cstring",
+        }
+    }
+
+    // Other read-only data, such as jump tables. Mach-O
+    // splits .cstring at NUL bytes, so they can't go there.
+    pub fn const_section(&self) -> &'static str {
+        match *self {
+            Target::Linux => return ".section .rodata",
+            Target::Darwin => return ".const",
+        }
+    }
+
+    // Both ABIs want rsp 16-byte aligned at every call instruction.
+    pub fn stack_align(&self) -> usize {
+        return 16
+    }
+
+    // Directives before a function label, e.g. its ELF symbol type.
+    pub fn function_begin(&self, name: &str) -> Vec<String> {
+        let sym = self.symbol(name);
+        match *self {
+            Target::Linux => return vec![format!(".global {}", sym), format!(".type {}, @function", sym)],
+            Target::Darwin => return vec![format!(".global {}", sym)],
+        }
+    }
+
+    pub fn function_end(&self, name: &str) -> Vec<String> {
+        let sym = self.symbol(name);
+        match *self {
+            Target::Linux => return vec![format!(".size {}, .-{}", sym, sym)],
+            Target::Darwin => return vec![],
+        }
+    }
+
+    // Trailer for the whole file. On Linux this marks the stack as
+    // non-executable so ld does not warn.
+    pub fn file_end(&self) -> Vec<String> {
+        match *self {
+            Target::Linux => return vec![".section .note.GNU-stack,\"\",@progbits".to_string()],
+            Target::Darwin => return vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    # [test]
+    fn can_mangle_symbols() {
+        assert_eq!(Target::from_triple("x86_64-linux-gnu").unwrap().symbol("main"), "main");
+        assert_eq!(Target::from_triple("x86_64-apple-darwin").unwrap().symbol("main"), "_main");
+        assert_eq!(Target::from_triple("i386-pc-windows"), None);
+    }
+}
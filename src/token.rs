@@ -1,107 +1,356 @@
 extern crate rugcc;
-use self::rugcc::common::{TK, Token};
+use self::rugcc::common::{TK, TY, Token, Type, SourceFile, Span, CompileError};
+use std::rc::Rc;
 
-use std::process;
+// Line, column and byte offset of every character, plus one past the end.
+fn locate(s: &[char]) -> Vec<(usize, usize, usize)> {
+    let mut locs = Vec::with_capacity(s.len() + 1);
+    let (mut line, mut col, mut offset) = (1, 1, 0);
+    for c in s {
+        locs.push((line, col, offset));
+        offset += c.len_utf8();
+        if *c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    locs.push((line, col, offset));
+    return locs
+}
+
+// Decodes the escape sequence whose backslash is at `s[i]`, giving its value
+// and the index just past it. None if the sequence is not one C knows.
+fn escape(s: &[char], i: usize) -> Option<(u32, usize)> {
+    let c = *s.get(i + 1)?;
+    let simple = match c {
+        'n' => Some(b'\n'), 't' => Some(b'\t'), 'r' => Some(b'\r'), 'a' => Some(7), 'b' => Some(8),
+        'f' => Some(12), 'v' => Some(11), 'e' => Some(27), '\\' | '\'' | '"' | '?' => Some(c as u8),
+        _ => None,
+    };
+    if let Some(v) = simple {
+        return Some((v as u32, i + 2));
+    }
+    if c.is_digit(8) {
+        let end = (i + 1..(i + 4).min(s.len())).take_while(|&j| s[j].is_digit(8)).last().unwrap() + 1;
+        return Some((s[i + 1..end].iter().fold(0, |n, c| n * 8 + c.to_digit(8).unwrap()), end));
+    }
+    if c == 'x' {
+        let digits: Vec<u32> = s[i + 2..].iter().take_while(|c| c.is_ascii_hexdigit()).map(|c| c.to_digit(16).unwrap()).collect();
+        if digits.is_empty() {
+            return None;
+        }
+        // Saturate rather than overflow; anything above 0xff is rejected anyway.
+        let v = digits.iter().fold(0u32, |n, &d| n.saturating_mul(16).saturating_add(d));
+        return Some((v, i + 2 + digits.len()));
+    }
+    return None
+}
+
+// The bytes a string or character literal stands for, given its spelling
+// between the quotes. The lexer has already checked the escapes.
+pub fn unescape(raw: &str) -> Vec<u8> {
+    let s: Vec<char> = raw.chars().collect();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == '\\' && s.get(i + 1) == Some(&'\n') {
+            i += 2;
+        } else if s[i] == '\\' {
+            let (v, next) = escape(&s, i).unwrap();
+            bytes.push(v as u8);
+            i = next;
+        } else {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(s[i].encode_utf8(&mut buf).as_bytes());
+            i += 1;
+        }
+    }
+    return bytes
+}
+
+// The value and type of an integer literal such as 0x1fUL. The type is the
+// first in the C11 list for its base and suffix that can hold the value.
+pub fn int_literal(spelling: &str) -> Result<(u64, Type), String> {
+    let lower = spelling.to_lowercase();
+    let (radix, start) = if lower.starts_with("0x") {
+        (16, 2)
+    } else if lower.starts_with("0b") {
+        (2, 2)
+    } else if spelling.starts_with('0') {
+        (8, 1)
+    } else {
+        (10, 0)
+    };
+    let end = match radix {
+        16 => start + spelling[start..].chars().take_while(|c| c.is_ascii_hexdigit()).count(),
+        _ => start + spelling[start..].chars().take_while(|c| c.is_ascii_digit()).count(),
+    };
+    let digits = &spelling[start..end];
+    if digits.is_empty() && radix != 8 {
+        return Err(format!("invalid integer constant: {}", spelling));
+    }
+    if let Some(c) = digits.chars().find(|c| !c.is_digit(radix)) {
+        let base = if radix == 8 { "octal" } else { "binary" };
+        return Err(format!("invalid digit '{}' in {} constant", c, base));
+    }
+    let suffix = &spelling[end..];
+    let is_u = |c| c == 'u' || c == 'U';
+    let (unsigned, longs) = if suffix.starts_with(is_u) {
+        (true, &suffix[1..])
+    } else if suffix.ends_with(is_u) {
+        (true, &suffix[..suffix.len() - 1])
+    } else {
+        (false, suffix)
+    };
+    let longs = match longs {
+        "" => 0,
+        "l" | "L" => 1,
+        "ll" | "LL" => 2,
+        _ => return Err(format!("invalid suffix '{}' on integer constant", suffix)),
+    };
+    let value = match u64::from_str_radix(if digits.is_empty() { "0" } else { digits }, radix) {
+        Ok(value) => value,
+        Err(_) => return Err(format!("integer constant is too large: {}", spelling)),
+    };
+
+    let int = |ty: TY, is_unsigned: bool| Type{ty, is_unsigned, ..Default::default()};
+    let candidates = [
+        (int(TY::INT, false), i32::MAX as u64), (int(TY::INT, true), u32::MAX as u64),
+        (int(TY::LONG, false), i64::MAX as u64), (int(TY::LONG, true), u64::MAX),
+        (int(TY::LLONG, false), i64::MAX as u64), (int(TY::LLONG, true), u64::MAX),
+    ];
+    let rank = |ty: &TY| match *ty { TY::INT => 0, TY::LONG => 1, _ => 2 };
+    for &(ref ty, max) in &candidates {
+        // Decimal literals without `u` never become unsigned.
+        let allowed = rank(&ty.ty) >= longs && (ty.is_unsigned || !unsigned) && (!ty.is_unsigned || unsigned || radix != 10);
+        if allowed && value <= max {
+            return Ok((value, ty.clone()));
+        }
+    }
+    return Err(format!("integer constant is too large for its type: {}", spelling))
+}
+
+// Whether a number is a floating constant: one with a fraction or an exponent.
+pub fn is_float_literal(spelling: &str) -> bool {
+    let lower = spelling.to_lowercase();
+    if lower.starts_with("0x") {
+        return lower.contains('p')
+    }
+    return lower.contains('.') || lower.contains('e')
+}
+
+// The value and type of a floating constant such as 1.5e3 or 2.5f. Float
+// constants are rounded to float precision straight from the decimal.
+pub fn float_literal(spelling: &str) -> Result<(f64, Type), String> {
+    let lower = spelling.to_lowercase();
+    if lower.starts_with("0x") {
+        return Err(format!("hexadecimal floating constants are not supported: {}", spelling));
+    }
+    let (digits, ty) = if lower.ends_with('f') {
+        (&spelling[..spelling.len() - 1], TY::FLOAT)
+    } else if lower.ends_with('l') {
+        return Err("long double is not supported".to_string());
+    } else {
+        (spelling, TY::DOUBLE)
+    };
+    let value = if ty == TY::FLOAT { digits.parse::<f32>().map(|f| f as f64) } else { digits.parse::<f64>() };
+    match value {
+        Ok(value) => return Ok((value, Type{ty, ..Default::default()})),
+        Err(_) => return Err(format!("invalid floating constant: {}", spelling)),
+    }
+}
 
-pub fn tokenize(s: Vec<char>) -> Vec<Token>{
+pub fn tokenize(file: Rc<SourceFile>) -> Result<Vec<Token>, CompileError> {
+    let s: Vec<char> = file.text.chars().collect();
+    let locs = locate(&s);
+    let span = |start: usize, end: usize| {
+        let (line, col, offset) = locs[start];
+        Span{file: Some(file.clone()), line, col, offset, len: locs[end].2 - offset}
+    };
     let mut counter: usize = 0;
     let mut tokens: Vec<Token> = Vec::new();
     let size = s.len();
     while counter < size {
         let c = s[counter];
+        let start = counter;
         if c.is_whitespace() || c == '\n' {
             counter += 1;
             continue;
         }
-        let opes: Vec<char> = "+-*/=(),{}&|<>[]&".chars().collect();
-        if opes.contains(&c) {
-            if c=='&' {
-                counter += 1;
-                if s[counter] == '&' {
-                    tokens.push(Token{ty: TK::LOGAND, val: "&&".to_string(), ..Default::default()});
-                    counter += 1;
-                    continue;
-                } else {
-                    tokens.push(Token{ty: TK::OPE(c), val: c.to_string(), ..Default::default()});
-                    continue;
-                }
-            } else if c=='|' {
+        // A backslash-newline joins two lines, as used by long #defines.
+        if c == '\\' && counter + 1 < size && s[counter + 1] == '\n' {
+            counter += 2;
+            continue;
+        }
+        if c == '/' && s.get(counter + 1) == Some(&'/') {
+            while counter < size && s[counter] != '\n' {
                 counter += 1;
-                if s[counter] == '|' {
-                    tokens.push(Token{ty: TK::LOGOR, val: "||".to_string(), ..Default::default()});
-                    counter += 1;
-                    continue;
-                } else {
-                    print!("cannot tokenize: {}\n", c);
-                    process::exit(1);
+            }
+            continue;
+        }
+        if c == '/' && s.get(counter + 1) == Some(&'*') {
+            counter += 2;
+            while !(s.get(counter) == Some(&'*') && s.get(counter + 1) == Some(&'/')) {
+                if counter >= size {
+                    error!(Lex, span(start, start + 2), "unterminated comment");
                 }
-            } else {
-                tokens.push(Token{ty: TK::OPE(c), val: c.to_string(), ..Default::default()});
                 counter += 1;
-                continue;
             }
+            counter += 2;
+            continue;
+        }
+        // Longest first, so `<<=` is not read as `<<` and `=`.
+        let puncts = [("<<=", TK::SHL_EQ), (">>=", TK::SHR_EQ), ("...", TK::ELLIPSIS),
+                      ("##", TK::HASHHASH), ("&&", TK::LOGAND), ("||", TK::LOGOR), ("==", TK::EQ), ("!=", TK::NE),
+                      ("<=", TK::LE), (">=", TK::GE), ("<<", TK::SHL), (">>", TK::SHR), ("++", TK::INC), ("--", TK::DEC), ("->", TK::ARROW),
+                      ("+=", TK::ADD_EQ), ("-=", TK::SUB_EQ), ("*=", TK::MUL_EQ), ("/=", TK::DIV_EQ), ("%=", TK::MOD_EQ),
+                      ("&=", TK::AND_EQ), ("|=", TK::OR_EQ), ("^=", TK::XOR_EQ)];
+        let at = |p: &str| p.chars().enumerate().all(|(i, c)| s.get(counter + i) == Some(&c));
+        if let Some(&(p, ref ty)) = puncts.iter().find(|&&(p, _)| at(p)) {
+            counter += p.len();
+            tokens.push(Token{ty: ty.clone(), val: p.to_string(), span: span(start, counter), ..Default::default()});
+            continue;
         }
-        if c=='"' {
-            let mut string = Vec::new();
+        let opes: Vec<char> = "+-*/%=(),{}&|^~!?:<>[].#".chars().collect();
+        let starts_number = c.is_digit(10) || (c == '.' && s.get(counter + 1).is_some_and(|c| c.is_ascii_digit()));
+        if opes.contains(&c) && !starts_number {
             counter += 1;
-            while s[counter] != '"' {
-                string.push(s[counter]);
-                counter += 1;
+            tokens.push(Token{ty: TK::OPE(c), val: c.to_string(), span: span(start, counter), ..Default::default()});
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            // Find the closing quote, checking escapes on the way.
+            counter += 1;
+            loop {
+                match s.get(counter) {
+                    None | Some(&'\n') => {
+                        let what = if c == '"' { "string literal" } else { "character constant" };
+                        error!(Lex, span(start, start + 1), "unterminated {}", what);
+                    },
+                    Some(&q) if q == c => break,
+                    Some(&'\\') if s.get(counter + 1) == Some(&'\n') => counter += 2,
+                    Some(&'\\') => {
+                        match escape(&s, counter) {
+                            Some((v, next)) if v <= 0xff => counter = next,
+                            Some((_, next)) => error!(Lex, span(counter, next), "escape sequence out of range"),
+                            None => error!(Lex, span(counter, (counter + 2).min(size)), "unknown escape sequence"),
+                        }
+                    },
+                    _ => counter += 1,
+                }
             }
-            tokens.push(Token{ty: TK::STR, val: c.to_string(), str: string.iter().collect(), ..Default::default()});
             counter += 1;
+            let raw: String = s[start + 1..counter - 1].iter().collect();
+            if c == '"' {
+                tokens.push(Token{ty: TK::STR, val: c.to_string(), str: raw, span: span(start, counter)});
+                continue;
+            }
+            match unescape(&raw).len() {
+                0 => error!(Lex, span(start, counter), "empty character constant"),
+                1 => {},
+                _ => error!(Lex, span(start, counter), "multi-character character constant"),
+            }
+            tokens.push(Token{ty: TK::CHAR_CONST, val: s[start..counter].iter().collect(), span: span(start, counter), ..Default::default()});
             continue;
         }
         if c==';' {
-            tokens.push(Token{ty: TK::END_LINE, val: c.to_string(), ..Default::default()});
             counter += 1;
+            tokens.push(Token{ty: TK::END_LINE, val: c.to_string(), span: span(start, counter), ..Default::default()});
             continue;
         }
         if c.is_alphabetic() || c=='_' {
             let mut name = Vec::new();
             name.push(c);
             counter += 1;
-            while s[counter].is_alphabetic() || s[counter].is_digit(10) || s[counter] == '_' {
+            while counter < size && (s[counter].is_alphabetic() || s[counter].is_digit(10) || s[counter] == '_') {
                 name.push(s[counter]);
                 counter += 1;
             }
-            match  name.iter().collect::<String>().as_str()  {
-                "return" => tokens.push(Token{ty: TK::RETURN, val: name.iter().collect(), ..Default::default()}),
-                "if" => tokens.push(Token{ty: TK::IF, val: name.iter().collect(), ..Default::default()}),
-                "else" => tokens.push(Token{ty: TK::ELSE, val: name.iter().collect(), ..Default::default()}),
-                "for" => tokens.push(Token{ty: TK::FOR, val: name.iter().collect(), ..Default::default()}),
-                "int" => tokens.push(Token{ty: TK::INT, val: name.iter().collect(), ..Default::default()}),
-                "char" => tokens.push(Token{ty: TK::CHAR, val: name.iter().collect(), ..Default::default()}),
-                "sizeof" => tokens.push(Token{ty: TK::SIZEOF, val: name.iter().collect(), ..Default::default()}),
-                _ => tokens.push(Token{ty: TK::IDENT, val: name.iter().collect(), ..Default::default()}),
-            }
+            let ty = match  name.iter().collect::<String>().as_str()  {
+                "return" => TK::RETURN,
+                "if" => TK::IF,
+                "else" => TK::ELSE,
+                "for" => TK::FOR,
+                "while" => TK::WHILE,
+                "do" => TK::DO,
+                "break" => TK::BREAK,
+                "continue" => TK::CONTINUE,
+                "goto" => TK::GOTO,
+                "switch" => TK::SWITCH,
+                "case" => TK::CASE,
+                "default" => TK::DEFAULT,
+                "extern" => TK::EXTERN,
+                "static" => TK::STATIC,
+                "struct" => TK::STRUCT,
+                "union" => TK::UNION,
+                "int" => TK::INT,
+                "char" => TK::CHAR,
+                "short" => TK::SHORT,
+                "long" => TK::LONG,
+                "signed" => TK::SIGNED,
+                "unsigned" => TK::UNSIGNED,
+                "_Bool" => TK::BOOL,
+                "float" => TK::FLOAT,
+                "double" => TK::DOUBLE,
+                "sizeof" => TK::SIZEOF,
+                _ => TK::IDENT,
+            };
+            tokens.push(Token{ty, val: name.iter().collect(), span: span(start, counter), ..Default::default()});
             continue;
         }
-        if c.is_digit(10){
+        if starts_number {
+            // Take the whole literal, suffix and all, then check it. A sign
+            // right after an exponent's `e` (`p` in hex) belongs to the number.
             let mut tmp = String::new();
-            while s[counter].is_digit(10){
-                tmp += &s[counter].to_string();
+            while counter < size {
+                let c = s[counter];
+                let hex = tmp.starts_with("0x") || tmp.starts_with("0X");
+                let exp = if hex { ['p', 'P'] } else { ['e', 'E'] };
+                let exp_sign = (c == '+' || c == '-') && tmp.ends_with(&exp[..]);
+                if !(c.is_alphanumeric() || c == '_' || c == '.' || exp_sign) {
+                    break
+                }
+                tmp.push(c);
                 counter += 1;
             }
-            tokens.push(Token{ty: TK::NUM, val: tmp, ..Default::default()});
+            let checked = if is_float_literal(&tmp) { float_literal(&tmp).map(|_| ()) } else { int_literal(&tmp).map(|_| ()) };
+            if let Err(msg) = checked {
+                error!(Lex, span(start, counter), "{}", msg);
+            }
+            tokens.push(Token{ty: TK::NUM, val: tmp, span: span(start, counter), ..Default::default()});
             continue;
         }
-        print!("cannot tokenize: {}\n", c);
-        process::exit(1);
+        error!(Lex, span(start, start + 1), "cannot tokenize: {}", c);
     }
-    tokens.push(Token{ty: TK::EOF, val: "EOF".to_string(), ..Default::default()});
+    tokens.push(Token{ty: TK::EOF, val: "EOF".to_string(), span: span(size, size), ..Default::default()});
     tokens.reverse();
-    return tokens
+    return Ok(tokens)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use self::rugcc::common::ErrorKind;
+
+    fn source(text: &str) -> Rc<SourceFile> {
+        return Rc::new(SourceFile{name: "test.c".to_string(), text: text.to_string()})
+    }
+
+    // Hand-written expectations have no location, so drop it from the result.
+    fn without_spans(mut tokens: Vec<Token>) -> Vec<Token> {
+        for t in tokens.iter_mut() {
+            t.span = Span::default();
+        }
+        return tokens
+    }
+
     # [test]
     fn can_tokenize_arithmetic_expr() {
-        let input = "int main() { return (2+2*3)/2-1;}".chars().collect();
+        let input = source("int main() { return (2+2*3)/2-1;}");
 
-        let result = tokenize(input);
+        let result = without_spans(tokenize(input).unwrap());
         let expect = [
             Token { ty: TK::EOF, val: "EOF".to_string().to_string() , ..Default::default()}, Token { ty: TK::OPE('}'), val: "}".to_string() , ..Default::default()},
             Token { ty: TK::END_LINE, val: ";".to_string() , ..Default::default()}, Token { ty: TK::NUM, val: "1".to_string() , ..Default::default()},
@@ -122,9 +371,9 @@ mod tests {
 
     # [test]
     fn can_tokenize_function() {
-        let input = "int add(int a,int b) {return a+b;} int main() { return add(1,2); }".chars().collect();
+        let input = source("int add(int a,int b) {return a+b;} int main() { return add(1,2); }");
 
-        let result = tokenize(input);
+        let result = without_spans(tokenize(input).unwrap());
 
         let expect = [
             Token { ty: TK::EOF, val: "EOF".to_string(), ..Default::default() }, Token { ty: TK::OPE('}'), val: "}".to_string(), ..Default::default() },
@@ -154,9 +403,9 @@ mod tests {
 
     # [test]
     fn can_tokenize_pointer(){
-        let input =  "int main() { int ary[2]; *ary=3; *(ary+1)=7; return *ary + *(ary+1);}".chars().collect();
+        let input = source("int main() { int ary[2]; *ary=3; *(ary+1)=7; return *ary + *(ary+1);}");
 
-        let result = tokenize(input);
+        let result = without_spans(tokenize(input).unwrap());
 
         let expect = [
             Token { ty: TK::EOF, val: "EOF".to_string(), ..Default::default() }, Token { ty: TK::OPE('}'), val: "}".to_string(), ..Default::default() },
@@ -185,4 +434,129 @@ mod tests {
             assert_eq!(result[i], expect[i]);
         }
     }
+
+    # [test]
+    fn can_locate_tokens() {
+        let input = source("int main() {\n  return 42;\n}");
+
+        let result = tokenize(input).unwrap();
+
+        let num = result.iter().find(|t| t.ty == TK::NUM).unwrap();
+        assert_eq!((num.span.line, num.span.col, num.span.offset, num.span.len), (2, 10, 22, 2));
+        let eof = &result[0];
+        assert_eq!((eof.span.line, eof.span.col, eof.span.offset), (3, 2, 27));
+    }
+
+    # [test]
+    fn can_tokenize_operators() {
+        let input = source("a<<=b>>=c<<d++ +=--e&&f&=g");
+
+        let result = tokenize(input).unwrap();
+
+        let tys: Vec<TK> = result.into_iter().rev().map(|t| t.ty).collect();
+        assert_eq!(tys, [TK::IDENT, TK::SHL_EQ, TK::IDENT, TK::SHR_EQ, TK::IDENT, TK::SHL, TK::IDENT, TK::INC, TK::ADD_EQ,
+                         TK::DEC, TK::IDENT, TK::LOGAND, TK::IDENT, TK::AND_EQ, TK::IDENT, TK::EOF]);
+    }
+
+    # [test]
+    fn can_skip_comments() {
+        let input = source("int x; // a / b\n/* int y;\n ** */ return x /**/ / 2;");
+
+        let result = tokenize(input).unwrap();
+
+        let vals: Vec<&str> = result.iter().rev().map(|t| t.val.as_str()).collect();
+        assert_eq!(vals, ["int", "x", ";", "return", "x", "/", "2", ";", "EOF"]);
+        assert_eq!(result[3].span.line, 3);
+    }
+
+    # [test]
+    fn cannot_tokenize_unterminated_comment() {
+        let input = source("int x;\n  /* int y;\n");
+
+        let err = tokenize(input).unwrap_err();
+        assert_eq!((err.kind, err.msg.as_str()), (ErrorKind::Lex, "unterminated comment"));
+        assert_eq!((err.span.line, err.span.col), (2, 3));
+    }
+
+    # [test]
+    fn can_tokenize_escapes() {
+        let input = source(r#"char *s = "a\"b\n\x41\101\0"; int c = '\'';"#);
+
+        let result = tokenize(input).unwrap();
+
+        let s = result.iter().find(|t| t.ty == TK::STR).unwrap();
+        assert_eq!(s.str, r#"a\"b\n\x41\101\0"#);
+        assert_eq!(unescape(&s.str), b"a\"b\nAA\0");
+        let c = result.iter().find(|t| t.ty == TK::CHAR_CONST).unwrap();
+        assert_eq!(c.val, r"'\''");
+        assert_eq!(unescape("\\377\\\nx\\\\"), [0xff, b'x', b'\\']);
+    }
+
+    # [test]
+    fn cannot_tokenize_bad_literals() {
+        let cases = [
+            ("char *s = \"abc;\n", "unterminated string literal", 11),
+            ("int c = 'a;", "unterminated character constant", 9),
+            ("int c = '';", "empty character constant", 9),
+            ("int c = 'ab';", "multi-character character constant", 9),
+            ("char *s = \"\\q\";", "unknown escape sequence", 12),
+            ("char *s = \"\\x100\";", "escape sequence out of range", 12),
+        ];
+        for &(text, msg, col) in &cases {
+            let err = tokenize(source(text)).unwrap_err();
+            assert_eq!((err.kind, err.msg.as_str(), err.span.col), (ErrorKind::Lex, msg, col));
+        }
+    }
+
+    # [test]
+    fn can_read_int_literals() {
+        let ty = |ty: TY, is_unsigned: bool| Type{ty, is_unsigned, ..Default::default()};
+        assert_eq!(int_literal("42").unwrap(), (42, ty(TY::INT, false)));
+        assert_eq!(int_literal("0").unwrap(), (0, ty(TY::INT, false)));
+        assert_eq!(int_literal("0x1F").unwrap(), (31, ty(TY::INT, false)));
+        assert_eq!(int_literal("017").unwrap(), (15, ty(TY::INT, false)));
+        assert_eq!(int_literal("0b101").unwrap(), (5, ty(TY::INT, false)));
+        assert_eq!(int_literal("2147483648").unwrap(), (2147483648, ty(TY::LONG, false)));
+        assert_eq!(int_literal("0x80000000").unwrap(), (0x80000000, ty(TY::INT, true)));
+        assert_eq!(int_literal("0xffffffffffffffff").unwrap(), (u64::MAX, ty(TY::LONG, true)));
+        assert_eq!(int_literal("1u").unwrap(), (1, ty(TY::INT, true)));
+        assert_eq!(int_literal("1L").unwrap(), (1, ty(TY::LONG, false)));
+        assert_eq!(int_literal("1uLL").unwrap(), (1, ty(TY::LLONG, true)));
+        assert_eq!(int_literal("1llu").unwrap(), (1, ty(TY::LLONG, true)));
+        assert_eq!(int_literal("08").unwrap_err(), "invalid digit '8' in octal constant");
+        assert_eq!(int_literal("0b2").unwrap_err(), "invalid digit '2' in binary constant");
+        assert_eq!(int_literal("1lul").unwrap_err(), "invalid suffix 'lul' on integer constant");
+        assert_eq!(int_literal("1uu").unwrap_err(), "invalid suffix 'uu' on integer constant");
+        assert_eq!(int_literal("1lL").unwrap_err(), "invalid suffix 'lL' on integer constant");
+        assert_eq!(int_literal("0x").unwrap_err(), "invalid integer constant: 0x");
+        assert_eq!(int_literal("18446744073709551616").unwrap_err(), "integer constant is too large: 18446744073709551616");
+        assert_eq!(int_literal("9223372036854775808").unwrap_err(), "integer constant is too large for its type: 9223372036854775808");
+    }
+
+    # [test]
+    fn can_read_float_literals() {
+        let ty = |ty: TY| Type{ty, ..Default::default()};
+        assert_eq!(float_literal("1.5").unwrap(), (1.5, ty(TY::DOUBLE)));
+        assert_eq!(float_literal(".5e1").unwrap(), (5.0, ty(TY::DOUBLE)));
+        assert_eq!(float_literal("1e-2").unwrap(), (0.01, ty(TY::DOUBLE)));
+        assert_eq!(float_literal("0.1f").unwrap(), (0.1f32 as f64, ty(TY::FLOAT)));
+        assert_eq!(float_literal("1.l").unwrap_err(), "long double is not supported");
+        assert_eq!(float_literal("0x1p3").unwrap_err(), "hexadecimal floating constants are not supported: 0x1p3");
+        assert_eq!(float_literal("1e").unwrap_err(), "invalid floating constant: 1e");
+
+        let input = source("x=1.5e+3-.25f+a.b");
+        let tokens: Vec<String> = tokenize(input).unwrap().into_iter().rev().map(|t| t.val).collect();
+        assert_eq!(tokens, ["x", "=", "1.5e+3", "-", ".25f", "+", "a", ".", "b", "EOF"]);
+    }
+
+    # [test]
+    fn cannot_tokenize_unknown_char() {
+        let input = source("int main() { return 1 $ 2; }");
+
+        let result = tokenize(input);
+
+        let err = result.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Lex);
+        assert_eq!((err.span.line, err.span.col), (1, 23));
+    }
 }
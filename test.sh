@@ -1,33 +1,7 @@
 #!/bin/bash
 
 runtest() {
-    ./target/debug/rugcc "$1" > ./tmp.s
-    cat <<EOF | gcc -xc -c -o tmp-test.o -
-        int plus(int x, int y) { return x + y; }
-        int *alloc1(int x, int y) {
-          static int arr[2];
-          arr[0] = x;
-          arr[1] = y;
-          return arr;
-        }
-        int *alloc2(int x, int y) {
-          static int arr[2];
-          arr[0] = x;
-          arr[1] = y;
-          return arr + 1;
-        }
-        int **alloc_ptr_ptr(int x) {
-          static int **p;
-          static int *q;
-          static int r;
-          r = x;
-          q = &r;
-          p = &q;
-          return p;
-        }
-EOF
-    cc -o ./tmp.exe ./tmp.s ./tmp-test.o
-    ./tmp.exe
+    ./target/debug/rugcc --run -e "$1" ./tmp-test.so
     out=$?
     if [ "$out" != "$2" ]; then
         echo "$1: $2 expected. but got $out"
@@ -35,7 +9,19 @@ EOF
         exit 1
     fi
     echo "$1 => $2"
-    rm -f ./tmp*
+}
+
+# Builds an executable through the assembler and linker instead of --run.
+exetest() {
+    ./target/debug/rugcc -e "$1" "${@:3}" -o ./tmp.exe && ./tmp.exe
+    out=$?
+    if [ "$out" != "$2" ]; then
+        echo "$1: $2 expected. but got $out"
+        rm -f ./tmp*
+        exit 1
+    fi
+    echo "$1 => $2 (executable)"
+    rm -f ./tmp.exe
 }
 
 cargo build && cargo test
@@ -43,6 +29,92 @@ if [ $? != "0" ]; then
     exit 1
 fi
 
+cat <<EOF | gcc -xc -shared -fPIC -o tmp-test.so -
+    int plus(int x, int y) { return x + y; }
+    int *alloc1(int x, int y) {
+      static int arr[2];
+      arr[0] = x;
+      arr[1] = y;
+      return arr;
+    }
+    int *alloc2(int x, int y) {
+      static int arr[2];
+      arr[0] = x;
+      arr[1] = y;
+      return arr + 1;
+    }
+    int **alloc_ptr_ptr(int x) {
+      static int **p;
+      static int *q;
+      static int r;
+      r = x;
+      q = &r;
+      p = &q;
+      return p;
+    }
+    struct c3 { char a, b, c; };
+    struct s12 { int a, b, c; };
+    struct s16 { int *p; int x; char c; };
+    struct big { int a[6]; };
+    int sum_c3(struct c3 s) { return s.a + s.b + s.c; }
+    int sum_s16(struct s16 s) { return *s.p + s.x + s.c; }
+    int sum_big(struct big b) { return b.a[0] + b.a[5]; }
+    int many(int a, int b, int c, int d, int e, struct s12 s, int f, struct big g) { return a+b+c+d+e+f + s.c + g.a[5]; }
+    struct s12 make_s12(int x) { struct s12 s = {x, x*2, x*3}; return s; }
+    struct big make_big(int x) { struct big b = {{x, 0, 0, 0, 0, x*2}}; return b; }
+    short neg16(short x) { return -x; }
+    unsigned char low8(int x) { return x; }
+    int minus1(void) { return -1; }
+    long long mul_ll(long long a, long long b) { return a * b; }
+    _Bool is_neg(long x) { return x < 0; }
+    #include <stdarg.h>
+    struct dd { double a, b; };
+    struct fi { float x; int y; };
+    double half(double x) { return x / 2; }
+    float add_f(float a, float b) { return a + b; }
+    double mix(int a, float b, long c, double d) { return a + b + c + d; }
+    double many_d(double a, double b, double c, double d, double e, double f, double g, double h, double i, float j) {
+      return a + b + c + d + e + f + g + h + i * 10 + j * 100;
+    }
+    struct dd make_dd(double a, double b) { struct dd s = {a, b}; return s; }
+    double sum_dd(struct dd s) { return s.a - s.b; }
+    double sum_fi(struct fi s) { return s.x * s.y; }
+    double sum_va(int n, ...) {
+      va_list ap;
+      va_start(ap, n);
+      double sum = 0;
+      for (int i = 0; i < n; i++) sum += va_arg(ap, double);
+      va_end(ap);
+      return sum;
+    }
+EOF
+
+# Calls in the other direction: gcc's code calls functions compiled by rugcc.
+cat <<EOF | gcc -xc -c -o tmp-abi.o -
+    struct s12 { int a, b, c; };
+    struct big { int a[6]; };
+    int rg_sum(struct s12 s, struct big b);
+    struct s12 rg_make_s12(int x);
+    struct big rg_make_big(int x);
+    int check_abi(void) {
+      struct s12 s = {1, 2, 3};
+      struct big b = {{1, 2, 3, 4, 5, 6}};
+      struct s12 t = rg_make_s12(5);
+      struct big m = rg_make_big(3);
+      return rg_sum(s, b) == 10 && t.a == 5 && t.c == 15 && m.a[0] == 3 && m.a[5] == 8;
+    }
+EOF
+cat <<EOF | gcc -xc -c -o tmp-fabi.o -
+    struct dd { double a, b; };
+    double rg_fsum(float a, double b, int c, struct dd d);
+    struct dd rg_make_dd(double x);
+    int check_float_abi(void) {
+      struct dd d = {0.25, 0.5};
+      struct dd m = rg_make_dd(1.5);
+      return rg_fsum(1.5f, 2.25, 3, d) == 7.5 && m.a == 1.5 && m.b == -1.5;
+    }
+EOF
+
 runtest 'int main() { return 128; }' 128
 runtest 'int main() { return 2+3; }' 5
 runtest 'int main() { return 10-3; }' 7
@@ -65,6 +137,10 @@ runtest 'int one() { return 1; } int two() { return 2; } int main() { return one
 
 runtest 'int mul(int a, int b) { return a * b; } int main() { return mul(2, 3); }' 6
 runtest 'int add(int a,int b,int c,int d,int e,int f) { return a+b+c+d+e+f; } int main() { return add(1,2,3,4,5,6); }' 21
+runtest 'int add(int a,int b,int c,int d,int e,int f) { return a+b+c+d+e+f; } int main() { return add(1,2,add(1,1,1,1,1,1),4,5+(6+(7+8)),6); }' 45
+runtest 'int main() { return 1+(2+(3+(4+(5+(6+(7+(8+(9+10)))))))); }' 55
+runtest "int main() { int x=1; $(printf 'x=x+1; %.0s' $(seq 400))return x; }" 145
+runtest 'int main() { int a=1; return a*(a+(a*(a+(a*(a+(a*(a+2))))))); }' 6
 runtest 'int sum(int a) { if (a) return a+sum(a-1); return 0; } int main() { return sum(10); }' 55
 
 runtest 'int main() { return 0||0; }' 0
@@ -76,14 +152,117 @@ runtest 'int main() { return 1&&0; }' 0
 runtest 'int main() { return 0&&1; }' 0
 runtest 'int main() { return 1&&1; }' 1
 
+runtest 'int main() { return 0x10 + 010 + 0b11 + 0XaB - 0xAb; }' 27
+runtest 'int main() { return 4294967296 - 4294967295; }' 1
+runtest 'int main() { return 10u + 20L + 30ull; }' 60
+runtest 'int main() { return sizeof(1) + sizeof(1L) * 10; }' 84
+runtest 'int main() { return sizeof(0x80000000) + sizeof(2147483648) * 10; }' 84
+runtest 'int main() { return sizeof(sizeof 1); }' 8
+
 runtest 'int main() { return 0<0; }' 0
 runtest 'int main() { return 1<0; }' 0
 runtest 'int main() { return 0<1; }' 1
 runtest 'int main() { return 0>0; }' 0
 runtest 'int main() { return 0>1; }' 0
 runtest 'int main() { return 1>0; }' 1
+runtest 'int main() { return 3<=3; }' 1
+runtest 'int main() { return 4<=3; }' 0
+runtest 'int main() { return 3>=4; }' 0
+runtest 'int main() { return 4>=4; }' 1
+runtest 'int main() { return 2==2; }' 1
+runtest 'int main() { return 2==3; }' 0
+runtest 'int main() { return 2!=3; }' 1
+runtest 'int main() { return 1<2==1; }' 1
+
+runtest 'int main() { return 17%5; }' 2
+runtest 'int main() { return 12&10; }' 8
+runtest 'int main() { return 12|10; }' 14
+runtest 'int main() { return 12^10; }' 6
+runtest 'int main() { return 1|2^3&4; }' 3
+runtest 'int main() { return 1<<4; }' 16
+runtest 'int main() { return 200>>3; }' 25
+runtest 'int main() { return 1<<2+1; }' 8
+runtest 'int main() { return ~0 + 43; }' 42
+runtest 'int main() { return -3 + 10; }' 7
+runtest 'int main() { return - -5; }' 5
+runtest 'int main() { return +5; }' 5
+runtest 'int main() { return !0; }' 1
+runtest 'int main() { return !7; }' 0
+runtest 'int main() { return -8>>1 == -4; }' 1
+
+runtest 'int main() { int a=5; a+=3; return a; }' 8
+runtest 'int main() { int a=5; a-=3; return a; }' 2
+runtest 'int main() { int a=5; a*=3; return a; }' 15
+runtest 'int main() { int a=17; a/=3; return a; }' 5
+runtest 'int main() { int a=17; a%=3; return a; }' 2
+runtest 'int main() { int a=12; a&=10; return a; }' 8
+runtest 'int main() { int a=12; a|=10; return a; }' 14
+runtest 'int main() { int a=12; a^=10; return a; }' 6
+runtest 'int main() { int a=3; a<<=2; return a; }' 12
+runtest 'int main() { int a=12; a>>=2; return a; }' 3
+runtest 'int main() { int a=5; return (a+=3) + a; }' 16
+runtest 'int main() { char c=250; return c+=10; }' 4
+runtest 'int main() { int a[3]; a[0]=1; a[1]=2; a[2]=3; int *p=a; p+=2; return *p; }' 3
+runtest 'int main() { int a[3]; a[0]=1; a[1]=2; a[2]=3; int *p=a+2; p-=1; return *p; }' 2
+
+runtest 'int main() { int a=5; ++a; return a; }' 6
+runtest 'int main() { int a=5; --a; return a; }' 4
+runtest 'int main() { int a=5; return ++a + a; }' 12
+runtest 'int main() { int a=5; return a++; }' 5
+runtest 'int main() { int a=5; a++; return a; }' 6
+runtest 'int main() { int a=5; return a-- * 2 + a; }' 14
+runtest 'int main() { int a[2]; a[0]=3; a[1]=7; int *p=a; p++; return *p; }' 7
+runtest 'int main() { int a[2]; a[0]=3; a[1]=7; int *p=a; return *p++ + *p; }' 10
+runtest 'int main() { int a[2]; a[0]=3; a[1]=7; a[1]++; return a[1]; }' 8
+runtest 'int main() { int sum=0; for (int i=0; i<5; i++) sum+=i; return sum; }' 10
+
+runtest 'int main() { int a; int b; a = b = 3; return a + b; }' 6
+runtest 'int main() { int a=1; int b=2; a += b += 3; return a * 10 + b; }' 65
+runtest 'int main() { int a; return (a = 7) + 1; }' 8
+runtest 'int main() { return 1 ? 5 : 7; }' 5
+runtest 'int main() { return 0 ? 5 : 7; }' 7
+runtest 'int main() { int a=2; return a==1 ? 10 : a==2 ? 20 : 30; }' 20
+runtest 'int main() { int a=0; int b=0; 1 ? (a=1) : (b=1); return a*10 + b; }' 10
+runtest 'int main() { int a[2]; a[0]=3; a[1]=4; int *p = 0 ? a : a+1; return *p; }' 4
+runtest 'int main() { return sizeof(1 ? 1 : 2L); }' 8
+runtest 'int main() { return (1, 2, 3); }' 3
+runtest 'int main() { int a; int b; return (a=2, b=3, a*b); }' 6
+runtest 'int main() { int i; int j; int s=0; for (i=0, j=10; i<j; i++, j--) s++; return s; }' 5
+
+runtest 'int main() { int i=0; while (i<10) i++; return i; }' 10
+runtest 'int main() { int i=0; while (0) i++; return i; }' 0
+runtest 'int main() { int i=0; do i++; while (i<5); return i; }' 5
+runtest 'int main() { int i=7; do i++; while (0); return i; }' 8
+runtest 'int main() { int i=0; for (;;) { if (i==6) break; i++; } return i; }' 6
+runtest 'int main() { int i=0; int s=0; for (; i<10;) { i++; if (i%2) continue; s+=i; } return s; }' 30
+runtest 'int main() { int i=0; int s=0; while (i<10) { i++; if (i>5) continue; s+=i; } return s; }' 15
+runtest 'int main() { int i=0; do { i++; if (i<3) continue; break; } while (1); return i; }' 3
+runtest 'int main() { int s=0; for (int i=0; i<3; i++) for (int j=0; j<10; j++) { if (j==2) break; s++; } return s; }' 6
+runtest 'int main() { int i=0; loop: i++; if (i<4) goto loop; return i; }' 4
+runtest 'int main() { goto skip; return 1; skip: return 2; }' 2
+runtest 'int main() { int i=0; for (;;) { for (;;) goto out; } out: ; return 9; }' 9
+runtest 'int main() { ;; return 3; }' 3
+
+runtest 'int main() { int x=3; switch (x) { case 1: return 10; case 3: return 30; default: return 99; } }' 30
+runtest 'int main() { int x=4; switch (x) { case 1: return 10; case 3: return 30; default: return 99; } }' 99
+runtest 'int main() { int x=4; switch (x) { case 1: return 10; case 3: return 30; } return 7; }' 7
+runtest 'int main() { int x=1; int s=0; switch (x) { case 1: s+=1; case 2: s+=2; break; case 3: s+=4; } return s; }' 3
+runtest 'int main() { int x=2; switch (x) { default: return 5; case 1: return 1; } }' 5
+runtest 'int main() { int x=2; switch (x) { case 1: x=10; { case 2: x=20; } } return x; }' 20
+runtest 'int main() { int s=0; for (int i=0; i<8; i++) switch (i) { case 0: s+=1; break; case 1: s+=2; case 2: s+=4; break; case 3: case 4: s+=8; break; case 6: continue; default: s+=100; } return s; }' 227
+runtest 'int main() { int s=0; for (int i=0; i<20; i++) switch (i) { case 10: case 11: case 12: case 13: case 15: s++; } return s; }' 5
+runtest 'int main() { int s=0; for (int i=0; i<5; i++) { switch (i) { case 2: break; default: s++; } } return s; }' 4
+runtest 'int main() { int x=1; switch (x) { case 1: switch (x+1) { case 1: return 1; case 2: break; } return 12; } return 0; }' 12
+exetest 'int main() { int s=0; for (int i=0; i<10; i++) switch (i) { case 1: s+=1; break; case 2: s+=2; break; case 3: s+=3; break; case 4: s+=4; break; default: s+=10; } return s; }' 70
 
 runtest 'int main() { int sum=0; for (int i=10; i<15; i=i+1) sum = sum + i; return sum;}' 60
+runtest 'int main() { int x=1; { int x=2; } return x; }' 1
+runtest 'int main() { int x=1; { int x=2; { int x=3; } x=x+10; } return x; }' 1
+runtest 'int main() { int x=1; { int x=2; return x; } }' 2
+runtest 'int main() { int x=5; { x=7; int x=2; } return x; }' 7
+runtest 'int main() { int s=0; { int a=3; s+=a; } { int b=4; s+=b; } return s; }' 7
+runtest 'int main() { int i=100; for (int i=0; i<3; i++) ; return i; }' 100
+runtest 'int main() { int s=0; for (int i=0; i<3; i++) { int i=10; s+=i; } return s; }' 30
 runtest 'int main() { int i=1; int j=1; int k; int m; for (k=0; k<10; k=k+1) { m=i+j; i=j; j=m; } return i;}' 89
 
 runtest 'int main() { int *p = alloc1(3,5); return *p + *(1 + p); }' 8
@@ -105,5 +284,148 @@ runtest 'int main() { char *p = "abc"; return p[0]; }' 97
 runtest 'int main() { char *p = "abc"; return p[1]; }' 98
 runtest 'int main() { char *p = "abc"; return p[1+1]; }' 99
 runtest 'int main() { char *p = "abc"; return p[3]; }' 0
+runtest 'int main() { return sizeof("abc"); }' 4
+runtest 'int main() { return "a\"b"[1]; }' 34
+runtest 'int main() { return "\x41\102\t"[1] + "\x41\102\t"[2]; }' 75
+runtest 'int main() { return "ab" "cd"[2]; }' 99
+runtest 'int main() { return sizeof("ab" "cd"); }' 5
+runtest 'int main() { return "\0x"[1]; }' 120
+runtest "int main() { return 'a'; }" 97
+runtest "int main() { return '\\n' + '\\''; }" 49
+runtest "int main() { return '\\377' + 2; }" 1
+runtest "int main() { return sizeof('a'); }" 4
+
+runtest 'int g; int main() { g = 3; return g; }' 3
+runtest 'int g = 5; int f() { return g++; } int main() { f(); return f() + g; }' 13
+runtest 'int a[] = {1, 2, 3, 4,}; int main() { return a[0] + a[1] * a[2] + sizeof(a); }' 23
+runtest 'int a[2][3] = {{1, 2, 3}, {4, 5, 6}}; int main() { return a[1][2] + a[0][1]; }' 8
+runtest 'int main() { int a[2][3]; a[1][2] = 5; int *p = a[1]; return p[2] + sizeof(a[1]); }' 17
+runtest 'char s[] = "hello"; int main() { return s[1] + sizeof(s); }' 107
+runtest 'char *ss[] = {"ab", "cd"}; int main() { return ss[1][1]; }' 100
+runtest 'int a[4] = {1, 2, 3, 4}; int *p = a + 2; int *q = &a[1]; int main() { return *p + p[-1] + *q; }' 7
+runtest 'char c = 300; int x = 7; int *p = &x; int main() { return c + *p; }' 51
+runtest 'int count() { static int n; n = n + 1; return n; } int main() { count(); count(); return count(); }' 3
+runtest 'int f() { static int n = 10; return n++; } int g() { static int n = 20; return n++; } int main() { f(); g(); return f() + g(); }' 32
+runtest 'extern int g; int main() { return g; } int g = 9;' 9
+runtest 'int main() { extern int g; return g; } int g; int g = 4;' 4
+runtest 'static int h = 6; int main() { return h; }' 6
+exetest 'int a[3] = {5, 6, 7}; char *s = "xyz"; int *p = &a[1]; int main() { static int k = 1; return *p + s[1] + k; }' 128
+
+runtest 'struct P { int x; int y; }; int main() { struct P p; p.x = 3; p.y = 4; return p.x * p.y; }' 12
+runtest 'int main() { struct { char c; int i; } s; struct { char c; int *p; char d; } t; return sizeof(s) * 10 + sizeof(t); }' 104
+runtest 'int main() { union { char c; int i; int *p; } u; u.i = 258; return u.c + sizeof(u); }' 10
+runtest 'struct S { char a; char b; char c; }; int main() { return sizeof(struct S) + sizeof(struct S *); }' 11
+runtest 'struct N { int v; struct N *next; }; int main() { struct N a; struct N b; a.v = 1; a.next = &b; b.v = 41; return a.v + a.next->v; }' 42
+runtest 'struct I { int a; int b; }; struct O { char c; struct I in; int arr[3]; }; int main() { struct O o; o.in.b = 5; o.arr[2] = 6; struct O *p = &o; return p->in.b + p->arr[2] + sizeof(o); }' 35
+runtest 'struct P { int x; int y; }; int main() { struct P a; struct P b; a.x = 1; a.y = 2; b = a; a.x = 9; return b.x * 10 + b.y; }' 12
+runtest 'struct P { int x; char s[13]; }; int main() { struct P a; a.s[12] = 7; struct P b = a; return b.s[12]; }' 7
+runtest 'struct P { int x; int y; }; struct P ps[3]; int main() { ps[2].y = 5; struct P *p = ps; return (p + 2)->y + sizeof(ps); }' 29
+runtest 'struct P { int x; int y; char *s; } g = {1, 2, "hi"}; int main() { return g.x + g.y + g.s[1]; }' 108
+runtest 'struct P { int x; int y; } gs[] = {{1, 2}, {3, 4}}; int main() { return gs[1].y + sizeof(gs); }' 20
+runtest 'union U { int i; char c[4]; } gu = {0x01020304}; int main() { return gu.c[0] + gu.c[3]; }' 5
+runtest 'struct L; struct L *p; struct L { int v; }; int main() { struct L l; l.v = 3; p = &l; return p->v; }' 3
+runtest 'struct S { int a; }; int main() { struct S { char c; } s; return sizeof(s); }' 1
+runtest 'struct P { int x; int y; }; struct P mk(int a) { struct P p; p.x = a; p.y = a * 2; return p; } int main() { return mk(3).y + mk(4).x; }' 10
+runtest 'struct B { int a[10]; }; struct B mk(int a) { struct B b; b.a[9] = a; return b; } int sum(struct B b, struct B c) { return b.a[9] + c.a[9]; } int main() { return sum(mk(3), mk(4)); }' 7
+runtest 'int f(int a, int b, int c, int d, int e, int f, int g, int h) { return a + b * h + g; } int main() { return f(1, 2, 3, 4, 5, 6, 7, 8); }' 24
+runtest 'struct c3 { char a; char b; char c; }; int sum_c3(struct c3 s); int main() { struct c3 c; c.a = 1; c.b = 2; c.c = 3; return sum_c3(c); }' 6
+runtest 'struct s16 { int *p; int x; char c; }; int sum_s16(struct s16 s); int main() { int v = 100; struct s16 q; q.p = &v; q.x = 20; q.c = 3; return sum_s16(q); }' 123
+runtest 'struct big { int a[6]; }; int sum_big(struct big b); int main() { struct big b; b.a[0] = 4; b.a[5] = 6; return sum_big(b); }' 10
+runtest 'struct s12 { int a; int b; int c; }; struct big { int a[6]; }; int many(int a, int b, int c, int d, int e, struct s12 s, int f, struct big g); int main() { struct s12 s; s.c = 3; struct big g; g.a[5] = 30; return many(1, 2, 3, 4, 5, s, 6, g); }' 54
+runtest 'struct s12 { int a; int b; int c; }; struct s12 make_s12(int x); int main() { struct s12 t = make_s12(4); return t.a + t.b + t.c; }' 24
+runtest 'struct big { int a[6]; }; struct big make_big(int x); int main() { return make_big(10).a[5] + make_big(1).a[0]; }' 21
+exetest 'struct s12 { int a; int b; int c; }; struct big { int a[6]; }; int check_abi();
+int rg_sum(struct s12 s, struct big b) { return s.a + s.c + b.a[5]; }
+struct s12 rg_make_s12(int x) { struct s12 s; s.a = x; s.b = x*2; s.c = x*3; return s; }
+struct big rg_make_big(int x) { struct big b; for (int i = 0; i < 6; i++) b.a[i] = x + i; return b; }
+int main() { return check_abi(); }' 1 ./tmp-abi.o
+
+runtest 'int main() { return sizeof(short) + sizeof(long) * 10 + sizeof(long long) + sizeof(_Bool) * 100; }' 190
+runtest 'int main() { unsigned u; long unsigned int l; short int s; signed char c; return sizeof(u) + sizeof(l) * 10 + sizeof(s) + sizeof(c); }' 87
+runtest 'int main() { short s = 65537; return s; }' 1
+runtest 'int main() { char c = 255; return c == -1; }' 1
+runtest 'int main() { unsigned char c = 255; return c; }' 255
+runtest 'int main() { short s = -2; unsigned short u = s; return (s < 0) + (u > 0) * 2 + (u == 65534) * 4; }' 7
+runtest 'int main() { int x = -1; return x < 0; }' 1
+runtest 'int main() { unsigned x = 0; x = x - 1; return x > 0; }' 1
+runtest 'int main() { unsigned x = -1; return x / 2 == 2147483647; }' 1
+runtest 'int main() { int x = -7; return (x / 2 == -3) + (x % 2 == -1) * 2; }' 3
+runtest 'int main() { unsigned u = 1; return -1 < u; }' 0
+runtest 'int main() { long l = -1; unsigned u = 1; return l < u; }' 1
+runtest 'int main() { int x = 2147483647; long y = x + 1; return y < 0; }' 1
+runtest 'int main() { unsigned x = 4294967295; long y = x + 1; return y == 0; }' 1
+runtest 'int main() { long x = 1; x = x << 40; return x >> 40; }' 1
+runtest 'int main() { long long x = 3000000000; return x / 1000000000; }' 3
+runtest 'int main() { _Bool b = 2; _Bool c = 0; int *p = &b; _Bool d = p; return b + c + d; }' 2
+runtest 'int main() { _Bool b = 0; b++; b++; return b; }' 1
+runtest 'int main() { return (char)300 + ((unsigned char)-1 == 255) * 100; }' 144
+runtest 'int main() { long l = (short)-1; return l == -1 && (unsigned short)-1 == 65535; }' 1
+runtest 'int main() { char c = 100; c += 100; return c == -56; }' 1
+runtest 'int main() { int x = -1; x /= 2u; return x == 2147483647; }' 1
+runtest 'int main() { unsigned char c = 255; switch (c) { case -1: return 2; case 255: return 1; } return 0; }' 1
+runtest 'unsigned short g = 65535; _Bool gb = 5; char gc = -3; int main() { return (g == 65535) + gb * 2 + (gc == -3) * 4; }' 7
+runtest 'short f(short a, unsigned char b) { return a * b; } int main() { return f(-2, 200) == -400; }' 1
+runtest 'short neg16(short x); int main() { return neg16(-5); }' 5
+runtest 'unsigned char low8(int x); int main() { return low8(300) == 44; }' 1
+runtest 'int minus1(); int main() { return minus1() < 0; }' 1
+runtest 'long long mul_ll(long long a, long long b); int main() { return mul_ll(3000000, 3000000) / 1000000000000; }' 9
+runtest '_Bool is_neg(long x); int main() { return is_neg(-5) + is_neg(5) * 2; }' 1
+
+runtest 'int main() { int a = -6; int b = 7; return a * b == -42; }' 1
+runtest 'int main() { int a = -6; return a * -7; }' 42
+runtest 'int main() { long a = -3000000000; return a * -1 / 1000000000; }' 3
+runtest 'int main() { int a = -20; return a / -3 + 10; }' 16
+runtest 'int main() { int a = -20; return (a % 3 == -2) + (20 % -3) * 2; }' 5
+runtest 'int main() { long a = -9; return a / 2 == -4 && a % 2 == -1; }' 1
+runtest 'int main() { short s = -300; return s / 7 == -42 && s % 7 == -6; }' 1
+runtest 'int main() { long a = -7; unsigned long b = 2; return a / b == 9223372036854775804; }' 1
+runtest 'int main() { int a = -16; return (a >> 2 == -4) + (a >> 31 == -1) * 2; }' 3
+runtest 'int main() { unsigned a = -16; return a >> 28; }' 15
+runtest 'int main() { unsigned long a = -1; long b = -1; return (a >> 60) + (b >> 63 == -1) * 100; }' 115
+runtest 'int main() { int a = -5; int b = 3; return (a < b) + (a <= b) * 2 + (b > a) * 4 + (b >= a) * 8; }' 15
+runtest 'int main() { int a = -1; unsigned b = 1; return (a < b) + (a > b) * 2; }' 2
+runtest 'int main() { char a = -3; char b = 2; return a < b && a * b == -6; }' 1
+runtest 'int main() { int x = -8; x >>= 1; x *= -3; x /= -4; return x == -3; }' 1
+runtest 'int main() { unsigned u = -8; u /= 2; u >>= 1; return u == 1073741822; }' 1
+runtest 'int g = -7 / 2; unsigned h = -16u >> 28; int main() { return (g == -3) + h * 2; }' 31
+
+runtest 'int main() { double d = 1.5; return d * 4; }' 6
+runtest 'int main() { float f = 0.1f; double d = 0.1; return (f == d) + (f == (float)d) * 2; }' 2
+runtest 'int main() { double a = 7; double b = 2; return (a / b == 3.5) + (a - b > 4.5) * 2 + (-a < b) * 4 + (a <= 7.0) * 8 + (a != b) * 16; }' 31
+runtest 'int main() { double d = -2.75; int i = d; unsigned char c = 300.5 - 100; return (i == -2) + (c == 200) * 2 + ((int)2.9e1 == 29) * 4; }' 7
+runtest 'int main() { unsigned long u = 18446744073709551615ul; double d = u; unsigned long v = 1e19; return (d == 18446744073709551616.0) + (v == 10000000000000000000ul) * 2; }' 3
+runtest 'int main() { double d = 0.5; int n = 0; if (d) n++; while (d) d = 0; return n + !d * 2 + (0.0 || 0.25) * 4 + (d ? 8 : 16); }' 23
+runtest 'int main() { double x = 1; float y = 2; int i = 3; x += 2; x *= y; x -= i; x /= 4; i += 1.7; y++; ++y; return (x == 0.75) + (y == 4) * 2 + (i == 4) * 4; }' 7
+runtest 'double g = 1.0 / 4 + 1; float h = 1.0f / 3; double a[2] = {1, -2.5}; int main() { return (g == 1.25) + (h == (float)(1.0 / 3)) * 2 + (a[1] == -2.5) * 4; }' 7
+runtest 'double sq(double x) { return x * x; } float fma3(float a, float b, float c) { return a * b + c; } int main() { return sq(3) + fma3(1.5, 2, 0.5); }' 12
+runtest 'double f(double a, double b, double c, double d, double e, double f, double g, double h, double i, float j, int k) { return a + b + c + d + e + f + g + h + i * 10 + j * 100 + k; } int main() { return f(1, 1, 1, 1, 1, 1, 1, 1, 2, 1, 3); }' 131
+runtest 'double half(double x); float add_f(float a, float b); double mix(int a, float b, long c, double d); int main() { return half(9) * 2 + add_f(1.25, 2.75) + mix(1, 2.5, 3, 4.5); }' 24
+runtest 'double many_d(double a, double b, double c, double d, double e, double f, double g, double h, double i, float j); int main() { return many_d(1, 1, 1, 1, 1, 1, 1, 1, 2, 1); }' 128
+runtest 'struct dd { double a; double b; }; struct dd make_dd(double a, double b); double sum_dd(struct dd s); int main() { struct dd s = make_dd(5.5, 2); return sum_dd(s) * 2 + s.b; }' 9
+runtest 'struct fi { float x; int y; }; double sum_fi(struct fi s); int main() { struct fi s; s.x = 2.5; s.y = 4; return sum_fi(s); }' 10
+runtest 'double sum_va(int n, ...); int main() { float f = 0.5; return sum_va(4, 1.5, 2.0, f, 4); }' 4
+runtest 'int sprintf(char *buf, char *fmt, ...); int main() { char buf[16]; sprintf(buf, "%.1f", 2.5 * 3); return buf[0] + buf[2]; }' 108
+exetest 'struct dd { double a; double b; }; int check_float_abi();
+double rg_fsum(float a, double b, int c, struct dd d) { return a + b + c + d.a + d.b; }
+struct dd rg_make_dd(double x) { struct dd d; d.a = x; d.b = -x; return d; }
+int main() { return check_float_abi(); }' 1 ./tmp-fabi.o
+
+runtest "$(printf 'int main() {\n  // return 1;\n  return 6 /* / 3 */ / 2;\n}')" 3
+runtest "$(printf '#define N 3\nint main() { return N; }')" 3
+runtest "$(printf '#define ADD(a, b) ((a)+(b))\nint main() { return ADD(1, ADD(2, 3)) * 2; }')" 12
+runtest "$(printf '#define CAT(a, b) a##b\nint main() { int xy = 7; return CAT(x, y); }')" 7
+runtest "$(printf '#define S(x) #x\nint main() { return S(hello)[1]; }')" 101
+runtest "$(printf '#if 1 + 2 * 3 == 7 && !defined(N)\nint main() { return 1; }\n#else\nint main() { return 2; }\n#endif')" 1
+runtest "$(printf '#define N\n#ifdef N\nint main() { return 3; }\n#endif\n#ifndef N\n#error N is defined\n#endif')" 3
+runtest "$(printf '#include <stdlib.h>\nint main() { return EXIT_FAILURE + (NULL); }')" 1
+runtest "$(printf '#include <stdio.h>\nint main() { char buf[8]; return sprintf(buf, "%%d", 123); }')" 3
+printf '#define FROM_HEADER 9\n' > ./tmp-inc.h
+runtest "$(printf '#include "tmp-inc.h"\nint main() { return FROM_HEADER; }')" 9
+runtest "$(printf 'int main() { return __LINE__ +\n __LINE__; }')" 3
+
+exetest 'int main() { return 42; }' 42
+exetest 'int sum(int a) { if (a) return a+sum(a-1); return 0; } int main() { return sum(10); }' 55
+
+rm -f ./tmp*
 
 echo "OK"